@@ -0,0 +1,139 @@
+use std::f32::consts;
+
+use crate::math::normalize_angle;
+
+// Recorded camera positions for flythroughs. Positions follow a Catmull-Rom spline through
+// the keyframes and the angle turns the short way round between them. Times are seconds on
+// whatever clock the caller records and plays back with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub x: f32,
+    pub y: f32,
+    pub angle: f32,
+    pub time: f32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+    playback_start: Option<f32>,
+}
+
+impl CameraPath {
+    // Keyframes recorded out of order are dropped, the path only goes forwards in time
+    pub fn record_keyframe(&mut self, x: f32, y: f32, angle: f32, time: f32) {
+        if self.keyframes.last().is_some_and(|last| time <= last.time) {
+            return;
+        }
+        self.keyframes.push(Keyframe { x, y, angle, time });
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+        self.playback_start = None;
+    }
+
+    pub fn play(&mut self, now: f32) {
+        if self.keyframes.len() > 1 {
+            self.playback_start = Some(now);
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.playback_start = None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback_start.is_some()
+    }
+
+    // Where the camera should be now, or None once playback has run off the end
+    pub fn playback(&mut self, now: f32) -> Option<Keyframe> {
+        let start = self.playback_start?;
+        let first = self.keyframes.first()?.time;
+        let frame = self.sample(first + now - start);
+        if frame.is_none() {
+            self.playback_start = None;
+        }
+        frame
+    }
+
+    // The interpolated camera at a recorded time, None outside the recording
+    pub fn sample(&self, time: f32) -> Option<Keyframe> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        if time < self.keyframes[0].time || time > self.keyframes[last].time {
+            return None;
+        }
+        if last == 0 {
+            return Some(self.keyframes[0]);
+        }
+        let segment = self.keyframes[1..]
+            .iter()
+            .position(|frame| time <= frame.time)
+            .unwrap_or(last - 1);
+        // The ends repeat their own keyframe for the missing neighbour
+        let frame = |index: usize| self.keyframes[index.min(last)];
+        let (p0, p1, p2, p3) = (
+            frame(segment.saturating_sub(1)),
+            frame(segment),
+            frame(segment + 1),
+            frame(segment + 2),
+        );
+        let t = (time - p1.time) / (p2.time - p1.time);
+        let spline = |a: f32, b: f32, c: f32, d: f32| {
+            0.5 * (2. * b
+                + (c - a) * t
+                + (2. * a - 5. * b + 4. * c - d) * t * t
+                + (3. * b - a - 3. * c + d) * t * t * t)
+        };
+        let turn = (p2.angle - p1.angle + consts::PI).rem_euclid(2. * consts::PI) - consts::PI;
+        Some(Keyframe {
+            x: spline(p0.x, p1.x, p2.x, p3.x),
+            y: spline(p0.y, p1.y, p2.y, p3.y),
+            angle: normalize_angle(p1.angle + turn * t),
+            time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> CameraPath {
+        let mut path = CameraPath::default();
+        path.record_keyframe(0., 0., 0.1, 1.);
+        path.record_keyframe(100., 50., 6.2, 2.);
+        path.record_keyframe(150., -20., 1., 4.);
+        path.record_keyframe(300., 0., 2., 5.);
+        path
+    }
+
+    #[test]
+    fn interpolation_passes_through_every_keyframe() {
+        let path = path();
+        for key in path.keyframes.iter() {
+            let sampled = path.sample(key.time).unwrap();
+            assert!((sampled.x - key.x).abs() < 1e-3 && (sampled.y - key.y).abs() < 1e-3);
+            assert!((sampled.angle - key.angle).abs() < 1e-3);
+        }
+        assert_eq!(path.sample(0.9), None);
+        assert_eq!(path.sample(5.1), None);
+        // From 0.1 to 6.2 radians the short way is back through 0
+        let halfway = path.sample(1.5).unwrap().angle;
+        assert!(!(0.1..=6.2).contains(&halfway));
+    }
+
+    #[test]
+    fn playback_runs_from_the_first_keyframe_then_stops() {
+        let mut path = path();
+        path.record_keyframe(400., 0., 0., 4.5);
+        assert_eq!(path.keyframes.len(), 4);
+
+        path.play(10.);
+        assert_eq!(path.playback(11.).map(|frame| frame.x), Some(100.));
+        assert!(path.is_playing());
+        assert_eq!(path.playback(14.5), None);
+        assert!(!path.is_playing());
+    }
+}
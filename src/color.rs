@@ -0,0 +1,80 @@
+use sdl2::pixels::Color;
+
+// Hue in degrees, any value, wrapped onto the wheel; saturation and value clamped to 0-1.
+// Saturation 0 gives a grey of the given value whatever the hue.
+pub fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (u8, u8, u8) {
+    let s = s.clamp(0., 1.);
+    let v = v.clamp(0., 1.);
+    let sextant = h.rem_euclid(360.) / 60.;
+    let chroma = v * s;
+    let x = chroma * (1. - (sextant % 2. - 1.).abs());
+    let (r, g, b) = match sextant as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    let m = v - chroma;
+    let channel = |c: f32| ((c + m) * 255.).round() as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+// Stepping the hue by the golden ratio never repeats and keeps neighbouring indexes far
+// apart on the wheel, so sector 5 and sector 6 never come out as similar shades
+pub fn sector_color(index: usize) -> Color {
+    const GOLDEN_RATIO_CONJUGATE: f32 = 0.618_034;
+    let (r, g, b) = hsv_to_rgb(
+        (index as f32 * GOLDEN_RATIO_CONJUGATE).fract() * 360.,
+        0.65,
+        0.95,
+    );
+    Color::RGB(r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(a: Color, b: Color) -> f32 {
+        let channel = |a: u8, b: u8| (f32::from(a) - f32::from(b)).powi(2);
+        (channel(a.r, b.r) + channel(a.g, b.g) + channel(a.b, b.b)).sqrt()
+    }
+
+    #[test]
+    fn first_sector_colors_are_pairwise_distinct() {
+        let colors: Vec<Color> = (0..12).map(sector_color).collect();
+        for (i, &a) in colors.iter().enumerate() {
+            for &b in colors[i + 1..].iter() {
+                assert!(distance(a, b) > 30., "{:?} and {:?} are too close", a, b);
+            }
+        }
+        // Neighbouring indexes land far apart on the wheel
+        for pair in colors.windows(2) {
+            assert!(distance(pair[0], pair[1]) > 150.);
+        }
+    }
+
+    #[test]
+    fn hue_sextants_and_greys() {
+        for (hue, rgb) in [
+            (0., (255, 0, 0)),
+            (60., (255, 255, 0)),
+            (120., (0, 255, 0)),
+            (180., (0, 255, 255)),
+            (240., (0, 0, 255)),
+            (300., (255, 0, 255)),
+            (360., (255, 0, 0)),
+            (-120., (0, 0, 255)),
+        ] {
+            assert_eq!(hsv_to_rgb(hue, 1., 1.), rgb, "hue {}", hue);
+        }
+        assert_eq!(hsv_to_rgb(30., 1., 1.), (255, 128, 0));
+        for hue in [0., 90., 200., 330.] {
+            assert_eq!(hsv_to_rgb(hue, 0., 0.5), (128, 128, 128));
+        }
+        assert_eq!(hsv_to_rgb(45., 0., 2.), (255, 255, 255));
+        assert_eq!(hsv_to_rgb(45., 1., 0.), (0, 0, 0));
+    }
+}
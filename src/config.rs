@@ -0,0 +1,385 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::ErrorKind,
+};
+
+// Settings read from rune.toml at startup and written back on quit, so automap toggles
+// persist. Only a flat list of `key = value` lines is understood, which is all the file
+// needs; anything missing or unreadable keeps its default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub width: u32, // Logical screen size, scaled up by multiplier for the window
+    pub height: u32,
+    pub multiplier: u32,
+    pub render_width: u32, // Internal resolution of the 3D view, upscaled to the window
+    pub render_height: u32,
+    pub aspect_ratio: f32, // Shape the 3D view is shown at, width over height
+    pub fov: f32,          // Horizontal field of view of the 3D view in degrees
+    pub move_speed: f32,   // Map units per tic
+    pub turn_speed: f32,   // Radians per tic
+    pub confirm_quit: bool,
+    pub follow: bool, // Automap toggles, as last left
+    pub fov_fill: bool,
+    pub show_grid: bool,
+    pub grid_labels: bool,
+    pub height_legend: bool,
+    pub flip_y: bool,
+    pub sector_colors: bool,
+    pub theme: String,                      // Automap colour preset by name
+    pub bindings: BTreeMap<String, String>, // Action to SDL key name, see DEFAULT_BINDINGS
+}
+
+// The key for each action that can be rebound, by SDL key name. rune.toml changes one with
+// a line like `key.grid = G`. Menus and prompts keep their own fixed keys.
+pub const DEFAULT_BINDINGS: [(&str, &str); 56] = [
+    ("forward", "Up"),
+    ("backward", "Down"),
+    ("turn_left", "Left"),
+    ("turn_right", "Right"),
+    ("strafe", "Left GUI"), // Held with a turn key to sidestep instead
+    ("look_up", "PageUp"),
+    ("look_down", "PageDown"),
+    ("pause", "P"),
+    ("quit", "Q"),
+    ("next_level", "."),
+    ("previous_level", ","),
+    ("view_3d", "Tab"),
+    ("wireframe", "W"),
+    ("reset_view", "Home"),
+    ("view_player", "End"),
+    ("zoom_in", "="),
+    ("zoom_out", "-"),
+    ("follow", "F"),
+    ("fov_fill", "V"),
+    ("grid", "G"),
+    ("grid_labels", "N"),
+    ("height_legend", "H"),
+    ("flip_y", "Y"),
+    ("sector_colors", "T"),
+    ("partitions", "O"),
+    ("location_path", "L"),
+    ("anti_alias", "A"),
+    ("bsp_index_order", "Z"),
+    ("reveal_secrets", "E"),
+    ("reveal_explored", "R"),
+    ("theme", "F9"),
+    ("dirty_tracking", "D"),
+    ("key_locator", "I"),
+    ("mark", "M"),
+    ("clear_marks", "C"),
+    ("player_start", "S"),
+    ("nearest_unvisited", "X"),
+    ("bsp_subtree", "B"),
+    ("subtree_up", "U"),
+    ("subtree_left", "J"),
+    ("subtree_right", "K"),
+    ("bsp_shallower", "["),
+    ("bsp_deeper", "]"),
+    ("bsp_off", "\\"),
+    ("monsters", "1"),
+    ("items", "2"),
+    ("keys", "3"),
+    ("decorations", "4"),
+    ("player_starts", "5"),
+    ("textures", "F2"),
+    ("flats", "F3"),
+    ("lumps", "F5"),
+    ("jukebox", "F4"),
+    ("record_keyframe", "F6"),
+    ("play_path", "F7"),
+    ("clear_path", "F8"),
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            width: 320,
+            height: 240,
+            multiplier: 4,
+            render_width: 320,
+            render_height: 200,
+            aspect_ratio: 4. / 3.,
+            fov: 90.,
+            move_speed: 3.,
+            turn_speed: 0.05,
+            confirm_quit: true,
+            follow: false,
+            fov_fill: false,
+            show_grid: true,
+            grid_labels: false,
+            height_legend: false,
+            flip_y: true,
+            sector_colors: false,
+            theme: "classic".to_string(),
+            bindings: DEFAULT_BINDINGS
+                .iter()
+                .map(|(action, key)| (action.to_string(), key.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Config {
+    pub const PATH: &'static str = "rune.toml";
+
+    pub fn load(path: &str) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(_) => Config::default(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut config = Config::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim().trim_matches('"')),
+                None => continue,
+            };
+            match key {
+                "width" => set(&mut config.width, value),
+                "height" => set(&mut config.height, value),
+                "multiplier" => set(&mut config.multiplier, value),
+                "render_width" => set(&mut config.render_width, value),
+                "render_height" => set(&mut config.render_height, value),
+                "aspect_ratio" => set(&mut config.aspect_ratio, value),
+                "fov" => set(&mut config.fov, value),
+                "move_speed" => set(&mut config.move_speed, value),
+                "turn_speed" => set(&mut config.turn_speed, value),
+                "confirm_quit" => set(&mut config.confirm_quit, value),
+                "follow" => set(&mut config.follow, value),
+                "fov_fill" => set(&mut config.fov_fill, value),
+                "show_grid" => set(&mut config.show_grid, value),
+                "grid_labels" => set(&mut config.grid_labels, value),
+                "height_legend" => set(&mut config.height_legend, value),
+                "flip_y" => set(&mut config.flip_y, value),
+                "sector_colors" => set(&mut config.sector_colors, value),
+                "theme" => set(&mut config.theme, value),
+                _ => {
+                    // Only actions that exist can be rebound
+                    if let Some(binding) = key
+                        .strip_prefix("key.")
+                        .and_then(|action| config.bindings.get_mut(action))
+                    {
+                        *binding = value.to_string();
+                    }
+                }
+            }
+        }
+        // A zero size would leave nothing to draw on
+        let defaults = Config::default();
+        if config.width == 0 || config.height == 0 || config.multiplier == 0 {
+            config.width = defaults.width;
+            config.height = defaults.height;
+            config.multiplier = defaults.multiplier;
+        }
+        if config.render_width == 0 || config.render_height == 0 {
+            config.render_width = defaults.render_width;
+            config.render_height = defaults.render_height;
+        }
+        if !(config.aspect_ratio > 0. && config.aspect_ratio.is_finite()) {
+            config.aspect_ratio = defaults.aspect_ratio;
+        }
+        // Anything from 180° on can't be projected onto a flat screen
+        if !(config.fov > 0. && config.fov < 180.) {
+            config.fov = defaults.fov;
+        }
+        config
+    }
+
+    // Only touches the file if a setting changed, and keeps its comments and other lines
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let existing = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+        let merged = self.merge_into(&existing);
+        if merged != existing {
+            std::fs::write(path, merged)?;
+        }
+        Ok(())
+    }
+
+    // The file's own lines, with those for settings whose value changed rewritten, then any
+    // other setting that isn't at its default
+    pub fn merge_into(&self, text: &str) -> String {
+        let settings = self.settings();
+        let mut written = HashSet::new();
+        let mut merged = String::new();
+        for line in text.lines() {
+            let setting = line.split('#').next().unwrap_or("").split_once('=');
+            let owned = setting.and_then(|(key, value)| {
+                let (key, value) = (key.trim(), value.trim().trim_matches('"'));
+                let (_, ours) = settings.iter().find(|(name, _)| name == key)?;
+                Some((key, value, ours))
+            });
+            match owned {
+                Some((key, value, ours)) => {
+                    written.insert(key.to_string());
+                    if value == ours {
+                        merged += line;
+                    } else {
+                        merged += &format!("{} = {}", key, ours);
+                    }
+                }
+                None => merged += line,
+            }
+            merged.push('\n');
+        }
+        let defaults = Config::default().settings();
+        for ((key, value), (_, default)) in settings.iter().zip(&defaults) {
+            if !written.contains(key) && value != default {
+                merged += &format!("{} = {}\n", key, value);
+            }
+        }
+        merged
+    }
+
+    // (key, value) for every line parse understands, in a fixed order
+    fn settings(&self) -> Vec<(String, String)> {
+        let settings: [(&str, &dyn std::fmt::Display); 18] = [
+            ("width", &self.width),
+            ("height", &self.height),
+            ("multiplier", &self.multiplier),
+            ("render_width", &self.render_width),
+            ("render_height", &self.render_height),
+            ("aspect_ratio", &self.aspect_ratio),
+            ("fov", &self.fov),
+            ("move_speed", &self.move_speed),
+            ("turn_speed", &self.turn_speed),
+            ("confirm_quit", &self.confirm_quit),
+            ("follow", &self.follow),
+            ("fov_fill", &self.fov_fill),
+            ("show_grid", &self.show_grid),
+            ("grid_labels", &self.grid_labels),
+            ("height_legend", &self.height_legend),
+            ("flip_y", &self.flip_y),
+            ("sector_colors", &self.sector_colors),
+            ("theme", &self.theme),
+        ];
+        let bindings = self
+            .bindings
+            .iter()
+            .map(|(action, key)| (format!("key.{}", action), key.clone()));
+        settings
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .chain(bindings)
+            .collect()
+    }
+
+    pub fn window_size(&self) -> (u32, u32) {
+        (self.width * self.multiplier, self.height * self.multiplier)
+    }
+}
+
+// Values that don't parse leave the setting alone
+fn set<T: std::str::FromStr>(setting: &mut T, value: &str) {
+    if let Ok(parsed) = value.parse() {
+        *setting = parsed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_resolution_overrides_the_defaults() {
+        let config = Config::parse("# Bigger window\nwidth = 640\nheight = 400\nmultiplier = 2\n");
+        assert_eq!(
+            (config.width, config.height, config.multiplier),
+            (640, 400, 2)
+        );
+        assert_eq!(config.window_size(), (1280, 800));
+        assert_eq!(
+            Config {
+                width: 320,
+                height: 240,
+                multiplier: 4,
+                ..config.clone()
+            },
+            Config::default()
+        );
+        assert_eq!(Config::parse(&config.merge_into("")), config);
+    }
+
+    #[test]
+    fn malformed_or_missing_settings_keep_their_defaults() {
+        let config = Config::parse("width = wide\nheight\nmove_speed = 5 # faster\nspeed = 9\n");
+        assert_eq!(config.width, 320);
+        assert_eq!(config.move_speed, 5.);
+        assert_eq!(Config::parse("multiplier = 0"), Config::default());
+        assert_eq!(Config::load("no/such/rune.toml"), Config::default());
+    }
+
+    #[test]
+    fn key_lines_rebind_known_actions_only() {
+        let config = Config::parse("key.grid = F10\nkey.fly = Space\ngrid = J\n");
+        assert_eq!(config.bindings["grid"], "F10");
+        assert!(!config.bindings.contains_key("fly"));
+        assert_eq!(config.bindings.len(), DEFAULT_BINDINGS.len());
+        assert_eq!(config.bindings["quit"], "Q");
+    }
+
+    #[test]
+    fn fov_outside_what_can_be_projected_keeps_the_default() {
+        assert_eq!(Config::parse("fov = 110").fov, 110.);
+        assert_eq!(Config::parse("fov = 180").fov, 90.);
+        assert_eq!(Config::parse("fov = -5").fov, 90.);
+    }
+
+    #[test]
+    fn merging_rewrites_only_changed_settings() {
+        let text = "# Mine\nwidth = 640 # wide\nfollow = false\nfuture_setting = 3\n";
+        let mut config = Config::parse(text);
+        assert_eq!(config.merge_into(text), text);
+
+        config.follow = true;
+        config.theme = "night".to_string();
+        assert_eq!(
+            config.merge_into(text),
+            "# Mine\nwidth = 640 # wide\nfollow = true\nfuture_setting = 3\ntheme = night\n"
+        );
+    }
+
+    #[test]
+    fn save_then_load_round_trips_every_field() {
+        let config = Config {
+            width: 400,
+            height: 300,
+            multiplier: 3,
+            render_width: 640,
+            render_height: 400,
+            aspect_ratio: 1.6,
+            fov: 75.,
+            move_speed: 4.5,
+            turn_speed: 0.08,
+            confirm_quit: false,
+            follow: true,
+            fov_fill: true,
+            show_grid: false,
+            grid_labels: true,
+            height_legend: true,
+            flip_y: false,
+            sector_colors: true,
+            theme: "night".to_string(),
+            bindings: Config::default()
+                .bindings
+                .into_iter()
+                .map(|(action, key)| match action.as_str() {
+                    "grid" => (action, "F10".to_string()),
+                    _ => (action, key),
+                })
+                .collect(),
+        };
+        let path = std::env::temp_dir().join(format!("rune-config-{}.toml", std::process::id()));
+        let path = path.to_str().unwrap();
+        config.save(path).unwrap();
+        let loaded = Config::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded, config);
+    }
+}
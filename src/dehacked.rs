@@ -0,0 +1,196 @@
+use regex::Regex;
+
+#[derive(Clone, Debug, Default)]
+pub struct DehackedSection {
+    pub index: usize,
+    pub fields: Vec<(String, String)>,
+}
+
+impl DehackedSection {
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TextReplacement {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DehackedPatch {
+    pub doom_version: Option<i32>,
+    pub patch_format: Option<i32>,
+    pub things: Vec<DehackedSection>,
+    pub frames: Vec<DehackedSection>,
+    pub weapons: Vec<DehackedSection>,
+    pub texts: Vec<TextReplacement>,
+    pub strings: Vec<(String, String)>, // BEX [STRINGS] mnemonic replacements
+}
+
+enum Block {
+    Header,
+    Thing,
+    Frame,
+    Weapon,
+    Strings,
+    Ignored, // Sections we don't keep yet (Ammo, Sound, Misc, ...)
+}
+
+impl DehackedPatch {
+    pub fn parse(source: &str) -> Self {
+        let section_re = Regex::new(r"^([A-Za-z]+)\s+(\d+)").unwrap();
+        let text_re = Regex::new(r"^Text\s+(\d+)\s+(\d+)").unwrap();
+        let source = source.replace('\r', "");
+
+        let mut patch = DehackedPatch::default();
+        let mut block = Block::Header;
+        let mut rest = source.as_str();
+        while !rest.is_empty() {
+            let (line, remaining) = rest.split_once('\n').unwrap_or((rest, ""));
+            rest = remaining;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // Text blocks are "Text <old length> <new length>" followed by exactly that many
+            // characters of old then new text, newlines included
+            if let Some(caps) = text_re.captures(line) {
+                let from_len: usize = caps[1].parse().unwrap_or(0);
+                let to_len: usize = caps[2].parse().unwrap_or(0);
+                let mut chars = rest.chars();
+                let from: String = chars.by_ref().take(from_len).collect();
+                let to: String = chars.by_ref().take(to_len).collect();
+                rest = chars.as_str();
+                patch.texts.push(TextReplacement { from, to });
+                block = Block::Ignored;
+                continue;
+            }
+
+            if let Some(caps) = section_re.captures(line) {
+                let index: usize = caps[2].parse().unwrap_or(0);
+                block = match caps[1].to_ascii_lowercase().as_str() {
+                    "thing" => Block::Thing,
+                    "frame" => Block::Frame,
+                    "weapon" => Block::Weapon,
+                    _ => Block::Ignored,
+                };
+                let section = DehackedSection {
+                    index,
+                    fields: Vec::new(),
+                };
+                match block {
+                    Block::Thing => patch.things.push(section),
+                    Block::Frame => patch.frames.push(section),
+                    Block::Weapon => patch.weapons.push(section),
+                    _ => (),
+                }
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim().to_string(), value.trim().to_string()),
+                None => {
+                    // BEX [SECTION] headers start blocks we mostly don't understand yet,
+                    // anything else (like the "Patch File for..." banner) is noise
+                    if line.starts_with('[') {
+                        block = if line.eq_ignore_ascii_case("[STRINGS]") {
+                            Block::Strings
+                        } else {
+                            Block::Ignored
+                        };
+                    }
+                    continue;
+                }
+            };
+            if let Block::Strings = block {
+                // A trailing backslash continues the value on the next line
+                let mut value = value;
+                while value.ends_with('\\') && !rest.is_empty() {
+                    value.pop();
+                    let (next, remaining) = rest.split_once('\n').unwrap_or((rest, ""));
+                    rest = remaining;
+                    value.push_str(next.trim());
+                }
+                patch.strings.push((key, value.replace("\\n", "\n")));
+                continue;
+            }
+            let section = match block {
+                Block::Header => {
+                    match key.to_ascii_lowercase().as_str() {
+                        "doom version" => patch.doom_version = value.parse().ok(),
+                        "patch format" => patch.patch_format = value.parse().ok(),
+                        _ => (),
+                    }
+                    continue;
+                }
+                Block::Thing => patch.things.last_mut(),
+                Block::Frame => patch.frames.last_mut(),
+                Block::Weapon => patch.weapons.last_mut(),
+                Block::Strings | Block::Ignored => None,
+            };
+            if let Some(section) = section {
+                section.fields.push((key, value));
+            }
+        }
+        patch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "Patch File for DeHackEd v3.0\r
+# Faster, tougher zombiemen\r
+Doom version = 19\r
+Patch format = 6\r
+\r
+Thing 1 (Player)\r
+Initial health = 200\r
+Speed = 12\r
+\r
+Frame 12\r
+Duration = 4\r
+\r
+Ammo 0 (Bullets)\r
+Max ammo = 400\r
+";
+
+    #[test]
+    fn parses_thing_section_fields() {
+        let patch = DehackedPatch::parse(PATCH);
+        assert_eq!(patch.doom_version, Some(19));
+        assert_eq!(patch.patch_format, Some(6));
+        assert_eq!(patch.things.len(), 1);
+        let thing = &patch.things[0];
+        assert_eq!(thing.index, 1);
+        assert_eq!(thing.field("initial health"), Some("200"));
+        assert_eq!(thing.field("Speed"), Some("12"));
+        assert_eq!(thing.fields.len(), 2);
+        assert_eq!(patch.frames[0].field("Duration"), Some("4"));
+        // Ammo isn't kept, and its fields mustn't land in the frame before it
+        assert_eq!(patch.frames[0].fields.len(), 1);
+    }
+
+    #[test]
+    fn parses_text_and_bex_strings() {
+        let patch = DehackedPatch::parse(
+            "Text 6 5\nHangarDepot\n[STRINGS]\nGOTARMOR = Got \\\n  armor!\nQUITMSG = bye\\nnow\n",
+        );
+        assert_eq!(patch.texts[0].from, "Hangar");
+        assert_eq!(patch.texts[0].to, "Depot");
+        assert_eq!(
+            patch.strings,
+            [
+                ("GOTARMOR".to_string(), "Got armor!".to_string()),
+                ("QUITMSG".to_string(), "bye\nnow".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::cmp;
+
+use sdl2::{
+    pixels::{Color, PixelFormatEnum},
+    rect::Rect,
+    render::{BlendMode, Texture, TextureCreator, WindowCanvas},
+    video::WindowContext,
+};
+
+// Software pixel buffer for the 3D view, ARGB8888 like the streaming texture it's copied to
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    fn pack(color: Color) -> u32 {
+        u32::from(color.a) << 24
+            | u32::from(color.r) << 16
+            | u32::from(color.g) << 8
+            | u32::from(color.b)
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        self.pixels.fill(Self::pack(color));
+    }
+
+    // Off-screen pixels are silently dropped
+    pub fn put(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+        self.pixels[y as usize * self.width as usize + x as usize] = Self::pack(color);
+    }
+
+    // Vertical span from y0 to y1 inclusive, in either order, clipped to the buffer
+    pub fn vline(&mut self, x: i32, y0: i32, y1: i32, color: Color) {
+        if x < 0 || x >= self.width as i32 {
+            return;
+        }
+        let top = cmp::max(cmp::min(y0, y1), 0);
+        let bottom = cmp::min(cmp::max(y0, y1), self.height as i32 - 1);
+        let packed = Self::pack(color);
+        for y in top..=bottom {
+            self.pixels[y as usize * self.width as usize + x as usize] = packed;
+        }
+    }
+
+    // Bresenham, including both end points, so it lands on the same pixels as SDL's draw_line
+    pub fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            self.put(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    // Draws color over the pixel at coverage (0-1) opacity, keeping track of alpha so a
+    // buffer cleared to transparent can be laid over the canvas
+    pub fn blend(&mut self, x: i32, y: i32, color: Color, coverage: f32) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+        let index = y as usize * self.width as usize + x as usize;
+        let dst = self.pixels[index];
+        let src_a = coverage.clamp(0., 1.) * f32::from(color.a) / 255.;
+        let dst_a = (dst >> 24) as f32 / 255.;
+        let out_a = src_a + dst_a * (1. - src_a);
+        if out_a == 0. {
+            return;
+        }
+        let mix = |src: u8, shift: u32| {
+            let dst = ((dst >> shift) & 0xFF) as f32;
+            ((f32::from(src) * src_a + dst * dst_a * (1. - src_a)) / out_a).round() as u8
+        };
+        let blended = Color::RGBA(
+            mix(color.r, 16),
+            mix(color.g, 8),
+            mix(color.b, 0),
+            (out_a * 255.).round() as u8,
+        );
+        self.pixels[index] = Self::pack(blended);
+    }
+
+    // Xiaolin Wu's anti-aliased line: each step along the major axis splits the colour
+    // between the two pixels straddling the line, and the end points are weighted by how
+    // much of their pixel the line covers
+    pub fn wu_line(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, color: Color) {
+        let fract = |v: f32| v - v.floor();
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        let (x0, y0, x1, y1) = if steep {
+            (y0, x0, y1, x1)
+        } else {
+            (x0, y0, x1, y1)
+        };
+        let (x0, y0, x1, y1) = if x0 > x1 {
+            (x1, y1, x0, y0)
+        } else {
+            (x0, y0, x1, y1)
+        };
+        let mut plot = |x: f32, y: f32, coverage: f32| {
+            let (x, y) = if steep { (y, x) } else { (x, y) };
+            self.blend(x as i32, y as i32, color, coverage);
+        };
+        let dx = x1 - x0;
+        let gradient = if dx == 0. { 1. } else { (y1 - y0) / dx };
+
+        let mut end = |x: f32, y: f32, gap: f32| {
+            let x_end = x.round();
+            let y_end = y + gradient * (x_end - x);
+            plot(x_end, y_end.floor(), (1. - fract(y_end)) * gap);
+            plot(x_end, y_end.floor() + 1., fract(y_end) * gap);
+            x_end
+        };
+        let start_x = end(x0, y0, 1. - fract(x0 + 0.5));
+        let end_x = end(x1, y1, fract(x1 + 0.5));
+
+        let mut y = y0 + gradient * (start_x - x0) + gradient;
+        let mut x = start_x + 1.;
+        while x < end_x {
+            plot(x, y.floor(), 1. - fract(y));
+            plot(x, y.floor() + 1., fract(y));
+            y += gradient;
+            x += 1.;
+        }
+    }
+
+    // Uploads the buffer through the shared texture and copies it to dest, scaling as needed
+    pub fn present(&self, canvas: &mut WindowCanvas, target: &mut FrameTexture, dest: Rect) {
+        let texture = target.sized(self.width, self.height);
+        let bytes: Vec<u8> = self.pixels.iter().flat_map(|p| p.to_ne_bytes()).collect();
+        texture
+            .update(None, &bytes, self.width as usize * 4)
+            .unwrap();
+        canvas.copy(texture, None, dest).unwrap();
+    }
+}
+
+// The streaming texture framebuffers are uploaded through, kept from frame to frame and
+// only recreated when a buffer of a different size comes along
+pub struct FrameTexture<'a> {
+    creator: &'a TextureCreator<WindowContext>,
+    texture: Option<(Texture<'a>, u32, u32)>,
+}
+
+impl<'a> FrameTexture<'a> {
+    pub fn new(creator: &'a TextureCreator<WindowContext>) -> Self {
+        FrameTexture {
+            creator,
+            texture: None,
+        }
+    }
+
+    fn sized(&mut self, width: u32, height: u32) -> &mut Texture<'a> {
+        if !matches!(self.texture, Some((_, w, h)) if (w, h) == (width, height)) {
+            let mut texture = self
+                .creator
+                .create_texture_streaming(PixelFormatEnum::ARGB8888, width, height)
+                .unwrap();
+            // Opaque buffers look the same either way; transparent ones go over what's there
+            texture.set_blend_mode(BlendMode::Blend);
+            self.texture = Some((texture, width, height));
+        }
+        &mut self.texture.as_mut().unwrap().0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(framebuffer: &Framebuffer) -> Vec<(u32, u32)> {
+        (0..framebuffer.pixels.len() as u32)
+            .filter(|&idx| framebuffer.pixels[idx as usize] != 0)
+            .map(|idx| (idx % framebuffer.width, idx / framebuffer.width))
+            .collect()
+    }
+
+    #[test]
+    fn put_clips_to_the_buffer() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        for (x, y) in [(-1, 0), (0, -1), (4, 0), (0, 3), (i32::MAX, i32::MIN)] {
+            framebuffer.put(x, y, Color::WHITE);
+        }
+        assert!(lit(&framebuffer).is_empty());
+        framebuffer.put(3, 2, Color::WHITE);
+        assert_eq!(lit(&framebuffer), [(3, 2)]);
+        assert_eq!(framebuffer.pixels[11], 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn vline_fills_inclusive_span_in_either_order() {
+        let mut framebuffer = Framebuffer::new(3, 6);
+        framebuffer.vline(1, 4, 2, Color::RED);
+        assert_eq!(lit(&framebuffer), [(1, 2), (1, 3), (1, 4)]);
+        assert_eq!(framebuffer.pixels[2 * 3 + 1], 0xFFFF0000);
+
+        // Clipped at the top and bottom, and nothing at all off the sides
+        let mut framebuffer = Framebuffer::new(3, 4);
+        framebuffer.vline(0, -5, 10, Color::RED);
+        framebuffer.vline(3, 0, 3, Color::RED);
+        framebuffer.vline(-1, 0, 3, Color::RED);
+        assert_eq!(lit(&framebuffer), [(0, 0), (0, 1), (0, 2), (0, 3)]);
+    }
+
+    fn line_pixels(x0: i32, y0: i32, x1: i32, y1: i32) -> Vec<(u32, u32)> {
+        let mut framebuffer = Framebuffer::new(8, 8);
+        framebuffer.line(x0, y0, x1, y1, Color::WHITE);
+        let mut pixels = lit(&framebuffer);
+        pixels.sort_unstable();
+        pixels
+    }
+
+    #[test]
+    fn line_matches_bresenham() {
+        let shallow = [(0, 0), (1, 0), (2, 1), (3, 1), (4, 2), (5, 2)];
+        assert_eq!(line_pixels(0, 0, 5, 2), shallow);
+        let steep = [(0, 0), (0, 1), (1, 2), (1, 3), (1, 4)];
+        assert_eq!(line_pixels(0, 0, 1, 4), steep);
+        assert_eq!(
+            line_pixels(6, 7, 2, 3),
+            [(2, 3), (3, 4), (4, 5), (5, 6), (6, 7)]
+        );
+        assert_eq!(line_pixels(1, 5, 4, 5), [(1, 5), (2, 5), (3, 5), (4, 5)]);
+        assert_eq!(line_pixels(3, 3, 3, 3), [(3, 3)]);
+        // Clipping is per pixel, so a line running off the edge keeps its on-screen part
+        assert_eq!(line_pixels(6, 0, 9, 0), [(6, 0), (7, 0)]);
+    }
+
+    #[test]
+    fn wu_line_end_points_are_partly_covered() {
+        let mut framebuffer = Framebuffer::new(12, 8);
+        framebuffer.wu_line(1., 1.25, 9., 5.25, Color::WHITE);
+        let alpha = |x: u32, y: u32| framebuffer.pixels[(y * 12 + x) as usize] >> 24;
+        // Each end covers half its pixel across x, split between the two rows it straddles
+        for (x, y) in [(1, 1), (1, 2), (9, 5), (9, 6)] {
+            assert!((1..255).contains(&alpha(x, y)), "({}, {})", x, y);
+        }
+        assert!(alpha(1, 1) > alpha(1, 2));
+        // Between them every column shares one pixel's worth of coverage over two rows
+        for x in 2..9 {
+            let column: u32 = (0..8).map(|y| alpha(x, y)).sum();
+            assert!((254..=256).contains(&column), "column {} has {}", x, column);
+        }
+        assert!(lit(&framebuffer).iter().all(|&(x, _)| (1..=9).contains(&x)));
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     cmp,
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
     f32::consts,
     time::{Duration, Instant},
 };
@@ -8,13 +8,31 @@ use std::{
 use sdl2::{
     event::Event,
     keyboard::{Keycode, Scancode},
+    mouse::MouseButton,
     pixels::Color,
-    EventPump,
+    render::WindowCanvas,
 };
 
-use crate::{level::Level, renderer::Renderer, wad::WadFile};
+use crate::{
+    camera_path::CameraPath,
+    config::{Config, DEFAULT_BINDINGS},
+    framebuffer::FrameTexture,
+    jukebox::{Jukebox, MusicPlayer},
+    level::Level,
+    level_names,
+    lighting::Lighting,
+    math::normalize_angle,
+    picture::Picture,
+    renderer::{AutomapOptions, AutomapReveal, Renderer},
+    strings::Strings,
+    text,
+    thing_info::ThingCategory,
+    wad::{GameVariant, Thing, WadFile},
+};
 
+#[derive(Clone, Copy, PartialEq)]
 enum GameState {
+    EpisodeSelect(usize), // Menu before play, with the highlighted entry
     Viewing,
     Playing,
     Paused,
@@ -22,203 +40,1062 @@ enum GameState {
     _GameOver,
 }
 
-#[derive(Copy, Clone, Debug)]
+// Full-screen previews of WAD graphics, holding the index being shown
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Browser {
+    Textures(usize),
+    Flats(usize),
+    Hex { lump: usize, row: usize }, // Raw bytes of any lump, scrolled to a row of 16
+}
+
+impl Browser {
+    // The next or previous entry in whatever is being browsed, wrapping at either end
+    fn step(self, wad: &WadFile, forward: bool) -> Browser {
+        match self {
+            Browser::Textures(index) => Browser::Textures(Interface::cycle_index(
+                index,
+                wad.texture_defs.len(),
+                forward,
+            )),
+            Browser::Flats(index) => {
+                Browser::Flats(Interface::cycle_index(index, wad.flats.len(), forward))
+            }
+            Browser::Hex { lump, .. } => Browser::Hex {
+                lump: Interface::cycle_index(lump, wad.directory.len(), forward),
+                row: 0,
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Player {
     pub x: f32,
     pub y: f32,
     pub angle: f32,
+    pub pitch: f32,
+    pub vx: f32,
+    pub vy: f32,
+}
+
+impl Player {
+    // Doom's per-tic friction, and the speed below which it just stops the player
+    pub const FRICTION: f32 = 0.90625;
+    const STOP_SPEED: f32 = 0.0625;
+
+    // Thrust of speed * (1 - friction) each tic tops out at speed
+    pub fn thrust(&mut self, angle: f32, speed: f32) {
+        let accel = speed * (1. - Self::FRICTION);
+        self.vx += angle.cos() * accel;
+        self.vy += angle.sin() * accel;
+    }
+
+    // Moves by the velocity then slows it down. A blocked move slides along the wall it hit,
+    // or failing that tries each axis alone, like Doom falls back to when the full step fails.
+    pub fn tic(&mut self, level: &Level) {
+        let from = (self.x, self.y);
+        let mut steps = vec![(self.vx, self.vy)];
+        if let Some(wall) = level.blocking_linedef(from, (from.0 + self.vx, from.1 + self.vy)) {
+            // Slide along the wall, keeping only the part of the move that runs beside it
+            let (nx, ny) = wall.normal(level);
+            let into = self.vx * nx + self.vy * ny;
+            steps.push((self.vx - into * nx, self.vy - into * ny));
+        }
+        steps.extend([(self.vx, 0.), (0., self.vy)]);
+        // A zero step always succeeds, so it doesn't count as sliding
+        match steps
+            .into_iter()
+            .filter(|&step| step != (0., 0.))
+            .find(|&(dx, dy)| level.try_move(from, (from.0 + dx, from.1 + dy)))
+        {
+            Some((dx, dy)) => {
+                self.x += dx;
+                self.y += dy;
+            }
+            None => {
+                self.vx = 0.;
+                self.vy = 0.;
+            }
+        }
+        self.vx *= Self::FRICTION;
+        self.vy *= Self::FRICTION;
+        if self.vx.hypot(self.vy) < Self::STOP_SPEED {
+            self.vx = 0.;
+            self.vy = 0.;
+        }
+    }
+}
+
+// What the interface draws over the renderer's view. When it changes between frames, the
+// frame on screen is stale even if the view hasn't moved.
+#[derive(PartialEq)]
+struct Overlays {
+    level: usize,
+    state: GameState,
+    view_3d: bool,
+    bsp_render: Option<u32>,
+    bsp_subtree: Option<i16>,
+    browser: Option<Browser>,
+    jukebox: Option<(usize, Option<usize>)>, // Selected and playing tracks while it's open
+    quit_pending: bool,
+    key_locator: bool,
+    thing_filter: HashSet<ThingCategory>,
+    marks: usize,
+    measure_anchor: Option<(f32, f32)>,
+    measure_end: Option<(f32, f32)>,
+    picked_linedef: Option<(usize, f32)>,
+    automap_reveal: AutomapReveal,
+}
+
+// Config::bindings looked up as SDL keys: the action a key press triggers, and the key to
+// check for actions that last as long as it's held
+#[derive(Default)]
+struct Keys {
+    actions: HashMap<Keycode, &'static str>,
+    held: HashMap<&'static str, Scancode>,
+}
+
+impl Keys {
+    // Key names go through SDL, so this waits until run; an unknown name leaves its action
+    // without a key
+    fn resolve(bindings: &BTreeMap<String, String>) -> Self {
+        let mut keys = Keys::default();
+        for (action, default) in DEFAULT_BINDINGS {
+            let name = bindings.get(action).map_or(default, String::as_str);
+            match (Keycode::from_name(name), Scancode::from_name(name)) {
+                (Some(keycode), Some(scancode)) => keys.bind(action, keycode, scancode),
+                _ => eprintln!("Unknown key {:?} for {}", name, action),
+            }
+        }
+        keys
+    }
+
+    fn bind(&mut self, action: &'static str, keycode: Keycode, scancode: Scancode) {
+        self.actions.insert(keycode, action);
+        self.held.insert(action, scancode);
+    }
+
+    fn action(&self, keycode: Keycode) -> Option<&'static str> {
+        self.actions.get(&keycode).copied()
+    }
 }
 
 pub struct Interface {
     state: GameState,
     pressed_keys: HashSet<Scancode>,
+    newly_pressed: HashSet<Scancode>, // Keys that went down this frame
     bsp_render: Option<u32>,
+    bsp_subtree: Option<i16>,
+    view_3d: bool,
+    attract_timeout: Option<Duration>,
+    attract_interval: Duration,
+    last_input: Instant,
+    attract_active: Option<Instant>, // When attract mode last changed level
+    picked_linedef: Option<(usize, f32)>, // Nearest line to the last right click, and how far
+    confirm_quit: bool,
+    quit_pending: bool,
+    thing_filter: HashSet<ThingCategory>, // Which things the automap shows
+    key_locator: bool,
+    marks: HashMap<usize, Vec<(f32, f32)>>, // Automap marks for each level index
+    jukebox_open: bool,
+    camera_path: CameraPath, // Flythrough keyframes, which drive the player while playing
+    browser: Option<Browser>, // Texture or flat preview replacing the view
+    measure_anchor: Option<(f32, f32)>,
+    measure_end: Option<(f32, f32)>,
+    player_start: usize, // Which co-op start, 0-3, the camera was last placed at
+    visited: HashSet<usize>, // Subsectors the player has been in on this level
+    automap_reveal: AutomapReveal,
+    config: Config,
+    keys: Keys,
 }
 
 impl Interface {
-    pub const WIDTH: u32 = 320;
-    pub const HEIGHT: u32 = 240;
-    pub const MULTIPLIER: u32 = 4;
+    pub const TICRATE: u32 = 35;
+    const HEX_PAGE: usize = 32; // Rows of the hex viewer on screen at once
 
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
         Interface {
             pressed_keys: HashSet::new(),
+            newly_pressed: HashSet::new(),
             state: GameState::Viewing,
             bsp_render: None,
+            bsp_subtree: None,
+            view_3d: false,
+            attract_timeout: Some(Duration::from_secs(120)),
+            attract_interval: Duration::from_secs(30),
+            last_input: Instant::now(),
+            attract_active: None,
+            picked_linedef: None,
+            confirm_quit: config.confirm_quit,
+            quit_pending: false,
+            thing_filter: HashSet::from([ThingCategory::Key, ThingCategory::PlayerStart]),
+            key_locator: false,
+            marks: HashMap::new(),
+            jukebox_open: false,
+            camera_path: CameraPath::default(),
+            browser: None,
+            measure_anchor: None,
+            measure_end: None,
+            player_start: 0,
+            visited: HashSet::new(),
+            automap_reveal: AutomapReveal::All,
+            config,
+            keys: Keys::default(),
+        }
+    }
+
+    // Step through a list of len items, wrapping at either end
+    fn cycle_index(index: usize, len: usize, forward: bool) -> usize {
+        if len == 0 {
+            0
+        } else if forward {
+            (index + 1) % len
+        } else {
+            (index + len - 1) % len
+        }
+    }
+
+    fn add_mark(&mut self, level: usize, x: f32, y: f32) {
+        self.marks.entry(level).or_default().push((x, y));
+    }
+
+    fn clear_marks(&mut self, level: usize) {
+        self.marks.remove(&level);
+    }
+
+    fn level_marks(&self, level: usize) -> &[(f32, f32)] {
+        self.marks.get(&level).map_or(&[], Vec::as_slice)
+    }
+
+    fn overlays(&self, level: usize, jukebox: &Jukebox) -> Overlays {
+        Overlays {
+            level,
+            state: self.state,
+            view_3d: self.view_3d,
+            bsp_render: self.bsp_render,
+            bsp_subtree: self.bsp_subtree,
+            browser: self.browser,
+            jukebox: self
+                .jukebox_open
+                .then_some((jukebox.selected, jukebox.playing)),
+            quit_pending: self.quit_pending,
+            key_locator: self.key_locator,
+            thing_filter: self.thing_filter.clone(),
+            marks: self.level_marks(level).len(),
+            measure_anchor: self.measure_anchor,
+            measure_end: self.measure_end,
+            picked_linedef: self.picked_linedef,
+            automap_reveal: self.automap_reveal,
+        }
+    }
+
+    // (dx, dy, straight-line distance) between two world points
+    pub fn measure(from: (f32, f32), to: (f32, f32)) -> (f32, f32, f32) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        (dx, dy, dx.hypot(dy))
+    }
+
+    // First click anchors, second measures, third clears
+    fn measure_click(&mut self, point: (f32, f32)) {
+        match (self.measure_anchor, self.measure_end) {
+            (None, _) => self.measure_anchor = Some(point),
+            (Some(_), None) => self.measure_end = Some(point),
+            (Some(_), Some(_)) => {
+                self.measure_anchor = None;
+                self.measure_end = None;
+            }
+        }
+    }
+
+    // Escape or the quit key asks first when confirm_quit is set; returns true once we should
+    // really quit
+    fn quit_key(&mut self, keycode: Keycode) -> bool {
+        if self.quit_pending {
+            // Y confirms, any other key backs out
+            self.quit_pending = false;
+            return keycode == Keycode::Y;
+        }
+        let quit = keycode == Keycode::Escape || self.keys.action(keycode) == Some("quit");
+        if quit && self.confirm_quit {
+            self.quit_pending = true;
+            return false;
         }
+        quit
     }
 
-    pub fn run(&mut self, wad: &WadFile) {
+    // Depth of the BSP search overlay; stepping turns it on and it never goes below zero
+    fn step_bsp_depth(&mut self, step: i32) {
+        self.bsp_render = Some(match self.bsp_render {
+            None => 0,
+            Some(depth) => depth.saturating_add_signed(step),
+        });
+    }
+
+    fn toggle_thing_category(&mut self, category: ThingCategory) {
+        if !self.thing_filter.remove(&category) {
+            self.thing_filter.insert(category);
+        }
+    }
+
+    // The next start slot after current that the level has, wrapping round
+    fn next_player_start(starts: &[Option<Thing>; 4], current: usize) -> Option<usize> {
+        (1..=4)
+            .map(|step| (current + step) % 4)
+            .find(|&slot| starts[slot].is_some())
+    }
+
+    // Marks the subsector the player is standing in
+    fn visit(&mut self, player: &Player, level: &Level) {
+        self.visited
+            .insert(level.subsector_at(player.x.trunc() as i16, player.y.trunc() as i16));
+    }
+
+    fn visited_percent(&self, level: &Level) -> f32 {
+        100. * self.visited.len() as f32 / level.subsectors.len().max(1) as f32
+    }
+
+    // Centre of the closest subsector not yet visited, or None once everything has been
+    fn nearest_unvisited(&self, player: &Player, level: &Level) -> Option<(f32, f32)> {
+        (0..level.subsectors.len())
+            .filter(|ssec| !self.visited.contains(ssec))
+            .filter_map(|ssec| level.subsector_centroid(ssec))
+            .min_by(|a, b| {
+                let distance = |(x, y): (f32, f32)| (x - player.x).hypot(y - player.y);
+                distance(*a).total_cmp(&distance(*b))
+            })
+    }
+
+    fn place_at(player: &mut Player, thing: &Thing) {
+        player.x = f32::from(thing.x);
+        player.y = f32::from(thing.y);
+        player.angle = thing.angle_facing;
+        player.pitch = 0.;
+        player.vx = 0.;
+        player.vy = 0.;
+    }
+
+    fn window_title(map: &str, variant: GameVariant) -> String {
+        format!("Rune - {} ({:?})", level_names::display_name(map), variant)
+    }
+
+    fn start_level(
+        &mut self,
+        map: &str,
+        variant: GameVariant,
+        level: &Level,
+        player: &mut Player,
+        renderer: &mut Renderer,
+        canvas: &mut WindowCanvas,
+    ) {
+        self.bsp_subtree = None;
+        self.picked_linedef = None;
+        self.measure_anchor = None;
+        self.measure_end = None;
+        canvas
+            .window_mut()
+            .set_title(&Self::window_title(map, variant))
+            .unwrap();
+        let player_thing = level.things.iter().find(|t| t.thing_type == 1).unwrap();
+        Self::place_at(player, player_thing);
+        self.player_start = 0;
+        self.visited.clear();
+        renderer.reset_view(level, None);
+        renderer.load_level_colormaps(level);
+    }
+
+    // Kiosk mode: once idle past the timeout, returns the level to switch to every interval
+    fn attract_step(
+        &mut self,
+        now: Instant,
+        current_level: usize,
+        level_count: usize,
+    ) -> Option<usize> {
+        let timeout = self.attract_timeout?;
+        match self.attract_active {
+            None if now.duration_since(self.last_input) >= timeout => {
+                self.attract_active = Some(now);
+                None
+            }
+            Some(advanced) if now.duration_since(advanced) >= self.attract_interval => {
+                self.attract_active = Some(now);
+                Some((current_level + 1) % level_count)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn run(&mut self, wad: &mut WadFile) {
         let mut current_level = 0;
-        let level_count = wad.levels.len();
-        let mut level = Level::new(&wad.levels[current_level]);
-        let mut renderer = Renderer::new();
+        let level_count = wad.level_count();
+        let mut level = Level::new(wad.level(current_level));
+        let mut lighting = Lighting::new(&level);
+        let mut renderer = Renderer::new(&self.config);
+        self.keys = Keys::resolve(&self.config.bindings);
+        let palette = wad.palette();
+        let mut strings = Strings::default();
+        if let Some(patch) = wad.dehacked() {
+            strings.apply_dehacked(&patch);
+        }
+        let quit_prompt = format!(
+            "{}\n\n{}",
+            strings.get("QUITMSG").unwrap_or_default(),
+            strings.get("PRESSYN").unwrap_or_default()
+        );
+        let variant = wad.game_variant();
+        let episodes = wad.episode_list();
+        if episodes.len() > 1 {
+            self.state = GameState::EpisodeSelect(0);
+        }
+        let mut texture_preview: Option<(usize, Picture)> = None;
+        let mut jukebox = Jukebox::new(
+            wad.music_lumps()
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        );
+        renderer.set_colormaps(palette.clone(), wad.colormap(), wad.colormaps());
+        renderer.load_level_colormaps(&level);
         renderer.find_bounds(&level);
         let player_thing = level.things.iter().find(|t| t.thing_type == 1).unwrap();
         let mut player = Player {
             x: f32::from(player_thing.x),
             y: f32::from(player_thing.y),
             angle: player_thing.angle_facing,
+            pitch: 0.,
+            vx: 0.,
+            vy: 0.,
         };
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
+        // The viewer still works without sound, the jukebox just stays quiet
+        let mut music = MusicPlayer::open(&sdl_context)
+            .map_err(|error| eprintln!("No music: {}", error))
+            .ok();
 
+        let (window_width, window_height) = self.config.window_size();
         let window = video_subsystem
             .window(
-                "Rune",
-                Self::WIDTH * Self::MULTIPLIER,
-                Self::HEIGHT * Self::MULTIPLIER,
+                &Self::window_title(wad.level_name(current_level), variant),
+                window_width,
+                window_height,
             )
             .position_centered()
             .build()
             .unwrap();
 
         let mut canvas = window.into_canvas().build().unwrap();
+        let texture_creator = canvas.texture_creator();
+        let mut frame_texture = FrameTexture::new(&texture_creator);
 
         canvas.set_draw_color(Color::BLACK);
         canvas.clear();
         canvas.present();
         let mut event_pump = sdl_context.event_pump().unwrap();
+        let run_start = Instant::now();
+        let mut last_frame = run_start;
+        let mut unused_time = Duration::ZERO; // Since the last whole tic of movement
         'running: loop {
             let loop_start = Instant::now();
-            canvas.set_draw_color(Color::BLACK);
-            canvas.clear();
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
+            let tics = Self::take_tics(&mut unused_time, loop_start - last_frame);
+            last_frame = loop_start;
+            let tic = (run_start.elapsed().as_millis() * u128::from(Self::TICRATE) / 1000) as u32;
+            let overlays = self.overlays(current_level, &jukebox);
+            let events: Vec<Event> = event_pump.poll_iter().collect();
+            let taps = events.iter().filter_map(|event| match event {
+                Event::KeyDown {
+                    scancode, repeat, ..
+                } if !repeat => *scancode,
+                _ => None,
+            });
+            self.update_keys(
+                event_pump.keyboard_state().pressed_scancodes().collect(),
+                taps,
+            );
+            for event in events {
+                if let Event::KeyDown { scancode, .. } = event {
+                    self.last_input = Instant::now();
+                    if self.attract_active.take().is_some() {
+                        // The key that wakes us from attract mode does nothing else
+                        continue;
                     }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Q),
+                    // Key repeat would fire the actions below again
+                    if !scancode.is_some_and(|scancode| self.just_pressed(scancode)) {
+                        continue;
+                    }
+                }
+                let action = match event {
+                    Event::KeyDown {
+                        keycode: Some(keycode),
                         ..
-                    } => break 'running,
+                    } => self.keys.action(keycode),
+                    _ => None,
+                };
+                match event {
+                    Event::Quit { .. } => break 'running,
                     Event::KeyDown {
-                        keycode: Some(Keycode::Period),
+                        keycode: Some(keycode),
                         ..
-                    } => {
-                        current_level = cmp::min(level_count - 1, current_level + 1);
-                        level = Level::new(&wad.levels[current_level]);
-                        let player_thing = level.things.iter().find(|t| t.thing_type == 1).unwrap();
-                        player.x = f32::from(player_thing.x);
-                        player.y = f32::from(player_thing.y);
-                        player.angle = player_thing.angle_facing;
-                        renderer.find_bounds(&level);
+                    } if self.quit_pending
+                        || keycode == Keycode::Escape
+                        || action == Some("quit") =>
+                    {
+                        // Handled here even when it doesn't quit, so N or a second Escape
+                        // only dismisses the prompt
+                        let quit = self.quit_key(keycode);
+                        if quit {
+                            break 'running;
+                        }
                     }
                     Event::KeyDown {
-                        keycode: Some(Keycode::Comma),
+                        keycode: Some(keycode),
                         ..
-                    } => {
-                        current_level = if current_level > 0 {
-                            current_level - 1
-                        } else {
-                            0
+                    } if matches!(self.state, GameState::EpisodeSelect(_)) => {
+                        let GameState::EpisodeSelect(selected) = self.state else {
+                            continue;
                         };
-                        level = Level::new(&wad.levels[current_level]);
-                        let player_thing = level.things.iter().find(|t| t.thing_type == 1).unwrap();
-                        player.x = f32::from(player_thing.x);
-                        player.y = f32::from(player_thing.y);
-                        player.angle = player_thing.angle_facing;
-                        renderer.find_bounds(&level);
+                        match keycode {
+                            Keycode::Up | Keycode::Down => {
+                                self.state = GameState::EpisodeSelect(Self::cycle_index(
+                                    selected,
+                                    episodes.len(),
+                                    keycode == Keycode::Down,
+                                ))
+                            }
+                            Keycode::Return => {
+                                let first_map = format!("E{}M1", episodes[selected]);
+                                if let Some(index) = wad.level_index(&first_map) {
+                                    current_level = index;
+                                    level = Level::new(wad.level(current_level));
+                                    lighting = Lighting::new(&level);
+                                    self.start_level(
+                                        wad.level_name(current_level),
+                                        variant,
+                                        &level,
+                                        &mut player,
+                                        &mut renderer,
+                                        &mut canvas,
+                                    );
+                                }
+                                self.state = GameState::Viewing;
+                            }
+                            _ => (),
+                        }
                     }
                     Event::KeyDown {
-                        keycode: Some(Keycode::N),
+                        keycode: Some(keycode),
                         ..
-                    } => match self.bsp_render {
-                        None => (),
-                        Some(level) => {
-                            if level == 0 {
-                                self.bsp_render = None;
-                            } else {
-                                self.bsp_render = Some(level - 1)
+                    } if self.jukebox_open
+                        && matches!(
+                            keycode,
+                            Keycode::Left | Keycode::Right | Keycode::Return | Keycode::Backspace
+                        ) =>
+                    {
+                        match keycode {
+                            Keycode::Left => jukebox.previous(),
+                            Keycode::Right => jukebox.next(),
+                            Keycode::Return => jukebox.play(),
+                            _ => jukebox.stop(),
+                        }
+                        if let Some(music) = music.as_mut() {
+                            if let Err(error) = music.follow(&jukebox, wad) {
+                                eprintln!("Couldn't play music: {}", error);
                             }
                         }
-                    },
+                    }
                     Event::KeyDown {
-                        keycode: Some(Keycode::M),
+                        keycode: Some(keycode @ (Keycode::Left | Keycode::Right)),
+                        ..
+                    } if self.browser.is_some() => {
+                        let forward = keycode == Keycode::Right;
+                        self.browser = self.browser.map(|browser| browser.step(wad, forward));
+                    }
+                    Event::KeyDown {
+                        keycode:
+                            Some(
+                                keycode @ (Keycode::Up
+                                | Keycode::Down
+                                | Keycode::PageUp
+                                | Keycode::PageDown),
+                            ),
+                        ..
+                    } if matches!(self.browser, Some(Browser::Hex { .. })) => {
+                        if let Some(Browser::Hex { lump, row }) = self.browser {
+                            let rows = wad.directory[lump].size.div_ceil(16);
+                            let row = match keycode {
+                                Keycode::Up => row.saturating_sub(1),
+                                Keycode::Down => row + 1,
+                                Keycode::PageUp => row.saturating_sub(Self::HEX_PAGE),
+                                _ => row + Self::HEX_PAGE,
+                            };
+                            self.browser = Some(Browser::Hex {
+                                lump,
+                                row: row.min(rows.saturating_sub(1)),
+                            });
+                        }
+                    }
+                    Event::MouseButtonDown {
+                        mouse_btn: MouseButton::Right,
+                        x,
+                        y,
+                        ..
+                    } if !self.view_3d => {
+                        let (x, y) = renderer.screen_to_world(x, y);
+                        self.picked_linedef = level.nearest_linedef(x, y);
+                    }
+                    Event::MouseButtonDown {
+                        mouse_btn: MouseButton::Left,
+                        x,
+                        y,
                         ..
-                    } => match self.bsp_render {
-                        None => self.bsp_render = Some(0),
-                        Some(level) => self.bsp_render = Some(level + 1),
+                    } if !self.view_3d => self.measure_click(renderer.screen_to_world(x, y)),
+                    // Uncovering or resizing the window can lose what was on screen
+                    Event::Window { .. } => renderer.invalidate(),
+                    Event::KeyDown { .. } => match action.unwrap_or("") {
+                        "jukebox" => self.jukebox_open = !self.jukebox_open,
+                        "record_keyframe" => self.camera_path.record_keyframe(
+                            player.x,
+                            player.y,
+                            player.angle,
+                            run_start.elapsed().as_secs_f32(),
+                        ),
+                        "play_path" => {
+                            if self.camera_path.is_playing() {
+                                self.camera_path.stop();
+                            } else {
+                                self.camera_path.play(run_start.elapsed().as_secs_f32());
+                            }
+                        }
+                        "clear_path" => self.camera_path.clear(),
+                        "next_level" => {
+                            current_level = cmp::min(level_count - 1, current_level + 1);
+                            level = Level::new(wad.level(current_level));
+                            lighting = Lighting::new(&level);
+                            self.start_level(
+                                wad.level_name(current_level),
+                                variant,
+                                &level,
+                                &mut player,
+                                &mut renderer,
+                                &mut canvas,
+                            );
+                        }
+                        "previous_level" => {
+                            current_level = if current_level > 0 {
+                                current_level - 1
+                            } else {
+                                0
+                            };
+                            level = Level::new(wad.level(current_level));
+                            lighting = Lighting::new(&level);
+                            self.start_level(
+                                wad.level_name(current_level),
+                                variant,
+                                &level,
+                                &mut player,
+                                &mut renderer,
+                                &mut canvas,
+                            );
+                        }
+                        "reset_view" => renderer.reset_view(&level, None),
+                        "view_player" => renderer.reset_view(&level, Some(&player)),
+                        "zoom_in" => renderer.zoom_by(1.25),
+                        "zoom_out" => renderer.zoom_by(0.8),
+                        "follow" => renderer.toggle_follow(),
+                        "fov_fill" => renderer.toggle_fov_fill(),
+                        "grid" => renderer.toggle_grid(),
+                        "height_legend" => renderer.toggle_height_legend(),
+                        "dirty_tracking" => renderer.toggle_dirty_tracking(),
+                        "player_start" => {
+                            let starts = level.player_starts();
+                            if let Some(slot) = Self::next_player_start(&starts, self.player_start)
+                            {
+                                self.player_start = slot;
+                                Self::place_at(&mut player, &starts[slot].unwrap());
+                            }
+                        }
+                        "nearest_unvisited" => {
+                            if let Some((x, y)) = self.nearest_unvisited(&player, &level) {
+                                player.x = x;
+                                player.y = y;
+                                player.vx = 0.;
+                                player.vy = 0.;
+                            }
+                        }
+                        "flip_y" => renderer.toggle_flip_y(),
+                        "reveal_explored" => {
+                            self.automap_reveal = match self.automap_reveal {
+                                AutomapReveal::All => AutomapReveal::Explored,
+                                AutomapReveal::Explored => AutomapReveal::All,
+                            }
+                        }
+                        "sector_colors" => renderer.toggle_sector_colors(),
+                        "partitions" => renderer.toggle_partitions(),
+                        "location_path" => renderer.toggle_location_path(),
+                        "anti_alias" => renderer.toggle_anti_alias(),
+                        "bsp_index_order" => renderer.toggle_bsp_index_order(),
+                        "reveal_secrets" => renderer.toggle_reveal_secrets(),
+                        "theme" => renderer.next_theme(),
+                        "grid_labels" => renderer.toggle_grid_labels(),
+                        "key_locator" => self.key_locator = !self.key_locator,
+                        "mark" => self.add_mark(current_level, player.x, player.y),
+                        "clear_marks" => self.clear_marks(current_level),
+                        "view_3d" => self.view_3d = !self.view_3d,
+                        "wireframe" => renderer.toggle_wireframe(),
+                        "bsp_subtree" => match self.bsp_subtree {
+                            None => self.bsp_subtree = Some(level.root_index()),
+                            Some(_) => self.bsp_subtree = None,
+                        },
+                        "subtree_up" => {
+                            if let Some(node_index) = self.bsp_subtree {
+                                self.bsp_subtree =
+                                    Some(level.node_parent(node_index).unwrap_or(node_index));
+                            }
+                        }
+                        "bsp_shallower" => self.step_bsp_depth(-1),
+                        "bsp_deeper" => self.step_bsp_depth(1),
+                        "bsp_off" => self.bsp_render = None,
+                        "textures" | "flats" | "lumps" => {
+                            // The same key again closes the browser
+                            self.browser = match (self.browser, action.unwrap_or("")) {
+                                (Some(Browser::Textures(_)), "textures")
+                                | (Some(Browser::Flats(_)), "flats")
+                                | (Some(Browser::Hex { .. }), "lumps") => None,
+                                (_, "textures") => Some(Browser::Textures(0)),
+                                (_, "flats") => Some(Browser::Flats(0)),
+                                _ => Some(Browser::Hex { lump: 0, row: 0 }),
+                            }
+                        }
+                        "subtree_left" | "subtree_right" => {
+                            if let Some(node_index) = self.bsp_subtree {
+                                let left = action == Some("subtree_left");
+                                self.bsp_subtree =
+                                    Some(level.node_child(node_index, left).unwrap_or(node_index));
+                            }
+                        }
+                        "monsters" => self.toggle_thing_category(ThingCategory::Monster),
+                        "items" => self.toggle_thing_category(ThingCategory::Item),
+                        "keys" => self.toggle_thing_category(ThingCategory::Key),
+                        "decorations" => self.toggle_thing_category(ThingCategory::Decoration),
+                        "player_starts" => self.toggle_thing_category(ThingCategory::PlayerStart),
+                        _ => {}
                     },
                     _ => {}
                 }
             }
-            self.handle_input(&mut player, &mut event_pump);
+            if let Some(frame) = self.camera_path.playback(run_start.elapsed().as_secs_f32()) {
+                player.x = frame.x;
+                player.y = frame.y;
+                player.angle = frame.angle;
+                player.vx = 0.;
+                player.vy = 0.;
+            } else if !self.quit_pending && self.browser.is_none() && !self.jukebox_open {
+                self.handle_input(&mut player, &level, tics);
+            }
+            self.visit(&player, &level);
+            if !self.pressed_keys.is_empty() {
+                self.last_input = Instant::now();
+                self.attract_active = None;
+            }
+            if let Some(next_level) = self.attract_step(Instant::now(), current_level, level_count)
+            {
+                current_level = next_level;
+                level = Level::new(wad.level(current_level));
+                lighting = Lighting::new(&level);
+                self.start_level(
+                    wad.level_name(current_level),
+                    variant,
+                    &level,
+                    &mut player,
+                    &mut renderer,
+                    &mut canvas,
+                );
+            }
+            if self.attract_active.is_some() {
+                player.angle = normalize_angle(player.angle + 0.005);
+            }
+
+            let lights_changed = lighting.update(tic);
+            // Animated views change every frame without the player moving
+            if lights_changed || self.view_3d || self.key_locator || self.browser.is_some() {
+                renderer.invalidate();
+            }
+            if self.overlays(current_level, &jukebox) != overlays {
+                renderer.invalidate();
+            }
+            if renderer.frame_unchanged(&player) {
+                Self::wait_for_frame(loop_start);
+                continue;
+            }
+            canvas.set_draw_color(Color::BLACK);
+            canvas.clear();
 
             // DRAW SOMETHING
-            renderer.draw(self.bsp_render, &player, &level, &mut canvas);
+            if let GameState::EpisodeSelect(selected) = self.state {
+                let mut menu = String::from("Choose an episode\n");
+                for (idx, &episode) in episodes.iter().enumerate() {
+                    let marker = if idx == selected { '>' } else { ' ' };
+                    let name = level_names::episode_name(episode).unwrap_or("");
+                    menu.push_str(&format!("\n{} Episode {}: {}", marker, episode, name));
+                }
+                text::draw_text_box(
+                    &mut canvas,
+                    &menu,
+                    (
+                        (self.config.width * self.config.multiplier / 2) as i32,
+                        (self.config.height * self.config.multiplier / 2) as i32,
+                    ),
+                    self.config.multiplier,
+                    Color::WHITE,
+                    Color::RGB(64, 0, 0),
+                );
+            } else if let Some(Browser::Textures(index)) = self.browser {
+                if texture_preview.as_ref().map(|(shown, _)| *shown) != Some(index) {
+                    texture_preview = wad.texture_picture(index).map(|picture| (index, picture));
+                }
+                if let (Some((_, picture)), Some(palette)) = (&texture_preview, &palette) {
+                    let def = &wad.texture_defs[index];
+                    let label = format!(
+                        "{} ({}x{}) {}/{}",
+                        def.name,
+                        def.width,
+                        def.height,
+                        index + 1,
+                        wad.texture_defs.len()
+                    );
+                    renderer.draw_picture(
+                        picture,
+                        palette,
+                        &label,
+                        &mut canvas,
+                        &mut frame_texture,
+                    );
+                }
+            } else if let Some(Browser::Flats(index)) = self.browser {
+                // Animated flats play through their frames, 8 tics each like the game
+                let frames = wad.flat_animation(index);
+                let frame = frames[(tic / 8) as usize % frames.len()];
+                if let (Some(flat), Some(palette)) = (wad.flats.get(frame), &palette) {
+                    let label = format!("{} {}/{}", flat.name, index + 1, wad.flats.len());
+                    renderer.draw_flat_preview(flat, palette, &mut canvas, &mut frame_texture);
+                    text::draw_text(
+                        &mut canvas,
+                        &label,
+                        12,
+                        12,
+                        self.config.multiplier / 2,
+                        Color::WHITE,
+                    );
+                }
+            } else if let Some(Browser::Hex { lump, row }) = self.browser {
+                let entry = &wad.directory[lump];
+                let bytes = wad.lump_data(lump).unwrap_or(&[]);
+                let mut dump = format!(
+                    "{} ({} bytes) {}/{}\n\n",
+                    entry.name,
+                    entry.size,
+                    lump + 1,
+                    wad.directory.len()
+                );
+                for offset in (row * 16..bytes.len()).step_by(16).take(Self::HEX_PAGE) {
+                    dump.push_str(&text::hex_dump_line(bytes, offset));
+                    dump.push('\n');
+                }
+                text::draw_text(
+                    &mut canvas,
+                    &dump,
+                    12,
+                    12,
+                    self.config.multiplier / 2,
+                    Color::WHITE,
+                );
+            } else if self.view_3d {
+                renderer.draw_3d(&player, &level, &mut canvas, &mut frame_texture);
+            } else {
+                let options = AutomapOptions {
+                    bsp_render: self.bsp_render,
+                    bsp_subtree: self.bsp_subtree,
+                    thing_filter: &self.thing_filter,
+                    key_locator: self.key_locator,
+                    marks: self.level_marks(current_level),
+                    tic,
+                    reveal: self.automap_reveal,
+                    visited: &self.visited,
+                };
+                renderer.draw(
+                    &options,
+                    &player,
+                    &level,
+                    &lighting,
+                    &mut canvas,
+                    &mut frame_texture,
+                );
+                if let Some(anchor) = self.measure_anchor {
+                    renderer.draw_measurement(anchor, self.measure_end, &mut canvas);
+                }
+                if let Some(picked) = self.picked_linedef {
+                    renderer.draw_picked_linedef(&level, picked, &mut canvas);
+                }
+                if let Some(depth) = self.bsp_render {
+                    text::draw_text(
+                        &mut canvas,
+                        &format!("BSP depth: {}", depth),
+                        12,
+                        ((self.config.height - 12) * self.config.multiplier) as i32,
+                        self.config.multiplier / 2,
+                        Color::CYAN,
+                    );
+                }
+                let visited = format!("{:.0}% of subsectors visited", self.visited_percent(&level));
+                let (width, _) = text::text_size(&visited, self.config.multiplier / 2);
+                text::draw_text(
+                    &mut canvas,
+                    &visited,
+                    ((self.config.width - 4) * self.config.multiplier - width) as i32,
+                    ((self.config.height - 12) * self.config.multiplier) as i32,
+                    self.config.multiplier / 2,
+                    Color::GRAY,
+                );
+            }
+            if self.jukebox_open {
+                let status = match jukebox.now_playing() {
+                    Some(name) => format!("Playing {}", name),
+                    None => "Stopped".to_string(),
+                };
+                text::draw_text_box(
+                    &mut canvas,
+                    &format!("Jukebox\n\n{}\n\n{}", jukebox.listing(), status),
+                    (
+                        (self.config.width * self.config.multiplier / 2) as i32,
+                        (self.config.height * self.config.multiplier / 2) as i32,
+                    ),
+                    self.config.multiplier / 2,
+                    Color::WHITE,
+                    Color::RGB(0, 0, 64),
+                );
+            }
+            if self.quit_pending {
+                text::draw_text_box(
+                    &mut canvas,
+                    &quit_prompt,
+                    (
+                        (self.config.width * self.config.multiplier / 2) as i32,
+                        (self.config.height * self.config.multiplier / 2) as i32,
+                    ),
+                    self.config.multiplier,
+                    Color::WHITE,
+                    Color::RGB(64, 0, 0),
+                );
+            }
 
             canvas.present();
-            let cycle_time = Instant::now() - loop_start;
-            let one_sixieth_second = Duration::new(0, 1_000_000_000u32 / 60);
-            if one_sixieth_second > cycle_time {
-                let remaining = one_sixieth_second - cycle_time;
-                ::std::thread::sleep(remaining);
-            }
+            Self::wait_for_frame(loop_start);
+        }
+        renderer.store_settings(&mut self.config);
+        // Losing the settings isn't worth failing the quit over, but say so
+        if let Err(error) = self.config.save(Config::PATH) {
+            eprintln!("Couldn't save {}: {}", Config::PATH, error);
         }
     }
 
-    fn get_scancodes(old: &HashSet<Scancode>, new: &HashSet<Scancode>) -> HashSet<Scancode> {
-        new - old
+    fn wait_for_frame(loop_start: Instant) {
+        let cycle_time = Instant::now() - loop_start;
+        let one_sixieth_second = Duration::new(0, 1_000_000_000u32 / 60);
+        if one_sixieth_second > cycle_time {
+            let remaining = one_sixieth_second - cycle_time;
+            ::std::thread::sleep(remaining);
+        }
+    }
+
+    // Keys held now that weren't last frame, plus any pressed and released within the frame
+    fn update_keys(&mut self, held: HashSet<Scancode>, taps: impl Iterator<Item = Scancode>) {
+        self.newly_pressed = &held - &self.pressed_keys;
+        self.newly_pressed.extend(taps);
+        self.pressed_keys = held;
+    }
+
+    // For actions that fire once per press, where pressed_keys is for held ones
+    pub fn just_pressed(&self, scancode: Scancode) -> bool {
+        self.newly_pressed.contains(&scancode)
     }
 
-    fn handle_input(&mut self, player: &mut Player, event_pump: &mut EventPump) {
-        let scancodes: HashSet<Scancode> =
-            event_pump.keyboard_state().pressed_scancodes().collect();
+    // Whether the key bound to an action is down, or went down this frame
+    fn held(&self, action: &str) -> bool {
+        self.keys
+            .held
+            .get(action)
+            .is_some_and(|scancode| self.pressed_keys.contains(scancode))
+    }
+
+    fn tapped(&self, action: &str) -> bool {
+        self.keys
+            .held
+            .get(action)
+            .is_some_and(|&scancode| self.just_pressed(scancode))
+    }
 
-        let newly_pressed: HashSet<Scancode> =
-            Interface::get_scancodes(&self.pressed_keys, &scancodes);
-        self.pressed_keys = scancodes;
+    // Whole tics in the time since the last frame, keeping the remainder for the next one
+    fn take_tics(accumulator: &mut Duration, elapsed: Duration) -> u32 {
+        let tic = Duration::from_secs(1) / Self::TICRATE;
+        // A long stall, like dragging the window, shouldn't fling the player on afterwards
+        *accumulator = cmp::min(*accumulator + elapsed, tic * Self::TICRATE);
+        let tics = (accumulator.as_nanos() / tic.as_nanos()) as u32;
+        *accumulator -= tic * tics;
+        tics
+    }
+
+    fn move_player(&self, player: &mut Player, level: &Level) {
+        let speed = self.config.move_speed;
+        if self.held("forward") {
+            player.thrust(player.angle, speed);
+        } else if self.held("backward") {
+            player.thrust(player.angle, -speed);
+        }
+        if self.held("turn_left") {
+            if self.held("strafe") {
+                player.thrust(player.angle + consts::FRAC_PI_2, speed);
+            } else {
+                player.angle = normalize_angle(player.angle + self.config.turn_speed);
+            }
+        } else if self.held("turn_right") {
+            if self.held("strafe") {
+                player.thrust(player.angle - consts::FRAC_PI_2, speed);
+            } else {
+                player.angle = normalize_angle(player.angle - self.config.turn_speed);
+            }
+        }
+        player.tic(level);
+        if self.held("look_up") {
+            player.pitch = f32::min(player.pitch + 0.02, Renderer::MAX_PITCH);
+        } else if self.held("look_down") {
+            player.pitch = f32::max(player.pitch - 0.02, -Renderer::MAX_PITCH);
+        }
+    }
 
+    // Presses are handled once a frame, but movement runs once for each tic that has passed
+    // since the last frame, so speeds are per tic whatever the frame rate
+    fn handle_input(&mut self, player: &mut Player, level: &Level, tics: u32) {
         match self.state {
+            GameState::EpisodeSelect(_) => {}
             GameState::_TitleScreen => {
-                if newly_pressed.contains(&Scancode::Space) {
+                if self.just_pressed(Scancode::Space) {
                     self.state = GameState::Playing
                 }
             }
             GameState::Playing | GameState::Viewing => {
-                if newly_pressed.contains(&Scancode::P) {
+                if self.tapped("pause") {
                     self.state = GameState::Paused;
                 }
-
-                if self.pressed_keys.contains(&Scancode::Up) {
-                    player.x += f32::cos(player.angle) * 3.;
-                    player.y += f32::sin(player.angle) * 3.;
-                } else if self.pressed_keys.contains(&Scancode::Down) {
-                    player.x -= f32::cos(player.angle) * 3.;
-                    player.y -= f32::sin(player.angle) * 3.;
-                }
-                if self.pressed_keys.contains(&Scancode::Left) {
-                    if self.pressed_keys.contains(&Scancode::LGui) {
-                        player.x += f32::cos(player.angle + consts::FRAC_PI_2) * 3.;
-                        player.y += f32::sin(player.angle + consts::FRAC_PI_2) * 3.;
-                    } else {
-                        player.angle += 0.05;
-                        if player.angle > 2.0 * consts::PI {
-                            player.angle -= 2.0 * consts::PI;
-                        }
-                    }
-                } else if self.pressed_keys.contains(&Scancode::Right) {
-                    if self.pressed_keys.contains(&Scancode::LGui) {
-                        player.x += f32::cos(player.angle - consts::FRAC_PI_2) * 3.;
-                        player.y += f32::sin(player.angle - consts::FRAC_PI_2) * 3.;
-                    } else {
-                        player.angle -= 0.05;
-                        if player.angle < 0.0 {
-                            player.angle += 2.0 * consts::PI;
-                        }
-                    }
+                for _ in 0..tics {
+                    self.move_player(player, level);
                 }
             }
             GameState::Paused => {
-                if newly_pressed.contains(&Scancode::P) {
+                if self.tapped("pause") {
                     self.state = GameState::Playing;
                 }
             }
             GameState::_GameOver => {
-                if newly_pressed.contains(&Scancode::Space) {
+                if self.just_pressed(Scancode::Space) {
                     // TODO: Reset
                     self.state = GameState::Playing
                 }
@@ -226,3 +1103,321 @@ impl Interface {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{build_level, build_minimal_wad, level_data, player_at, MiniLevel},
+        wad::TextureDef,
+    };
+
+    #[test]
+    fn window_title_names_the_map_and_game() {
+        assert_eq!(
+            Interface::window_title("E1M1", GameVariant::Shareware),
+            "Rune - E1M1: Hangar (Shareware)"
+        );
+    }
+
+    #[test]
+    fn overlays_change_with_what_the_interface_draws() {
+        let mut interface = Interface::new(Config::default());
+        let mut jukebox = Jukebox::new(vec!["D_E1M1".to_string(), "D_E1M2".to_string()]);
+        let before = interface.overlays(0, &jukebox);
+        // The jukebox only counts while it's on screen
+        jukebox.next();
+        assert!(interface.overlays(0, &jukebox) == before);
+        interface.jukebox_open = true;
+        assert!(interface.overlays(0, &jukebox) != before);
+        interface.jukebox_open = false;
+        interface.add_mark(0, 10., 20.);
+        assert!(interface.overlays(0, &jukebox) != before);
+        assert!(interface.overlays(1, &jukebox) != before);
+    }
+
+    #[test]
+    fn attract_mode_advances_and_wraps_after_interval() {
+        let mut interface = Interface::new(Config::default());
+        let start = interface.last_input;
+        let after = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(interface.attract_step(after(119), 2, 3), None);
+        // Going idle only starts attract mode; the first level change is an interval later
+        assert_eq!(interface.attract_step(after(120), 2, 3), None);
+        assert_eq!(interface.attract_step(after(149), 2, 3), None);
+        assert_eq!(interface.attract_step(after(150), 2, 3), Some(0));
+        assert_eq!(interface.attract_step(after(179), 0, 3), None);
+        assert_eq!(interface.attract_step(after(180), 0, 3), Some(1));
+    }
+
+    #[test]
+    fn quit_confirmation_transitions() {
+        // Asking first is the default
+        let mut interface = Interface::new(Config::default());
+        interface.keys.bind("quit", Keycode::Q, Scancode::Q);
+        assert!(!interface.quit_key(Keycode::Escape));
+        assert!(interface.quit_pending);
+        assert!(!interface.quit_key(Keycode::N));
+        assert!(!interface.quit_pending);
+        assert!(!interface.quit_key(Keycode::Q));
+        assert!(interface.quit_key(Keycode::Y));
+
+        interface.confirm_quit = false;
+        assert!(!interface.quit_key(Keycode::Y));
+        assert!(interface.quit_key(Keycode::Escape));
+    }
+
+    #[test]
+    fn toggling_monsters_changes_which_things_pass() {
+        // Zombieman, blue keycard, player 1 start, stimpack
+        let things = [3004, 5, 1, 2011];
+        let mut interface = Interface::new(Config::default());
+        let shown = |interface: &Interface| -> Vec<i16> {
+            things
+                .into_iter()
+                .filter(|&t| interface.thing_filter.contains(&ThingCategory::of(t)))
+                .collect()
+        };
+        assert_eq!(shown(&interface), [5, 1]);
+        interface.toggle_thing_category(ThingCategory::Monster);
+        assert_eq!(shown(&interface), [3004, 5, 1]);
+        interface.toggle_thing_category(ThingCategory::Monster);
+        assert_eq!(shown(&interface), [5, 1]);
+    }
+
+    #[test]
+    fn measure_distance_between_world_points() {
+        assert_eq!(Interface::measure((10., 20.), (40., 60.)), (30., 40., 50.));
+        assert_eq!(
+            Interface::measure((40., 60.), (10., 20.)),
+            (-30., -40., 50.)
+        );
+        assert_eq!(Interface::measure((5., 5.), (5., 5.)), (0., 0., 0.));
+    }
+
+    #[test]
+    fn third_measuring_click_clears() {
+        let mut interface = Interface::new(Config::default());
+        interface.measure_click((0., 0.));
+        assert_eq!(
+            (interface.measure_anchor, interface.measure_end),
+            (Some((0., 0.)), None)
+        );
+        interface.measure_click((3., 4.));
+        assert_eq!(interface.measure_end, Some((3., 4.)));
+        interface.measure_click((9., 9.));
+        assert_eq!(
+            (interface.measure_anchor, interface.measure_end),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn bsp_depth_steps_and_clamps_at_zero() {
+        let mut interface = Interface::new(Config::default());
+        assert_eq!(interface.bsp_render, None);
+        // The first step of either kind just turns the overlay on
+        interface.step_bsp_depth(-1);
+        assert_eq!(interface.bsp_render, Some(0));
+        interface.step_bsp_depth(-1);
+        assert_eq!(interface.bsp_render, Some(0));
+        interface.step_bsp_depth(1);
+        interface.step_bsp_depth(1);
+        assert_eq!(interface.bsp_render, Some(2));
+        interface.step_bsp_depth(-1);
+        assert_eq!(interface.bsp_render, Some(1));
+    }
+
+    #[test]
+    fn marks_are_kept_per_level_and_cleared() {
+        let mut interface = Interface::new(Config::default());
+        interface.add_mark(0, 10., 20.);
+        interface.add_mark(0, 30., 40.);
+        interface.add_mark(2, 50., 60.);
+        assert_eq!(interface.level_marks(0), [(10., 20.), (30., 40.)]);
+        assert_eq!(interface.level_marks(2), [(50., 60.)]);
+        assert!(interface.level_marks(1).is_empty());
+
+        interface.clear_marks(0);
+        assert!(interface.level_marks(0).is_empty());
+        assert_eq!(interface.level_marks(2), [(50., 60.)]);
+    }
+
+    #[test]
+    fn texture_browser_wraps_at_both_ends() {
+        let mut wad =
+            WadFile::from_bytes(build_minimal_wad(&MiniLevel::one_room("MAP01", 128))).unwrap();
+        wad.texture_defs = ["STARTAN3", "BROWN1", "DOOR3"]
+            .iter()
+            .map(|name| TextureDef {
+                name: name.to_string(),
+                width: 64,
+                height: 128,
+                patches: Vec::new(),
+            })
+            .collect();
+        let step = |index, forward| match Browser::Textures(index).step(&wad, forward) {
+            Browser::Textures(index) => index,
+            other => panic!("left the texture browser for {:?}", other),
+        };
+        assert_eq!(step(0, true), 1);
+        assert_eq!(step(2, true), 0);
+        assert_eq!(step(0, false), 2);
+        assert_eq!(step(1, false), 0);
+        // Nothing to browse stays on the first entry
+        assert_eq!(Interface::cycle_index(0, 0, false), 0);
+    }
+
+    #[test]
+    fn player_start_cycling_skips_missing_slots() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 3, 128);
+        mini.things.push((320, 64, 3));
+        let starts = build_level(&mini).player_starts();
+        assert_eq!(Interface::next_player_start(&starts, 0), Some(2));
+        assert_eq!(Interface::next_player_start(&starts, 2), Some(0));
+        let none = [None; 4];
+        assert_eq!(Interface::next_player_start(&none, 0), None);
+    }
+
+    #[test]
+    fn entering_subsectors_raises_coverage() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 4, 128));
+        let mut interface = Interface::new(Config::default());
+        let mut player = player_at(64., 64., 0.);
+        interface.visit(&player, &level);
+        assert_eq!(interface.visited_percent(&level), 25.);
+        // Staying put doesn't count twice
+        interface.visit(&player, &level);
+        assert_eq!(interface.visited_percent(&level), 25.);
+        assert_eq!(
+            interface.nearest_unvisited(&player, &level),
+            Some((192., 64.))
+        );
+
+        player.x = 300.;
+        interface.visit(&player, &level);
+        assert!(interface.visited.contains(&2));
+        assert_eq!(interface.visited_percent(&level), 50.);
+        assert_eq!(
+            interface.nearest_unvisited(&player, &level),
+            Some((192., 64.))
+        );
+    }
+
+    #[test]
+    fn velocity_decays_to_rest_after_input_stops() {
+        let level = build_level(&MiniLevel::one_room("MAP01", 4096));
+        let mut player = player_at(100., 2048., 0.);
+        for _ in 0..100 {
+            player.thrust(0., 3.);
+            player.tic(&level);
+        }
+        // Thrust and friction balance just under the full speed
+        assert!((player.vx - 3. * Player::FRICTION).abs() < 0.01);
+        assert_eq!(player.vy, 0.);
+
+        let mut tics = 0;
+        let mut last_x = player.x;
+        while player.vx > 0. {
+            player.tic(&level);
+            assert!(player.x > last_x);
+            last_x = player.x;
+            tics += 1;
+        }
+        // 2.72 units a tic times 0.90625^n falls below the 0.0625 stop speed at n = 39
+        assert_eq!(tics, 39);
+        assert_eq!((player.vx, player.vy), (0., 0.));
+    }
+
+    #[test]
+    fn movement_follows_the_bound_keys() {
+        let level = build_level(&MiniLevel::one_room("MAP01", 4096));
+        let mut interface = Interface::new(Config::default());
+        interface.keys.bind("forward", Keycode::W, Scancode::W);
+        let mut player = player_at(100., 2048., 0.);
+
+        // Up isn't bound to anything any more
+        interface.update_keys(HashSet::from([Scancode::Up]), std::iter::empty());
+        interface.handle_input(&mut player, &level, 1);
+        assert_eq!(player.x, 100.);
+
+        interface.update_keys(HashSet::from([Scancode::W]), std::iter::empty());
+        interface.handle_input(&mut player, &level, 1);
+        assert!(player.x > 100.);
+    }
+
+    #[test]
+    fn frames_move_the_player_by_whole_tics() {
+        let tic = Duration::from_secs(1) / Interface::TICRATE;
+        let mut unused = Duration::ZERO;
+        // Two 60 Hz frames make one tic between them, and a 20 Hz frame just under two
+        let frame = Duration::from_secs(1) / 60;
+        assert_eq!(Interface::take_tics(&mut unused, frame), 0);
+        assert_eq!(Interface::take_tics(&mut unused, frame), 1);
+        assert_eq!(unused, frame * 2 - tic);
+        assert_eq!(
+            Interface::take_tics(&mut unused, Duration::from_millis(50)),
+            1
+        );
+        assert_eq!(
+            Interface::take_tics(&mut unused, Duration::from_secs(10)),
+            35
+        );
+
+        // Speeds are per tic, so two tics at once go as far as two frames of one
+        let level = build_level(&MiniLevel::one_room("MAP01", 4096));
+        let mut interface = Interface::new(Config::default());
+        interface.keys.bind("forward", Keycode::Up, Scancode::Up);
+        interface.update_keys(HashSet::from([Scancode::Up]), std::iter::empty());
+        let (mut once, mut twice) = (player_at(100., 2048., 0.), player_at(100., 2048., 0.));
+        interface.handle_input(&mut once, &level, 2);
+        interface.handle_input(&mut twice, &level, 1);
+        interface.handle_input(&mut twice, &level, 1);
+        assert_eq!(once.x, twice.x);
+        interface.handle_input(&mut once, &level, 0);
+        assert_eq!(once.x, twice.x);
+    }
+
+    #[test]
+    fn running_into_a_wall_stops_the_player() {
+        let level = build_level(&MiniLevel::one_room("MAP01", 128));
+        let mut player = player_at(126., 64., 0.);
+        player.thrust(0., 30.);
+        player.tic(&level);
+        assert_eq!((player.x, player.y), (126., 64.));
+        assert_eq!((player.vx, player.vy), (0., 0.));
+    }
+
+    #[test]
+    fn held_key_is_just_pressed_once_per_press() {
+        let mut interface = Interface::new(Config::default());
+        let tab = Scancode::Tab;
+        let held = || HashSet::from([tab]);
+        let mut frames = Vec::new();
+        for keys in [held(), held(), held(), HashSet::new(), held(), held()] {
+            interface.update_keys(keys, std::iter::empty());
+            frames.push(interface.just_pressed(tab));
+        }
+        assert_eq!(frames, [true, false, false, false, true, false]);
+
+        // Pressed and released between two frames still counts, once
+        interface.update_keys(HashSet::new(), std::iter::once(tab));
+        assert!(interface.just_pressed(tab));
+        interface.update_keys(HashSet::new(), std::iter::empty());
+        assert!(!interface.just_pressed(tab));
+    }
+
+    #[test]
+    fn blocked_moves_slide_along_a_slanted_wall() {
+        // The east wall leans in, running from (128, 0) up to (64, 128)
+        let mut data = level_data(&MiniLevel::one_room("MAP01", 128));
+        data.vertexes[3].x = 64;
+        let level = Level::new(&data);
+        let mut player = player_at(80., 64., 0.);
+        player.vx = 20.;
+        player.tic(&level);
+        // Moving straight east would cross the wall at x 96, and neither axis alone helps
+        assert!((player.x - 84.).abs() < 0.01 && (player.y - 56.).abs() < 0.01);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use sdl2::{
+    mixer::{self, Music},
+    AudioSubsystem, Sdl,
+};
+
+use crate::{mus, wad::WadFile};
+
+// Playlist for the music browser. MusicPlayer does the playing, following whatever the
+// playlist says is on.
+#[derive(Clone, Debug)]
+pub struct Jukebox {
+    pub tracks: Vec<String>,
+    pub selected: usize,
+    pub playing: Option<usize>,
+}
+
+impl Jukebox {
+    pub fn new(tracks: Vec<String>) -> Self {
+        Jukebox {
+            tracks,
+            selected: 0,
+            playing: None,
+        }
+    }
+
+    // Moving the selection while a track plays switches to the new track, like skipping
+    pub fn next(&mut self) {
+        if self.tracks.is_empty() {
+            return;
+        }
+        self.selected = (self.selected + 1) % self.tracks.len();
+        if self.playing.is_some() {
+            self.play();
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if self.tracks.is_empty() {
+            return;
+        }
+        self.selected = (self.selected + self.tracks.len() - 1) % self.tracks.len();
+        if self.playing.is_some() {
+            self.play();
+        }
+    }
+
+    pub fn play(&mut self) {
+        if self.selected < self.tracks.len() {
+            self.playing = Some(self.selected);
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = None;
+    }
+
+    pub fn now_playing(&self) -> Option<&str> {
+        self.playing.map(|index| self.tracks[index].as_str())
+    }
+
+    // One line per track, the selection marked with > and the playing track with +
+    pub fn listing(&self) -> String {
+        self.tracks
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let cursor = if index == self.selected { '>' } else { ' ' };
+                let playing = if self.playing == Some(index) {
+                    '+'
+                } else {
+                    ' '
+                };
+                format!("{}{} {}", cursor, playing, name)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// Plays the jukebox's tracks through SDL_mixer as MIDI, looping each until it changes.
+// SDL_mixer reads the MIDI in place while it plays, so each track is converted once and
+// kept for the rest of the run.
+pub struct MusicPlayer {
+    _audio: AudioSubsystem,
+    loaded: HashMap<String, Music<'static>>,
+    current: Option<String>,
+}
+
+impl MusicPlayer {
+    pub fn open(sdl_context: &Sdl) -> Result<Self, String> {
+        let audio = sdl_context.audio()?;
+        mixer::open_audio(
+            mixer::DEFAULT_FREQUENCY,
+            mixer::DEFAULT_FORMAT,
+            mixer::DEFAULT_CHANNELS,
+            1024,
+        )?;
+        Ok(MusicPlayer {
+            _audio: audio,
+            loaded: HashMap::new(),
+            current: None,
+        })
+    }
+
+    // Starts, switches or stops the music to match the jukebox. A track that won't play is
+    // reported once and not retried until the jukebox moves on.
+    pub fn follow(&mut self, jukebox: &Jukebox, wad: &WadFile) -> Result<(), String> {
+        let wanted = jukebox.now_playing();
+        if wanted == self.current.as_deref() {
+            return Ok(());
+        }
+        self.current = wanted.map(String::from);
+        let name = match wanted {
+            Some(name) => name,
+            None => {
+                Music::halt();
+                return Ok(());
+            }
+        };
+        if !self.loaded.contains_key(name) {
+            let lump = wad
+                .lump_bytes(name)
+                .ok_or(format!("{} has no data", name))?;
+            let midi = mus::to_midi(lump).ok_or(format!("{} isn't MUS", name))?;
+            let music = Music::from_static_bytes(Box::leak(midi.into_boxed_slice()))?;
+            self.loaded.insert(name.to_string(), music);
+        }
+        self.loaded[name].play(-1)
+    }
+}
+
+impl Drop for MusicPlayer {
+    fn drop(&mut self) {
+        Music::halt();
+        self.loaded.clear();
+        mixer::close_audio();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jukebox() -> Jukebox {
+        Jukebox::new(["D_E1M1", "D_E1M2", "D_INTER"].map(String::from).to_vec())
+    }
+
+    #[test]
+    fn selection_wraps_and_skips_while_playing() {
+        let mut jukebox = jukebox();
+        jukebox.previous();
+        assert_eq!((jukebox.selected, jukebox.now_playing()), (2, None));
+        jukebox.next();
+        assert_eq!(jukebox.selected, 0);
+
+        jukebox.play();
+        assert_eq!(jukebox.now_playing(), Some("D_E1M1"));
+        jukebox.next();
+        assert_eq!(jukebox.now_playing(), Some("D_E1M2"));
+        assert_eq!(jukebox.listing(), "   D_E1M1\n>+ D_E1M2\n   D_INTER");
+
+        jukebox.stop();
+        assert_eq!(jukebox.now_playing(), None);
+        jukebox.next();
+        assert_eq!((jukebox.selected, jukebox.now_playing()), (2, None));
+    }
+
+    #[test]
+    fn empty_playlist_never_plays() {
+        let mut jukebox = Jukebox::new(Vec::new());
+        jukebox.next();
+        jukebox.previous();
+        jukebox.play();
+        assert_eq!((jukebox.selected, jukebox.now_playing()), (0, None));
+    }
+}
@@ -1,8 +1,18 @@
 // Sector > Sidedef > Linedef > Segment > Subsector >> BSP
 
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    f32::consts,
+    fmt::Write,
+    rc::Rc,
+};
 
-use crate::wad::{BBox, ChildIdx, LevelData, Sector, Thing, Vertex};
+use crate::{
+    interface::Player,
+    math::{self, normalize_angle},
+    thing_info::{KeyColor, ThingCategory},
+    wad::{BBox, Blockmap, ChildIdx, LevelData, Sector, Skill, Thing, Vertex},
+};
 
 #[derive(Clone, Debug)]
 pub struct Sidedef {
@@ -34,6 +44,13 @@ impl Sidedef {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineClass {
+    OneSided,
+    TwoSided,
+    NoSides, // Broken map data, nothing references this line
+}
+
 #[derive(Clone, Debug)]
 pub struct Linedef {
     pub start_vert: usize,
@@ -46,6 +63,12 @@ pub struct Linedef {
 }
 
 impl Linedef {
+    const BLOCK_ALL: i16 = 0x0001;
+    const BLOCK_MONSTERS: i16 = 0x0002;
+    const SECRET: i16 = 0x0020;
+    const NOT_ON_MAP: i16 = 0x0080;
+    const ALREADY_ON_MAP: i16 = 0x0100;
+
     pub fn new(
         start_vert: usize,
         end_vert: usize,
@@ -65,6 +88,89 @@ impl Linedef {
             left_sidedef,
         }
     }
+
+    // One-sided lines stop everything; the flags make two-sided ones block as well
+    pub fn blocks_player(&self) -> bool {
+        self.classify() != LineClass::TwoSided || self.flags & Self::BLOCK_ALL != 0
+    }
+
+    pub fn blocks_monsters(&self) -> bool {
+        self.blocks_player() || self.flags & Self::BLOCK_MONSTERS != 0
+    }
+
+    // Doom's automap draws these as plain walls so they don't give a secret door away
+    pub fn is_secret_on_map(&self) -> bool {
+        self.flags & Self::SECRET != 0
+    }
+
+    pub fn hidden_on_map(&self) -> bool {
+        self.flags & Self::NOT_ON_MAP != 0
+    }
+
+    // Shown on the automap from the start, before the player has seen it
+    pub fn always_on_map(&self) -> bool {
+        self.flags & Self::ALREADY_ON_MAP != 0
+    }
+
+    pub fn classify(&self) -> LineClass {
+        match (&self.right_sidedef, &self.left_sidedef) {
+            (Some(_), Some(_)) => LineClass::TwoSided,
+            (None, None) => LineClass::NoSides,
+            _ => LineClass::OneSided,
+        }
+    }
+
+    // (right, left) sector ids, for two-sided lines only
+    pub fn sector_pair(&self) -> Option<(usize, usize)> {
+        match self.side_sectors() {
+            (Some(right), Some(left)) => Some((right, left)),
+            _ => None,
+        }
+    }
+
+    pub fn side_sectors(&self) -> (Option<usize>, Option<usize>) {
+        (
+            self.right_sidedef.as_ref().map(|side| side.sector.id),
+            self.left_sidedef.as_ref().map(|side| side.sector.id),
+        )
+    }
+
+    // Both sides in the same sector, used for fake floors and invisible bridges
+    pub fn is_self_referencing(&self) -> bool {
+        matches!(self.sector_pair(), Some((right, left)) if right == left)
+    }
+
+    pub fn length(&self, level: &Level) -> f32 {
+        let v1 = level.vertexes[self.start_vert];
+        let v2 = level.vertexes[self.end_vert];
+        let dx = f32::from(v2.x) - f32::from(v1.x);
+        let dy = f32::from(v2.y) - f32::from(v1.y);
+        dx.hypot(dy)
+    }
+
+    // Unit normal on the right (front) side: the direction rotated 90° clockwise. Zero-length
+    // linedefs have none and give (0, 0).
+    pub fn normal(&self, level: &Level) -> (f32, f32) {
+        let v1 = level.vertexes[self.start_vert];
+        let v2 = level.vertexes[self.end_vert];
+        let dx = f32::from(v2.x) - f32::from(v1.x);
+        let dy = f32::from(v2.y) - f32::from(v1.y);
+        let length = dx.hypot(dy);
+        if length == 0. {
+            return (0., 0.);
+        }
+        (dy / length, -dx / length)
+    }
+
+    // Zero-length linedefs just give back their shared vertex
+    pub fn midpoint(&self, level: &Level) -> (f32, f32) {
+        let v1 = level.vertexes[self.start_vert];
+        let v2 = level.vertexes[self.end_vert];
+        (
+            (f32::from(v1.x) + f32::from(v2.x)) / 2.,
+            (f32::from(v1.y) + f32::from(v2.y)) / 2.,
+        )
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -94,6 +200,23 @@ impl Segment {
             offset,
         }
     }
+
+    // The side of the linedef the seg faces: its right side when the seg runs the same way
+    pub fn front_sidedef(&self) -> Option<Rc<Sidedef>> {
+        if self.dir_like_linedef {
+            self.linedef.right_sidedef.clone()
+        } else {
+            self.linedef.left_sidedef.clone()
+        }
+    }
+
+    pub fn back_sidedef(&self) -> Option<Rc<Sidedef>> {
+        if self.dir_like_linedef {
+            self.linedef.left_sidedef.clone()
+        } else {
+            self.linedef.right_sidedef.clone()
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -102,6 +225,14 @@ pub struct SubSector {
     pub segments: Vec<Rc<Segment>>,
 }
 
+impl SubSector {
+    // Every seg in a subsector faces into the same sector, so the first one will do
+    pub fn sector_id(&self) -> Option<usize> {
+        let sidedef = self.segments.first()?.front_sidedef()?;
+        Some(sidedef.sector.id)
+    }
+}
+
 #[derive(Debug)]
 pub enum Child {
     NODE(Rc<Node>),
@@ -118,11 +249,13 @@ pub struct Node {
     pub left_bbox: BBox,
     pub right_child: Option<Child>,
     pub left_child: Option<Child>,
+    pub id: i16,
 }
 
 impl Node {
+    // Right is the front of the partition line, left is behind it
     pub fn find_partial(&self, x: i16, y: i16, levels: u32) -> &Child {
-        let child = if !self.is_point_behind(x, y) {
+        let child = if self.is_point_behind(x, y) {
             self.left_child.as_ref().unwrap()
         } else {
             self.right_child.as_ref().unwrap()
@@ -135,14 +268,14 @@ impl Node {
             Child::SUBSECTOR(_) => child,
         }
     }
-    pub fn _find(&self, x: i16, y: i16) -> &SubSector {
+    pub fn find(&self, x: i16, y: i16) -> &SubSector {
         let child = if self.is_point_behind(x, y) {
             self.left_child.as_ref().unwrap()
         } else {
             self.right_child.as_ref().unwrap()
         };
         match child {
-            Child::NODE(n) => n._find(x, y),
+            Child::NODE(n) => n.find(x, y),
             Child::SUBSECTOR(s) => &s,
         }
     }
@@ -166,10 +299,93 @@ impl Node {
         let x2 = self.partition_x + self.delta_x;
         let y2 = self.partition_y + self.delta_y;
         return (x2 - self.partition_x) as i32 * (y - self.partition_y) as i32
-            > (y2 - self.partition_y) as i32 * (x - self.partition_x) as i32;
+            >= (y2 - self.partition_y) as i32 * (x - self.partition_x) as i32;
     }
 }
 
+// Whether any of the bbox is within fov radians centred on where the player faces
+pub fn bbox_in_view(bbox: &BBox, player: &Player, fov: f32) -> bool {
+    let (left, right) = (f32::from(bbox.left), f32::from(bbox.left + bbox.width));
+    let (bottom, top) = (f32::from(bbox.top - bbox.height), f32::from(bbox.top));
+    if (left..=right).contains(&player.x) && (bottom..=top).contains(&player.y) {
+        return true;
+    }
+    // From outside, the box spans less than half a turn. Corner angles are relative to the
+    // view direction in (-π, π], and a span over π means the box is behind, straddling ±π.
+    let angles: Vec<f32> = [(left, bottom), (left, top), (right, bottom), (right, top)]
+        .iter()
+        .map(|&(x, y)| {
+            let angle = normalize_angle((y - player.y).atan2(x - player.x) - player.angle);
+            if angle > consts::PI {
+                angle - 2. * consts::PI
+            } else {
+                angle
+            }
+        })
+        .collect();
+    let min = angles.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = angles.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let half_fov = fov / 2.;
+    if max - min > consts::PI {
+        max <= half_fov || min >= -half_fov
+    } else {
+        min <= half_fov && max >= -half_fov
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThingCounts {
+    pub monsters: usize,
+    pub items: usize,
+    pub keys: usize,
+    pub decorations: usize,
+    pub other: usize,
+}
+
+// Elements compared index by index between two versions of a level
+#[derive(Clone, Debug, Default)]
+pub struct ElementDiff {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub field_changes: Vec<(&'static str, usize)>, // How many changed elements touched each field
+}
+
+// A field's name and whether it differs between two versions of an element
+type FieldCheck<T> = (&'static str, fn(&T, &T) -> bool);
+
+impl ElementDiff {
+    fn compare<T>(old: &[T], new: &[T], fields: &[FieldCheck<T>]) -> Self {
+        let mut diff = ElementDiff {
+            added: new.len().saturating_sub(old.len()),
+            removed: old.len().saturating_sub(new.len()),
+            changed: 0,
+            field_changes: fields.iter().map(|(name, _)| (*name, 0)).collect(),
+        };
+        for (a, b) in old.iter().zip(new.iter()) {
+            let mut changed = false;
+            for (idx, (_, differs)) in fields.iter().enumerate() {
+                if differs(a, b) {
+                    diff.field_changes[idx].1 += 1;
+                    changed = true;
+                }
+            }
+            if changed {
+                diff.changed += 1;
+            }
+        }
+        diff.field_changes.retain(|(_, count)| *count > 0);
+        diff
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LevelDiff {
+    pub vertexes: ElementDiff,
+    pub linedefs: ElementDiff,
+    pub sectors: ElementDiff,
+}
+
 pub struct Level {
     pub vertexes: Vec<Vertex>,
     pub things: Vec<Thing>,
@@ -180,6 +396,9 @@ pub struct Level {
     pub subsectors: Vec<Rc<SubSector>>,
     pub nodes: HashMap<i16, Rc<Node>>,
     pub root_node: Rc<Node>,
+    pub blockmap: Blockmap,
+    sector_lines: Vec<Vec<usize>>, // Linedefs bordering each sector, on either side
+    subsector_lines: Vec<Vec<usize>>, // Linedefs each subsector's segs lie on
 }
 
 impl Level {
@@ -229,6 +448,18 @@ impl Level {
             })
             .collect();
 
+        let mut sector_lines = vec![Vec::new(); sectors.len()];
+        for (index, linedef) in linedefs.iter().enumerate() {
+            let (right, left) = linedef.side_sectors();
+            if let Some(right) = right {
+                sector_lines[right].push(index);
+            }
+            // A line with the same sector on both sides is only listed once
+            if let Some(left) = left.filter(|&left| Some(left) != right) {
+                sector_lines[left].push(index);
+            }
+        }
+
         let segments: Vec<Rc<Segment>> = data
             .segs
             .iter()
@@ -259,6 +490,20 @@ impl Level {
             })
             .collect();
 
+        let subsector_lines = data
+            .subsectors
+            .iter()
+            .map(|ssec| {
+                let mut lines: Vec<usize> = data.segs
+                    [ssec.first_segment..ssec.first_segment + ssec.segment_count]
+                    .iter()
+                    .map(|seg| seg.linedef)
+                    .collect();
+                lines.dedup();
+                lines
+            })
+            .collect();
+
         let mut nodes: HashMap<i16, Rc<Node>> = HashMap::new();
         data.nodes.iter().enumerate().for_each(|(idx, data)| {
             let left = match data.left_child {
@@ -280,8 +525,9 @@ impl Level {
                 delta_y: data.delta_y,
                 right_bbox: data.right_bbox,
                 left_bbox: data.left_bbox,
-                right_child: left,
-                left_child: right,
+                right_child: right,
+                left_child: left,
+                id: idx as i16,
             });
             nodes.insert(idx as i16, n);
         });
@@ -296,6 +542,1139 @@ impl Level {
             subsectors,
             nodes,
             root_node: root,
+            blockmap: data.blockmap.clone(),
+            sector_lines,
+            subsector_lines,
+        }
+    }
+
+    // Linedefs with a seg in any of the given subsectors, what the player has seen of the map
+    pub fn explored_linedefs(&self, subsectors: &HashSet<usize>) -> HashSet<usize> {
+        subsectors
+            .iter()
+            .filter_map(|&ssec| self.subsector_lines.get(ssec))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    pub fn sector_linedefs(&self, sector: usize) -> Vec<usize> {
+        self.sector_lines.get(sector).cloned().unwrap_or_default()
+    }
+
+    // Sectors sharing a two-sided line with this one, in index order
+    pub fn sector_neighbours(&self, sector: usize) -> Vec<usize> {
+        let mut neighbours: Vec<usize> = self
+            .sector_linedefs(sector)
+            .iter()
+            .filter_map(|&idx| match self.linedefs[idx].side_sectors() {
+                (Some(right), Some(left)) if right != left => {
+                    Some(if right == sector { left } else { right })
+                }
+                _ => None,
+            })
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    // Runs of at least three neighbouring sectors whose floors climb by the same step, give
+    // or take a couple of units, bottom first. Steps are up to 24 high, what a player can
+    // walk up. Where runs overlap only the longest is kept.
+    pub fn detect_stairs(&self) -> Vec<Vec<usize>> {
+        const MAX_STEP: i16 = 24;
+        const TOLERANCE: i16 = 2;
+        let floor = |sector: usize| self.sectors[sector].floor_height;
+        let adjacency: Vec<Vec<usize>> = (0..self.sectors.len())
+            .map(|sector| self.sector_neighbours(sector))
+            .collect();
+        let mut runs: Vec<Vec<usize>> = Vec::new();
+        for start in 0..self.sectors.len() {
+            for &second in adjacency[start].iter() {
+                let step = floor(second) - floor(start);
+                if !(1..=MAX_STEP).contains(&step) {
+                    continue;
+                }
+                let mut run = vec![start, second];
+                while let Some(&next) = adjacency[*run.last().unwrap()].iter().find(|&&next| {
+                    let rise = floor(next) - floor(*run.last().unwrap());
+                    rise > 0 && (rise - step).abs() <= TOLERANCE && !run.contains(&next)
+                }) {
+                    run.push(next);
+                }
+                if run.len() >= 3 {
+                    runs.push(run);
+                }
+            }
+        }
+        runs.sort_by_key(|run| std::cmp::Reverse(run.len()));
+        let mut used = HashSet::new();
+        runs.retain(|run| {
+            if run.iter().any(|sector| used.contains(sector)) {
+                return false;
+            }
+            used.extend(run.iter().copied());
+            true
+        });
+        runs
+    }
+
+    // Sectors whose boundary has a gap. A closed boundary enters and leaves every corner, so
+    // each one is the end of an even number of its lines. Lines with the sector on both sides
+    // are inside it and don't count. Corners are compared by position, not vertex number.
+    pub fn unclosed_sectors(&self) -> Vec<usize> {
+        (0..self.sectors.len())
+            .filter(|&sector| {
+                let mut ends: HashMap<(i16, i16), usize> = HashMap::new();
+                for &index in self.sector_lines[sector].iter() {
+                    let linedef = &self.linedefs[index];
+                    let (right, left) = linedef.side_sectors();
+                    if right == left {
+                        continue;
+                    }
+                    for vert in [linedef.start_vert, linedef.end_vert] {
+                        let v = self.vertexes[vert];
+                        *ends.entry((v.x, v.y)).or_default() += 1;
+                    }
+                }
+                ends.values().any(|count| count % 2 == 1)
+            })
+            .collect()
+    }
+
+    pub fn root_index(&self) -> i16 {
+        self.root_node.id
+    }
+
+    pub fn node_parent(&self, node_index: i16) -> Option<i16> {
+        self.nodes.values().find_map(|node| {
+            let is_child =
+                |child: &Option<Child>| matches!(child, Some(Child::NODE(n)) if n.id == node_index);
+            if is_child(&node.left_child) || is_child(&node.right_child) {
+                Some(node.id)
+            } else {
+                None
+            }
+        })
+    }
+
+    // Graphviz dump of the BSP tree: boxes for nodes, ellipses for subsector leaves
+    pub fn bsp_to_dot(&self) -> String {
+        let mut dot = String::from("digraph BSP {\n");
+        self.node_to_dot(&self.root_node, &mut dot);
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn node_to_dot(&self, node: &Node, dot: &mut String) {
+        let bbox = |b: &BBox| format!("({}, {}) {}x{}", b.left, b.top, b.width, b.height);
+        writeln!(
+            dot,
+            "  n{} [shape=box, label=\"node {}\\n({}, {}) + ({}, {})\\nR {}\\nL {}\"];",
+            node.id,
+            node.id,
+            node.partition_x,
+            node.partition_y,
+            node.delta_x,
+            node.delta_y,
+            bbox(&node.right_bbox),
+            bbox(&node.left_bbox)
+        )
+        .unwrap();
+        for (side, child) in [("R", &node.right_child), ("L", &node.left_child)] {
+            match child {
+                Some(Child::NODE(child)) => {
+                    writeln!(dot, "  n{} -> n{} [label=\"{}\"];", node.id, child.id, side).unwrap();
+                    self.node_to_dot(child, dot);
+                }
+                Some(Child::SUBSECTOR(ssec)) => {
+                    let idx = self
+                        .subsectors
+                        .iter()
+                        .position(|s| Rc::ptr_eq(s, ssec))
+                        .unwrap();
+                    writeln!(dot, "  n{} -> s{} [label=\"{}\"];", node.id, idx, side).unwrap();
+                    writeln!(
+                        dot,
+                        "  s{} [shape=ellipse, label=\"subsector {}\\n{} segs\"];",
+                        idx, idx, ssec.segment_count
+                    )
+                    .unwrap();
+                }
+                None => (),
+            }
+        }
+    }
+
+    pub fn node_child(&self, node_index: i16, left: bool) -> Option<i16> {
+        let node = self.nodes.get(&node_index)?;
+        let child = if left {
+            &node.left_child
+        } else {
+            &node.right_child
+        };
+        match child {
+            Some(Child::NODE(n)) => Some(n.id),
+            _ => None,
+        }
+    }
+
+    // Every subsector under a node, left child first, whichever way the player faces
+    pub fn subtree_subsectors(&self, node_index: i16) -> Vec<usize> {
+        let mut subsectors = Vec::new();
+        if let Some(node) = self.nodes.get(&node_index) {
+            self.collect_subtree(node, &mut subsectors);
+        }
+        subsectors
+    }
+
+    fn collect_subtree(&self, node: &Node, subsectors: &mut Vec<usize>) {
+        for child in [&node.left_child, &node.right_child] {
+            match child {
+                Some(Child::NODE(n)) => self.collect_subtree(n, subsectors),
+                Some(Child::SUBSECTOR(ssec)) => {
+                    subsectors.extend(self.subsectors.iter().position(|s| Rc::ptr_eq(s, ssec)))
+                }
+                None => (),
+            }
+        }
+    }
+
+    pub fn flat_usage(&self) -> HashMap<String, Vec<usize>> {
+        let mut usage: HashMap<String, Vec<usize>> = HashMap::new();
+        for sector in self.sectors.iter() {
+            for flat in [&sector.floor_tex, &sector.ceiling_tex] {
+                let sectors = usage.entry(flat.clone()).or_default();
+                if sectors.last() != Some(&sector.id) {
+                    sectors.push(sector.id);
+                }
+            }
+        }
+        usage
+    }
+
+    pub fn texture_usage(&self) -> HashMap<String, usize> {
+        let mut usage: HashMap<String, usize> = HashMap::new();
+        for sidedef in self.sidedefs.iter() {
+            for texture in [&sidedef.upper_tex, &sidedef.lower_tex, &sidedef.middle_tex] {
+                // "-" marks an untextured part of the wall
+                if texture != "-" {
+                    *usage.entry(texture.clone()).or_default() += 1;
+                }
+            }
+        }
+        usage
+    }
+
+    // (linedef, front sector, back sector) for every two-sided line joining two different
+    // sectors. Lines with the same sector on both sides are a mapping trick, not a portal.
+    pub fn portals(&self) -> Vec<(usize, usize, usize)> {
+        self.linedefs
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, linedef)| {
+                let (front, back) = linedef.sector_pair()?;
+                (front != back).then_some((idx, front, back))
+            })
+            .collect()
+    }
+
+    pub fn diff(&self, other: &Level) -> LevelDiff {
+        LevelDiff {
+            vertexes: ElementDiff::compare(
+                &self.vertexes,
+                &other.vertexes,
+                &[("position", |a, b| a.x != b.x || a.y != b.y)],
+            ),
+            linedefs: ElementDiff::compare(
+                &self.linedefs,
+                &other.linedefs,
+                &[
+                    ("vertexes", |a, b| {
+                        a.start_vert != b.start_vert || a.end_vert != b.end_vert
+                    }),
+                    ("flags", |a, b| a.flags != b.flags),
+                    ("special", |a, b| a.special_type != b.special_type),
+                    ("tag", |a, b| a.sector_tag != b.sector_tag),
+                    ("sides", |a, b| a.side_sectors() != b.side_sectors()),
+                ],
+            ),
+            sectors: ElementDiff::compare(
+                &self.sectors,
+                &other.sectors,
+                &[
+                    ("floor height", |a, b| a.floor_height != b.floor_height),
+                    ("ceiling height", |a, b| {
+                        a.ceiling_height != b.ceiling_height
+                    }),
+                    ("floor flat", |a, b| a.floor_tex != b.floor_tex),
+                    ("ceiling flat", |a, b| a.ceiling_tex != b.ceiling_tex),
+                    ("light", |a, b| a.light_level != b.light_level),
+                    ("special", |a, b| a.special_type != b.special_type),
+                    ("tag", |a, b| a.tag != b.tag),
+                ],
+            ),
+        }
+    }
+
+    // Sectors bucketed by light level, 16 levels to a bin
+    pub fn light_histogram(&self) -> [usize; 16] {
+        let mut bins = [0; 16];
+        for sector in self.sectors.iter() {
+            bins[(sector.light_level.clamp(0, 255) / 16) as usize] += 1;
+        }
+        bins
+    }
+
+    // What a player would find on the given skill, by category
+    pub fn thing_counts(&self, skill: Skill) -> ThingCounts {
+        let mut counts = ThingCounts::default();
+        for thing in self.things.iter().filter(|thing| thing.appears_on(skill)) {
+            match ThingCategory::of(thing.thing_type) {
+                ThingCategory::Monster => counts.monsters += 1,
+                ThingCategory::Item => counts.items += 1,
+                ThingCategory::Key => counts.keys += 1,
+                ThingCategory::Decoration => counts.decorations += 1,
+                ThingCategory::PlayerStart | ThingCategory::Other => counts.other += 1,
+            }
+        }
+        counts
+    }
+
+    // Index and colour of every key thing
+    pub fn keys(&self) -> Vec<(usize, KeyColor)> {
+        self.things
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, thing)| Some((idx, KeyColor::of(thing.thing_type)?)))
+            .collect()
+    }
+
+    // Mean of the sector's subsector vertices, good enough for placing a label
+    pub fn sector_centroid(&self, sector: usize) -> Option<(f32, f32)> {
+        let vertexes: Vec<Vertex> = self
+            .subsectors
+            .iter()
+            .filter(|ssec| ssec.sector_id() == Some(sector))
+            .flat_map(|ssec| {
+                ssec.segments
+                    .iter()
+                    .map(|seg| self.vertexes[seg.start_vert])
+            })
+            .collect();
+        Self::mean_position(&vertexes)
+    }
+
+    pub fn subsector_centroid(&self, subsector: usize) -> Option<(f32, f32)> {
+        let vertexes: Vec<Vertex> = self
+            .subsectors
+            .get(subsector)?
+            .segments
+            .iter()
+            .map(|seg| self.vertexes[seg.start_vert])
+            .collect();
+        Self::mean_position(&vertexes)
+    }
+
+    pub fn sector_area(&self, sector: usize) -> f32 {
+        (0..self.subsectors.len())
+            .filter(|&ssec| self.subsectors[ssec].sector_id() == Some(sector))
+            .map(|ssec| self.subsector_area(ssec))
+            .sum()
+    }
+
+    // Subsectors are convex, so their seg ends sorted round the middle make the polygon.
+    // Sides along partition lines have no seg but are filled in between neighbouring ends.
+    // Corners that only lie on partitions are missed, which undercounts a little.
+    pub fn subsector_area(&self, subsector: usize) -> f32 {
+        let Some(ssec) = self.subsectors.get(subsector) else {
+            return 0.;
+        };
+        let vertexes: Vec<Vertex> = ssec
+            .segments
+            .iter()
+            .flat_map(|seg| [self.vertexes[seg.start_vert], self.vertexes[seg.end_vert]])
+            .collect();
+        let Some((cx, cy)) = Self::mean_position(&vertexes) else {
+            return 0.;
+        };
+        let mut points: Vec<(f32, f32)> = vertexes
+            .iter()
+            .map(|v| (f32::from(v.x) - cx, f32::from(v.y) - cy))
+            .collect();
+        points.sort_by(|a, b| a.1.atan2(a.0).total_cmp(&b.1.atan2(b.0)));
+        let twice_area: f32 = (0..points.len())
+            .map(|i| {
+                let (x1, y1) = points[i];
+                let (x2, y2) = points[(i + 1) % points.len()];
+                x1 * y2 - x2 * y1
+            })
+            .sum();
+        twice_area.abs() / 2.
+    }
+
+    fn mean_position(vertexes: &[Vertex]) -> Option<(f32, f32)> {
+        if vertexes.is_empty() {
+            return None;
         }
+        let count = vertexes.len() as f32;
+        let (sum_x, sum_y) = vertexes.iter().fold((0., 0.), |(x, y), v| {
+            (x + f32::from(v.x), y + f32::from(v.y))
+        });
+        Some((sum_x / count, sum_y / count))
+    }
+
+    // (lowest, highest) floor in the level
+    pub fn floor_height_range(&self) -> Option<(i16, i16)> {
+        let floors = self.sectors.iter().map(|sector| sector.floor_height);
+        Some((floors.clone().min()?, floors.max()?))
+    }
+
+    // (floor, ceiling)
+    pub fn sector_heights(&self, sector: usize) -> Option<(i16, i16)> {
+        let sector = self.sectors.get(sector)?;
+        Some((sector.floor_height, sector.ceiling_height))
+    }
+
+    pub fn self_referencing_linedefs(&self) -> Vec<usize> {
+        self.linedefs
+            .iter()
+            .enumerate()
+            .filter(|(_, linedef)| linedef.is_self_referencing())
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    fn distance_to_linedef(&self, linedef: &Linedef, x: f32, y: f32) -> f32 {
+        let v1 = self.vertexes[linedef.start_vert];
+        let v2 = self.vertexes[linedef.end_vert];
+        let (x1, y1) = (f32::from(v1.x), f32::from(v1.y));
+        let (dx, dy) = (f32::from(v2.x) - x1, f32::from(v2.y) - y1);
+        let length_sq = dx * dx + dy * dy;
+        // Clamp to the segment so points past either end measure to that endpoint
+        let t = if length_sq == 0. {
+            0.
+        } else {
+            (((x - x1) * dx + (y - y1) * dy) / length_sq).clamp(0., 1.)
+        };
+        (x - (x1 + t * dx)).hypot(y - (y1 + t * dy))
+    }
+
+    pub fn nearest_linedef(&self, x: f32, y: f32) -> Option<(usize, f32)> {
+        let mut nearest: Option<(usize, f32)> = None;
+        let consider = |nearest: &mut Option<(usize, f32)>, idx: usize| {
+            let distance = self.distance_to_linedef(&self.linedefs[idx], x, y);
+            if nearest.is_none_or(|(_, best)| distance < best) {
+                *nearest = Some((idx, distance));
+            }
+        };
+
+        // Search rings of blockmap cells outward from the point's cell. Anything outside
+        // ring r is at least r cells away, so stop once the best hit is closer than that.
+        let blockmap = &self.blockmap;
+        let (column, row) = blockmap.cell(x, y);
+        let max_ring = [
+            column,
+            row,
+            blockmap.columns as i32 - column,
+            blockmap.rows as i32 - row,
+        ]
+        .iter()
+        .map(|d| d.abs())
+        .max()
+        .unwrap_or(0);
+        for ring in 0..=max_ring {
+            for c in column - ring..=column + ring {
+                for r in row - ring..=row + ring {
+                    if (c - column).abs() != ring && (r - row).abs() != ring {
+                        continue;
+                    }
+                    if let Some(block) = blockmap.block(c, r) {
+                        block.iter().for_each(|&idx| consider(&mut nearest, idx));
+                    }
+                }
+            }
+            if let Some((_, best)) = nearest {
+                if best <= (ring * Blockmap::CELL_SIZE) as f32 {
+                    return nearest;
+                }
+            }
+        }
+        if nearest.is_none() {
+            // No usable blockmap, fall back to checking everything
+            (0..self.linedefs.len()).for_each(|idx| consider(&mut nearest, idx));
+        }
+        nearest
+    }
+
+    // Points exactly on a partition line take the side Doom's R_PointOnSide gives them,
+    // so a thing on a boundary always lands in the same sector it would in the game
+    pub fn sector_at(&self, x: i16, y: i16) -> Option<usize> {
+        self.root_node.find(x, y).sector_id()
+    }
+
+    // Linedefs with a special acting on sectors with this tag. Tag 0 is untagged: those
+    // specials are doors and the like working on the sector behind the line, so none count.
+    pub fn sector_triggers(&self, sector_tag: usize) -> Vec<usize> {
+        if sector_tag == 0 {
+            return Vec::new();
+        }
+        (0..self.linedefs.len())
+            .filter(|&idx| {
+                let linedef = &self.linedefs[idx];
+                linedef.sector_tag == sector_tag && linedef.special_type != 0
+            })
+            .collect()
+    }
+
+    // (sector, colormap name) for Boom's 242 transfer lines, which give every sector sharing
+    // the line's tag the colormap named by the middle texture of its front side
+    pub fn colormap_transfers(&self) -> Vec<(usize, String)> {
+        let mut transfers = Vec::new();
+        for linedef in self.linedefs.iter().filter(|ld| ld.special_type == 242) {
+            let Some(side) = &linedef.right_sidedef else {
+                continue;
+            };
+            for sector in self.sectors.iter().filter(|s| s.tag == linedef.sector_tag) {
+                transfers.push((sector.id, side.middle_tex.clone()));
+            }
+        }
+        transfers
+    }
+
+    // Co-op starts for players 1-4 by slot. Like the game, a later duplicate replaces an
+    // earlier one.
+    pub fn player_starts(&self) -> [Option<Thing>; 4] {
+        let mut starts = [None; 4];
+        for thing in self.things.iter() {
+            if (1..=4).contains(&thing.thing_type) {
+                starts[thing.thing_type as usize - 1] = Some(*thing);
+            }
+        }
+        starts
+    }
+
+    // (sector, position) of every teleport destination, the things teleport lines send you to
+    pub fn teleport_destinations(&self) -> Vec<(usize, (i16, i16))> {
+        self.things
+            .iter()
+            .filter(|thing| thing.thing_type == 14)
+            .filter_map(|thing| Some((self.sector_at(thing.x, thing.y)?, (thing.x, thing.y))))
+            .collect()
+    }
+
+    // Indexes of things whose position lies within radius of (x, y). A straight scan; the
+    // blockmap could narrow it down if this ever shows up in a profile.
+    pub fn things_near(&self, x: f32, y: f32, radius: f32) -> Vec<usize> {
+        self.things
+            .iter()
+            .enumerate()
+            .filter(|(_, thing)| (f32::from(thing.x) - x).hypot(f32::from(thing.y) - y) <= radius)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    // Subsectors whose node bbox is at least partly in the field of view, nearest first
+    pub fn visible_subsectors(&self, player: &Player, fov: f32) -> Vec<usize> {
+        let mut visible = Vec::new();
+        self.collect_visible(&self.root_node, player, fov, &mut visible);
+        visible
+    }
+
+    fn collect_visible(&self, node: &Node, player: &Player, fov: f32, visible: &mut Vec<usize>) {
+        let right = (&node.right_child, &node.right_bbox);
+        let left = (&node.left_child, &node.left_bbox);
+        let sides = if node.is_point_behind(player.x.trunc() as i16, player.y.trunc() as i16) {
+            [left, right]
+        } else {
+            [right, left]
+        };
+        for (child, bbox) in sides {
+            if !bbox_in_view(bbox, player, fov) {
+                continue;
+            }
+            match child {
+                Some(Child::NODE(n)) => self.collect_visible(n, player, fov, visible),
+                Some(Child::SUBSECTOR(ssec)) => {
+                    visible.extend(self.subsectors.iter().position(|s| Rc::ptr_eq(s, ssec)))
+                }
+                None => (),
+            }
+        }
+    }
+
+    // Whether moving in a straight line between the points crosses no line that blocks the
+    // player. Floor and ceiling heights aren't considered.
+    pub fn try_move(&self, from: (f32, f32), to: (f32, f32)) -> bool {
+        self.blocking_linedef(from, to).is_none()
+    }
+
+    // The first line in the list that blocks the player moving between the points
+    pub fn blocking_linedef(&self, from: (f32, f32), to: (f32, f32)) -> Option<&Linedef> {
+        self.linedefs
+            .iter()
+            .filter(|linedef| linedef.blocks_player())
+            .find(|linedef| {
+                let v1 = self.vertexes[linedef.start_vert];
+                let v2 = self.vertexes[linedef.end_vert];
+                let p1 = (f32::from(v1.x), f32::from(v1.y));
+                let p2 = (f32::from(v2.x), f32::from(v2.y));
+                // Leaving a line the player already stands on is allowed, or they'd stick
+                matches!(math::seg_intersect(from, to, p1, p2), Some((t, _)) if t > 0.)
+            })
+            .map(|linedef| &**linedef)
+    }
+
+    // The nodes point location passes through from the root, and whether the point was
+    // behind each one's partition. Its length is the depth of the subsector found.
+    pub fn location_path(&self, x: i16, y: i16) -> Vec<(&Node, bool)> {
+        let mut path = Vec::new();
+        let mut node = &*self.root_node;
+        loop {
+            let behind = node.is_point_behind(x, y);
+            path.push((node, behind));
+            let child = if behind {
+                &node.left_child
+            } else {
+                &node.right_child
+            };
+            match child {
+                Some(Child::NODE(n)) => node = n,
+                _ => return path,
+            }
+        }
+    }
+
+    pub fn subsector_at(&self, x: i16, y: i16) -> usize {
+        let found = self.root_node.find(x, y);
+        self.subsectors
+            .iter()
+            .position(|ssec| std::ptr::eq(ssec.as_ref(), found))
+            .unwrap()
+    }
+
+    pub fn thing_sectors(&self) -> Vec<Option<usize>> {
+        self.things
+            .iter()
+            .map(|thing| self.sector_at(thing.x, thing.y))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts;
+
+    use super::*;
+    use crate::test_support::{build_level, level_data, player_at, MiniLevel};
+
+    #[test]
+    fn subtree_from_leaf_parent_covers_only_its_subsectors() {
+        // Three rooms: the root splits room 0 from a node holding rooms 1 and 2
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 128));
+        let root = level.root_index();
+        let leaf_parent = [true, false]
+            .into_iter()
+            .find_map(|left| level.node_child(root, left))
+            .unwrap();
+        assert_eq!(level.node_parent(leaf_parent), Some(root));
+
+        let mut under_leaf_parent = level.subtree_subsectors(leaf_parent);
+        under_leaf_parent.sort();
+        assert_eq!(under_leaf_parent, [1, 2]);
+        let mut under_root = level.subtree_subsectors(root);
+        under_root.sort();
+        assert_eq!(under_root, [0, 1, 2]);
+
+        // In room 0 facing west, the culled walk drops rooms 1 and 2 but the subtree walk does not
+        let west = player_at(64., 64., consts::PI);
+        assert_eq!(level.visible_subsectors(&west, consts::FRAC_PI_2), [0]);
+        assert_eq!(level.subtree_subsectors(leaf_parent).len(), 2);
+    }
+
+    #[test]
+    fn linedef_length_and_midpoint() {
+        // Corners of the room are vertexes 0 (0, 0), 1 (0, 128), 2 (128, 0), 3 (128, 128)
+        let mut data = level_data(&MiniLevel::one_room("MAP01", 128));
+        (data.linedefs[1].start_vert, data.linedefs[1].end_vert) = (0, 2);
+        (data.linedefs[2].start_vert, data.linedefs[2].end_vert) = (1, 2);
+        (data.linedefs[3].start_vert, data.linedefs[3].end_vert) = (3, 3);
+        let level = Level::new(&data);
+
+        let vertical = &level.linedefs[0];
+        assert_eq!(vertical.length(&level), 128.);
+        assert_eq!(vertical.midpoint(&level), (0., 64.));
+        let horizontal = &level.linedefs[1];
+        assert_eq!(horizontal.length(&level), 128.);
+        assert_eq!(horizontal.midpoint(&level), (64., 0.));
+        let diagonal = &level.linedefs[2];
+        assert!((diagonal.length(&level) - 128. * consts::SQRT_2).abs() < 1e-3);
+        assert_eq!(diagonal.midpoint(&level), (64., 64.));
+        let degenerate = &level.linedefs[3];
+        assert_eq!(degenerate.length(&level), 0.);
+        assert_eq!(degenerate.midpoint(&level), (128., 128.));
+    }
+
+    #[test]
+    fn flat_usage_maps_flats_to_sectors() {
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 3, 128));
+        data.sectors[1].floor_tex = "NUKAGE1".to_string();
+        let usage = Level::new(&data).flat_usage();
+        assert_eq!(usage["FLOOR4_8"], [0, 2]);
+        assert_eq!(usage["NUKAGE1"], [1]);
+        assert_eq!(usage["CEIL3_5"], [0, 1, 2]);
+        assert_eq!(usage.len(), 3);
+    }
+
+    #[test]
+    fn texture_usage_counts_textures_but_not_blanks() {
+        // Three rooms have eight one-sided walls and two open lines
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 3, 128));
+        let step = data.linedefs[3].right_sidedef;
+        data.sidedefs[step].lower_tex = "STEP6".to_string();
+        let usage = Level::new(&data).texture_usage();
+        assert_eq!(usage["STARTAN3"], 8);
+        assert_eq!(usage["STEP6"], 1);
+        assert!(!usage.contains_key("-"));
+    }
+
+    #[test]
+    fn nearest_linedef_picks_closest_wall() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 128));
+        // Room 2's south wall, rather than the open line 44 units west
+        assert_eq!(level.nearest_linedef(300., 20.), Some((8, 20.)));
+        assert_eq!(level.nearest_linedef(250., 64.), Some((6, 6.)));
+        // Past the corner the distance is to the shared vertex, not the extended wall
+        let (idx, distance) = level.nearest_linedef(-3., -4.).unwrap();
+        assert!(idx == 0 || idx == 2);
+        assert_eq!(distance, 5.);
+    }
+
+    #[test]
+    fn thing_sectors_follow_point_location() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 2, 128);
+        // The partition between the rooms points north, and Doom puts points on such a
+        // line behind it, in the west room
+        mini.things.extend([(200, 64, 3001), (128, 64, 3001)]);
+        let level = build_level(&mini);
+        let start = &level.things[0];
+        assert_eq!(
+            level.thing_sectors(),
+            [level.sector_at(start.x, start.y), Some(1), Some(0)]
+        );
+        assert_eq!(level.thing_sectors()[0], Some(0));
+    }
+
+    #[test]
+    fn classify_by_sidedef_presence() {
+        use LineClass::{NoSides, OneSided, TwoSided};
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 2, 128));
+        (
+            data.linedefs[5].right_sidedef,
+            data.linedefs[5].left_sidedef,
+        ) = (65535, 65535);
+        let level = Level::new(&data);
+        let classes: Vec<LineClass> = level.linedefs.iter().map(|l| l.classify()).collect();
+        assert_eq!(
+            classes,
+            [OneSided, OneSided, OneSided, TwoSided, OneSided, NoSides, OneSided]
+        );
+    }
+
+    #[test]
+    fn two_rooms_have_one_portal() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 2, 128));
+        assert_eq!(level.portals(), [(3, 0, 1)]);
+        assert!(level.self_referencing_linedefs().is_empty());
+    }
+
+    #[test]
+    fn self_referencing_line_is_not_a_portal() {
+        // The third room's west line gets the second room on both sides
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 3, 128));
+        let left = data.linedefs[6].left_sidedef;
+        data.sidedefs[left].sector = 1;
+        let level = Level::new(&data);
+        assert_eq!(level.self_referencing_linedefs(), [6]);
+        assert_eq!(level.portals(), [(3, 0, 1)]);
+    }
+
+    #[test]
+    fn bsp_dot_has_an_entry_per_node_and_leaf() {
+        let dot = build_level(&MiniLevel::row_of_rooms("MAP01", 4, 128)).bsp_to_dot();
+        assert!(dot.starts_with("digraph BSP {\n") && dot.ends_with("}\n"));
+        let count = |pattern: &str| dot.lines().filter(|line| line.contains(pattern)).count();
+        assert_eq!(count("[shape=box"), 3);
+        assert_eq!(count("[shape=ellipse"), 4);
+        // Every node but the root hangs off one edge, as does every leaf
+        assert_eq!(count(" -> "), 2 + 4);
+        for idx in 0..4 {
+            assert_eq!(count(&format!("  s{} [shape=ellipse", idx)), 1);
+        }
+    }
+
+    #[test]
+    fn square_sector_centroid_is_its_centre() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 2, 128);
+        mini.rooms[1].floor_height = 24;
+        let level = build_level(&mini);
+        assert_eq!(level.sector_centroid(0), Some((64., 64.)));
+        assert_eq!(level.sector_centroid(1), Some((192., 64.)));
+        assert_eq!(level.sector_heights(1), Some((24, 128)));
+        // No subsector belongs to a sector that isn't there
+        assert_eq!(level.sector_centroid(2), None);
+        assert_eq!(level.sector_heights(2), None);
+    }
+
+    #[test]
+    fn keys_are_found_with_their_colours() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 2, 128);
+        // Blue card, a shotgun, red skull, yellow card
+        mini.things
+            .extend([(32, 32, 5), (48, 48, 2001), (160, 32, 38), (200, 100, 6)]);
+        let level = build_level(&mini);
+        assert_eq!(
+            level.keys(),
+            [
+                (1, KeyColor::Blue),
+                (3, KeyColor::Red),
+                (4, KeyColor::Yellow)
+            ]
+        );
+        assert!(build_level(&MiniLevel::one_room("MAP01", 128))
+            .keys()
+            .is_empty());
+    }
+
+    #[test]
+    fn thing_counts_depend_on_skill() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 2, 128);
+        mini.things.extend([
+            (32, 32, 3004),  // Zombieman
+            (48, 32, 3001),  // Imp
+            (160, 32, 3002), // Demon
+            (160, 96, 2011), // Stimpack
+        ]);
+        let mut data = level_data(&mini);
+        // The imp only appears on the hard skills and the stimpack only in multiplayer
+        data.things[2].flags = 0x04;
+        data.things[4].flags = 0x07 | 0x10;
+        let level = Level::new(&data);
+        let easy = level.thing_counts(Skill::Baby);
+        let hard = level.thing_counts(Skill::Hard);
+        assert_eq!((easy.monsters, easy.items), (2, 0));
+        assert_eq!((hard.monsters, hard.items), (3, 0));
+        assert_eq!(level.thing_counts(Skill::Nightmare), hard);
+        assert_eq!(easy.other, 1);
+    }
+
+    #[test]
+    fn diff_reports_one_changed_sector() {
+        let mini = MiniLevel::row_of_rooms("MAP01", 2, 128);
+        let old = build_level(&mini);
+        let mut data = level_data(&mini);
+        data.sectors[1].floor_height = 16;
+        let diff = old.diff(&Level::new(&data));
+        assert_eq!(
+            (
+                diff.sectors.added,
+                diff.sectors.removed,
+                diff.sectors.changed
+            ),
+            (0, 0, 1)
+        );
+        assert_eq!(diff.sectors.field_changes, [("floor height", 1)]);
+        assert_eq!(diff.vertexes.changed + diff.linedefs.changed, 0);
+
+        // A third room adds to every element after the shared ones
+        let grown = old.diff(&build_level(&MiniLevel::row_of_rooms("MAP01", 3, 128)));
+        assert_eq!((grown.sectors.added, grown.sectors.changed), (1, 0));
+        assert_eq!(grown.vertexes.added, 2);
+    }
+
+    #[test]
+    fn light_histogram_bins_sectors_by_sixteens() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 4, 64);
+        for (room, light) in mini.rooms.iter_mut().zip([0, 15, 16, 255]) {
+            room.light_level = light;
+        }
+        let bins = build_level(&mini).light_histogram();
+        assert_eq!((bins[0], bins[1], bins[15]), (2, 1, 1));
+        assert_eq!(bins.iter().sum::<usize>(), 4);
+    }
+
+    #[test]
+    fn sector_linedefs_include_both_shared_walls() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 128));
+        assert_eq!(level.sector_linedefs(0), [0, 1, 2, 3]);
+        assert_eq!(level.sector_linedefs(1), [3, 4, 5, 6]);
+        assert_eq!(level.sector_linedefs(2), [6, 7, 8, 9]);
+        assert!(level.sector_linedefs(3).is_empty());
+    }
+
+    #[test]
+    fn teleport_destinations_report_sector_and_position() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 3, 128);
+        // Destinations in the last and first rooms, and a torch that isn't one
+        mini.things
+            .extend([(300, 40, 14), (20, 100, 14), (100, 100, 44)]);
+        let level = build_level(&mini);
+        assert_eq!(
+            level.teleport_destinations(),
+            [(2, (300, 40)), (0, (20, 100))]
+        );
+    }
+
+    #[test]
+    fn player_starts_fill_their_slots() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 2, 128);
+        // Player 2 and a deathmatch start, which has no slot
+        mini.things.extend([(192, 64, 2), (200, 100, 11)]);
+        let level = build_level(&mini);
+        let starts = level.player_starts().map(|start| start.map(|t| (t.x, t.y)));
+        assert_eq!(starts, [Some((64, 64)), Some((192, 64)), None, None]);
+    }
+
+    #[test]
+    fn things_near_include_the_boundary() {
+        let mut mini = MiniLevel::one_room("MAP01", 256);
+        // 50 units away along an axis, on the diagonal (30, 40) away, and 51 away
+        mini.things
+            .extend([(150, 100, 2011), (130, 140, 2011), (100, 151, 2011)]);
+        mini.things[0] = (100, 100, 1);
+        let level = build_level(&mini);
+        assert_eq!(level.things_near(100., 100., 50.), [0, 1, 2]);
+        assert_eq!(level.things_near(100., 100., 49.9), [0]);
+        assert!(level.things_near(-500., -500., 100.).is_empty());
+    }
+
+    #[test]
+    fn explored_linedefs_come_from_visited_subsectors() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 128));
+        assert!(level.explored_linedefs(&HashSet::new()).is_empty());
+        assert_eq!(
+            level.explored_linedefs(&HashSet::from([1])),
+            HashSet::from([3, 4, 5, 6])
+        );
+        assert_eq!(
+            level.explored_linedefs(&HashSet::from([0, 2])),
+            HashSet::from([0, 1, 2, 3, 6, 7, 8, 9])
+        );
+    }
+
+    #[test]
+    fn front_sidedef_follows_seg_direction() {
+        // The shared line has room 0 on its right, so room 0's seg runs with it and room 1's
+        // against it
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 2, 128));
+        for (ssec, (front, back)) in [(0, (0, 1)), (1, (1, 0))] {
+            let seg = level.subsectors[ssec]
+                .segments
+                .iter()
+                .find(|seg| seg.linedef.classify() == LineClass::TwoSided)
+                .unwrap();
+            assert_eq!(seg.dir_like_linedef, ssec == 0);
+            assert_eq!(seg.front_sidedef().unwrap().sector.id, front);
+            assert_eq!(seg.back_sidedef().unwrap().sector.id, back);
+        }
+        // A one-sided wall has nothing behind it
+        let wall = &level.subsectors[0].segments[0];
+        assert!(wall.front_sidedef().is_some() && wall.back_sidedef().is_none());
+    }
+
+    #[test]
+    fn location_path_is_as_long_as_the_subsector_is_deep() {
+        // Nodes passed from this node down to the leaf holding target, by a full search
+        fn depth(node: &Node, target: &SubSector) -> Option<usize> {
+            [&node.right_child, &node.left_child]
+                .into_iter()
+                .find_map(|child| match child {
+                    Some(Child::NODE(n)) => depth(n, target),
+                    Some(Child::SUBSECTOR(ssec)) if std::ptr::eq(&**ssec, target) => Some(0),
+                    _ => None,
+                })
+                .map(|below| below + 1)
+        }
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 4, 128));
+        let mut lengths = Vec::new();
+        for room in 0..4 {
+            let x = room * 128 + 64;
+            let path = level.location_path(x, 64);
+            let ssec = &level.subsectors[level.subsector_at(x, 64)];
+            assert_eq!(Some(path.len()), depth(&level.root_node, ssec));
+            lengths.push(path.len());
+        }
+        assert_eq!(lengths, [1, 2, 3, 3]);
+        // Only the last room is in front of every partition on its way down
+        assert!(level
+            .location_path(448, 64)
+            .iter()
+            .all(|&(_, behind)| !behind));
+    }
+
+    #[test]
+    fn sector_area_sums_its_subsectors() {
+        let close = |a: f32, b: f32| (a - b).abs() < 0.5;
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 256));
+        assert!(close(level.sector_area(0), 65536.));
+        assert!(close(level.sector_area(2), 65536.));
+        assert_eq!(level.sector_area(3), 0.);
+
+        // Rooms 1 and 2 made one sector split across two subsectors
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 3, 256));
+        for side in data.sidedefs.iter_mut().filter(|side| side.sector == 2) {
+            side.sector = 1;
+        }
+        let level = Level::new(&data);
+        assert!(close(level.sector_area(1), 2. * 65536.));
+        assert_eq!(level.sector_area(2), 0.);
+    }
+
+    #[test]
+    fn corridor_rooms_behind_the_player_are_not_visible() {
+        // Five rooms in a row, the player in the middle one
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 5, 128));
+        let fov = consts::FRAC_PI_2;
+        let east = player_at(320., 64., 0.);
+        assert_eq!(level.visible_subsectors(&east, fov), [2, 3, 4]);
+        let west = player_at(320., 64., consts::PI);
+        assert_eq!(level.visible_subsectors(&west, fov), [2, 1, 0]);
+        // Up close to the north wall and facing it, the rooms either side are out of view
+        let north = player_at(320., 100., consts::FRAC_PI_2);
+        assert_eq!(level.visible_subsectors(&north, fov), [2]);
+    }
+
+    #[test]
+    fn bbox_behind_the_player_is_culled() {
+        let bbox = |left, top, width, height| BBox {
+            top,
+            left,
+            width,
+            height,
+        };
+        let fov = consts::FRAC_PI_2;
+        let player = player_at(0., 0., 0.);
+        // Wholly behind, ahead, straddling the view's edge and round the player
+        assert!(!bbox_in_view(&bbox(-200, 50, 100, 100), &player, fov));
+        assert!(bbox_in_view(&bbox(100, 50, 100, 100), &player, fov));
+        assert!(bbox_in_view(&bbox(100, 250, 200, 100), &player, fov));
+        assert!(!bbox_in_view(&bbox(100, 400, 100, 100), &player, fov));
+        assert!(bbox_in_view(&bbox(-10, 10, 20, 20), &player, fov));
+        // Straddling the line directly behind, where the corner angles wrap round
+        assert!(!bbox_in_view(
+            &bbox(-200, 50, 100, 100),
+            &player_at(0., 0., 0.1),
+            fov
+        ));
+        assert!(bbox_in_view(
+            &bbox(-200, 50, 100, 100),
+            &player_at(0., 0., 3.),
+            fov
+        ));
+    }
+
+    #[test]
+    fn sector_with_a_missing_wall_is_unclosed() {
+        let mini = MiniLevel::row_of_rooms("MAP01", 3, 128);
+        assert!(build_level(&mini).unclosed_sectors().is_empty());
+
+        // Room 1's north wall loses its only side, leaving a gap between two corners
+        let mut data = level_data(&mini);
+        data.linedefs[4].right_sidedef = 65535;
+        assert_eq!(Level::new(&data).unclosed_sectors(), [1]);
+
+        // A line inside a sector doesn't bound it, so it can't close a gap either
+        let mut data = level_data(&mini);
+        let left = data.linedefs[6].left_sidedef;
+        data.sidedefs[left].sector = 1;
+        assert_eq!(Level::new(&data).unclosed_sectors(), [1, 2]);
+    }
+
+    #[test]
+    fn wall_normals_point_out_of_the_front() {
+        // Walls face into the room: west wall 0, north 1, south 2, east 3
+        let level = build_level(&MiniLevel::one_room("MAP01", 128));
+        let normals: Vec<(f32, f32)> = level.linedefs.iter().map(|l| l.normal(&level)).collect();
+        assert_eq!(normals, [(1., 0.), (0., -1.), (0., 1.), (-1., 0.)]);
+
+        let mut data = level_data(&MiniLevel::one_room("MAP01", 128));
+        data.vertexes[3] = data.vertexes[2];
+        let level = Level::new(&data);
+        assert_eq!(level.linedefs[3].normal(&level), (0., 0.));
+    }
+
+    #[test]
+    fn sector_triggers_find_the_lines_acting_on_a_tag() {
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 2, 128));
+        // The second room is a door tagged 5, opened by a switch on the first room's north wall
+        data.sectors[1].tag = 5;
+        data.linedefs[1].special_type = 103;
+        data.linedefs[1].sector_tag = 5;
+        // A leftover tag on a line with no special doesn't trigger anything
+        data.linedefs[2].sector_tag = 5;
+        let level = Level::new(&data);
+        assert_eq!(level.sector_triggers(5), [1]);
+        assert_eq!(level.sector_triggers(6), []);
+        assert_eq!(level.sector_triggers(0), []);
+    }
+
+    #[test]
+    fn sector_neighbours_come_from_two_sided_lines() {
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 4, 128));
+        // A second line between the first two rooms only counts once, and the line into the
+        // last room turned to face the third on both sides leaves the last with no neighbours
+        data.linedefs.push(data.linedefs[3]);
+        data.sidedefs[data.linedefs[9].left_sidedef].sector = 2;
+        let level = Level::new(&data);
+        assert_eq!(level.sector_neighbours(0), [1]);
+        assert_eq!(level.sector_neighbours(1), [0, 2]);
+        assert_eq!(level.sector_neighbours(2), [1]);
+        assert_eq!(level.sector_neighbours(3), []);
+    }
+
+    #[test]
+    fn detect_stairs_finds_a_five_step_staircase_in_order() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 5, 128);
+        // Steps of 8, one of them a unit short
+        for (room, floor) in mini.rooms.iter_mut().zip([0, 8, 16, 23, 32]) {
+            room.floor_height = floor;
+        }
+        assert_eq!(build_level(&mini).detect_stairs(), [[0, 1, 2, 3, 4]]);
+
+        // A level landing before the steps and a drop after them aren't part of the run
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 7, 128);
+        for (room, floor) in mini.rooms.iter_mut().zip([0, 0, 8, 16, 23, 32, 0]) {
+            room.floor_height = floor;
+        }
+        assert_eq!(build_level(&mini).detect_stairs(), [[1, 2, 3, 4, 5]]);
+
+        // Too steep to walk up
+        for (idx, room) in mini.rooms.iter_mut().enumerate() {
+            room.floor_height = idx as i16 * 32;
+        }
+        assert!(build_level(&mini).detect_stairs().is_empty());
+    }
+
+    #[test]
+    fn block_all_stops_the_player_on_a_two_sided_line() {
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 3, 128));
+        // The line into the second room blocks monsters, the one into the third everything
+        data.linedefs[3].flags |= 0x0002;
+        data.linedefs[6].flags |= 0x0001;
+        let level = Level::new(&data);
+        let flags = |idx: usize| {
+            let linedef = &level.linedefs[idx];
+            (linedef.blocks_player(), linedef.blocks_monsters())
+        };
+        // The east wall is one-sided, so it stops everything whatever its flags
+        assert_eq!(
+            [flags(3), flags(6), flags(9)],
+            [(false, true), (true, true), (true, true)]
+        );
+
+        // Only the block-all line stops the player
+        assert!(level.try_move((64., 64.), (192., 64.)));
+        assert!(!level.try_move((192., 64.), (320., 64.)));
     }
 }
@@ -0,0 +1,138 @@
+// Human-readable map names as shown on the Doom automap
+const DOOM_NAMES: [[&str; 9]; 4] = [
+    [
+        "Hangar",
+        "Nuclear Plant",
+        "Toxin Refinery",
+        "Command Control",
+        "Phobos Lab",
+        "Central Processing",
+        "Computer Station",
+        "Phobos Anomaly",
+        "Military Base",
+    ],
+    [
+        "Deimos Anomaly",
+        "Containment Area",
+        "Refinery",
+        "Deimos Lab",
+        "Command Center",
+        "Halls of the Damned",
+        "Spawning Vats",
+        "Tower of Babel",
+        "Fortress of Mystery",
+    ],
+    [
+        "Hell Keep",
+        "Slough of Despair",
+        "Pandemonium",
+        "House of Pain",
+        "Unholy Cathedral",
+        "Mt. Erebus",
+        "Limbo",
+        "Dis",
+        "Warrens",
+    ],
+    [
+        "Hell Beneath",
+        "Perfect Hatred",
+        "Sever The Wicked",
+        "Unruly Evil",
+        "They Will Repent",
+        "Against Thee Wickedly",
+        "And Hell Followed",
+        "Unto The Cruel",
+        "Fear",
+    ],
+];
+
+const DOOM2_NAMES: [&str; 32] = [
+    "Entryway",
+    "Underhalls",
+    "The Gantlet",
+    "The Focus",
+    "The Waste Tunnels",
+    "The Crusher",
+    "Dead Simple",
+    "Tricks and Traps",
+    "The Pit",
+    "Refueling Base",
+    "'O' of Destruction!",
+    "The Factory",
+    "Downtown",
+    "The Inmost Dens",
+    "Industrial Zone",
+    "Suburbs",
+    "Tenements",
+    "The Courtyard",
+    "The Citadel",
+    "Gotcha!",
+    "Nirvana",
+    "The Catacombs",
+    "Barrels o' Fun",
+    "The Chasm",
+    "Bloodfalls",
+    "The Abandoned Mines",
+    "Monster Condo",
+    "The Spirit World",
+    "The Living End",
+    "Icon of Sin",
+    "Wolfenstein",
+    "Grosse",
+];
+
+const EPISODE_NAMES: [&str; 4] = [
+    "Knee-Deep in the Dead",
+    "The Shores of Hell",
+    "Inferno",
+    "Thy Flesh Consumed",
+];
+
+pub fn episode_name(episode: usize) -> Option<&'static str> {
+    EPISODE_NAMES.get(episode.checked_sub(1)?).copied()
+}
+
+pub fn lookup(map: &str) -> Option<&'static str> {
+    let map = map.to_ascii_uppercase();
+    if let Some(number) = map.strip_prefix("MAP") {
+        let number: usize = number.parse().ok()?;
+        return DOOM2_NAMES.get(number.checked_sub(1)?).copied();
+    }
+    let (episode, number) = map.strip_prefix('E')?.split_once('M')?;
+    let episode: usize = episode.parse().ok()?;
+    let number: usize = number.parse().ok()?;
+    DOOM_NAMES
+        .get(episode.checked_sub(1)?)?
+        .get(number.checked_sub(1)?)
+        .copied()
+}
+
+// "E1M1: Hangar", or just the map name when we don't know it
+pub fn display_name(map: &str) -> String {
+    match lookup(map) {
+        Some(name) => format!("{}: {}", map, name),
+        None => map.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_maps_resolve_to_their_names() {
+        assert_eq!(lookup("E1M1"), Some("Hangar"));
+        assert_eq!(lookup("e1m1"), Some("Hangar"));
+        assert_eq!(lookup("MAP01"), Some("Entryway"));
+        assert_eq!(display_name("E1M1"), "E1M1: Hangar");
+        assert_eq!(episode_name(1), Some("Knee-Deep in the Dead"));
+        assert_eq!(episode_name(0), None);
+    }
+
+    #[test]
+    fn unknown_maps_fall_back_to_the_raw_name() {
+        assert_eq!(lookup("E9M9"), None);
+        assert_eq!(lookup("MAP99"), None);
+        assert_eq!(display_name("TESTMAP"), "TESTMAP");
+    }
+}
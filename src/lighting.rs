@@ -0,0 +1,208 @@
+use crate::{level::Level, rng::DoomRng};
+
+// Doom's light thinkers, all timed in 35Hz tics
+const FLICKER_MAX_TIME: i32 = 64;
+const FLICKER_MIN_TIME: i32 = 7;
+const STROBE_BRIGHT: i32 = 5;
+const FAST_DARK: i32 = 15;
+const SLOW_DARK: i32 = 35;
+const GLOW_SPEED: i16 = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub enum LightKind {
+    Flicker,
+    Strobe { bright_time: i32, dark_time: i32 },
+    Glow { rising: bool },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LightEffect {
+    pub sector: usize,
+    pub kind: LightKind,
+    pub max_light: i16,
+    pub min_light: i16,
+    pub light: i16,
+    count: i32,
+}
+
+impl LightEffect {
+    fn tick(&mut self, rng: &mut DoomRng) {
+        match self.kind {
+            LightKind::Flicker => {
+                self.count -= 1;
+                if self.count > 0 {
+                    return;
+                }
+                if self.light == self.max_light {
+                    self.light = self.min_light;
+                    self.count = (i32::from(rng.next()) & FLICKER_MIN_TIME) + 1;
+                } else {
+                    self.light = self.max_light;
+                    self.count = (i32::from(rng.next()) & FLICKER_MAX_TIME) + 1;
+                }
+            }
+            LightKind::Strobe {
+                bright_time,
+                dark_time,
+            } => {
+                self.count -= 1;
+                if self.count > 0 {
+                    return;
+                }
+                if self.light == self.min_light {
+                    self.light = self.max_light;
+                    self.count = bright_time;
+                } else {
+                    self.light = self.min_light;
+                    self.count = dark_time;
+                }
+            }
+            LightKind::Glow { rising } => {
+                if rising {
+                    self.light += GLOW_SPEED;
+                    if self.light >= self.max_light {
+                        self.light -= GLOW_SPEED;
+                        self.kind = LightKind::Glow { rising: false };
+                    }
+                } else {
+                    self.light -= GLOW_SPEED;
+                    if self.light <= self.min_light {
+                        self.light += GLOW_SPEED;
+                        self.kind = LightKind::Glow { rising: true };
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct Lighting {
+    pub effects: Vec<LightEffect>,
+    levels: Vec<i16>,
+    last_tic: Option<u32>,
+    rng: DoomRng,
+}
+
+impl Lighting {
+    pub fn new(level: &Level) -> Self {
+        let mut rng = DoomRng::new();
+        let mut effects = Vec::new();
+        for sector in level.sectors.iter() {
+            let max_light = sector.light_level;
+            let min_light = Self::min_neighbour_light(level, sector.id, max_light);
+            let strobe = |bright_time, dark_time, in_sync: bool, rng: &mut DoomRng| {
+                (
+                    LightKind::Strobe {
+                        bright_time,
+                        dark_time,
+                    },
+                    // A strobe with nothing darker around it blinks to black
+                    if min_light == max_light { 0 } else { min_light },
+                    if in_sync {
+                        1
+                    } else {
+                        (i32::from(rng.next()) & 7) + 1
+                    },
+                )
+            };
+            let (kind, min_light, count) = match sector.special_type {
+                1 => (
+                    LightKind::Flicker,
+                    min_light,
+                    (i32::from(rng.next()) & FLICKER_MAX_TIME) + 1,
+                ),
+                2 | 4 => strobe(STROBE_BRIGHT, FAST_DARK, false, &mut rng),
+                3 => strobe(STROBE_BRIGHT, SLOW_DARK, false, &mut rng),
+                8 => (LightKind::Glow { rising: false }, min_light, 0),
+                12 => strobe(STROBE_BRIGHT, SLOW_DARK, true, &mut rng),
+                13 => strobe(STROBE_BRIGHT, FAST_DARK, true, &mut rng),
+                _ => continue,
+            };
+            effects.push(LightEffect {
+                sector: sector.id,
+                kind,
+                max_light,
+                min_light,
+                light: max_light,
+                count,
+            });
+        }
+
+        Lighting {
+            effects,
+            levels: level.sectors.iter().map(|s| s.light_level).collect(),
+            last_tic: None,
+            rng,
+        }
+    }
+
+    fn min_neighbour_light(level: &Level, sector: usize, max_light: i16) -> i16 {
+        let mut min_light = max_light;
+        for linedef in level.linedefs.iter() {
+            if let (Some(right), Some(left)) = (&linedef.right_sidedef, &linedef.left_sidedef) {
+                let neighbour = if right.sector.id == sector {
+                    &left.sector
+                } else if left.sector.id == sector {
+                    &right.sector
+                } else {
+                    continue;
+                };
+                if neighbour.light_level < min_light {
+                    min_light = neighbour.light_level;
+                }
+            }
+        }
+        min_light
+    }
+
+    // Returns whether any sector's light level changed
+    pub fn update(&mut self, tic: u32) -> bool {
+        let last_tic = match self.last_tic {
+            Some(last_tic) => last_tic,
+            None => tic,
+        };
+        for _ in last_tic..tic {
+            for effect in self.effects.iter_mut() {
+                effect.tick(&mut self.rng);
+            }
+        }
+        self.last_tic = Some(tic);
+        let mut changed = false;
+        for effect in self.effects.iter() {
+            changed |= self.levels[effect.sector] != effect.light;
+            self.levels[effect.sector] = effect.light;
+        }
+        changed
+    }
+
+    pub fn light_level(&self, sector: usize) -> i16 {
+        self.levels[sector]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{level_data, MiniLevel};
+
+    #[test]
+    fn slow_blink_alternates_between_own_and_darkest_neighbour_light() {
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 2, 128));
+        data.sectors[0].special_type = 12;
+        data.sectors[1].light_level = 96;
+        let mut lighting = Lighting::new(&Level::new(&data));
+        lighting.update(0);
+        assert_eq!(lighting.light_level(0), 160);
+
+        // Synchronised blinks go dark on the first tic, stay dark for SLOW_DARK tics
+        // and bright for STROBE_BRIGHT
+        let dark = 1..=SLOW_DARK as u32;
+        let bright = dark.end() + 1..=dark.end() + STROBE_BRIGHT as u32;
+        for tic in 1..=*bright.end() + 1 {
+            lighting.update(tic);
+            let expected = if bright.contains(&tic) { 160 } else { 96 };
+            assert_eq!(lighting.light_level(0), expected, "tic {}", tic);
+            assert_eq!(lighting.light_level(1), 96);
+        }
+    }
+}
@@ -1,15 +1,195 @@
+mod camera_path;
+mod color;
+mod config;
+mod dehacked;
+mod framebuffer;
 mod interface;
+mod jukebox;
 mod level;
+mod level_names;
+mod lighting;
+mod math;
+mod mus;
+mod picture;
+mod progression;
 mod renderer;
+mod rng;
+mod strings;
+#[cfg(test)]
+mod test_support;
+mod text;
+mod theme;
+mod thing_info;
 mod wad;
 
+use config::Config;
+use level::{Level, LevelDiff};
 use wad::WadFile;
 
 use crate::interface::Interface;
 
 fn main() {
-    let wad_file = WadFile::load_from("./doom1.wad");
-    println!("# Subsectors: {:?}", wad_file.levels[0].subsectors.len());
-    let mut interface = Interface::new();
-    interface.run(&wad_file);
+    let mut args: Vec<String> = std::env::args().collect();
+    // Like Doom's -file: PWADs loaded over the IWAD in the order given, so it goes last
+    let pwads = match args.iter().position(|arg| arg == "-file") {
+        Some(idx) => args.split_off(idx).split_off(1),
+        None => Vec::new(),
+    };
+    let paths: Vec<&str> = std::iter::once("./doom1.wad")
+        .chain(pwads.iter().map(String::as_str))
+        .collect();
+    let mut wad_file = load_or_exit(&paths);
+    match args.get(1).map(String::as_str) {
+        Some("--flats") => print_flats(&load_level(&mut wad_file, args.get(2))),
+        Some("--textures") => print_textures(&load_level(&mut wad_file, args.get(2))),
+        Some("--light") => print_light(&load_level(&mut wad_file, args.get(2))),
+        Some("--mem") => print_memory(&mut wad_file),
+        Some("--triggers") => print_triggers(&load_level(&mut wad_file, args.get(2))),
+        Some("--diff") => match (args.get(2), args.get(3)) {
+            (Some(old_path), Some(new_path)) => {
+                let old = load_level(&mut load_or_exit(&[old_path]), args.get(4));
+                let new = load_level(&mut load_or_exit(&[new_path]), args.get(4));
+                print_diff(&old.diff(&new));
+            }
+            _ => eprintln!("Usage: --diff OLD.WAD NEW.WAD MAP"),
+        },
+        Some("--bsp-dot") => {
+            let dot = load_level(&mut wad_file, args.get(2)).bsp_to_dot();
+            match args.get(3) {
+                Some(path) => std::fs::write(path, dot).unwrap(),
+                None => print!("{}", dot),
+            }
+        }
+        _ => {
+            println!("# Subsectors: {:?}", wad_file.level(0).subsectors.len());
+            let mut interface = Interface::new(Config::load(Config::PATH));
+            interface.run(&mut wad_file);
+        }
+    }
+}
+
+fn load_or_exit(paths: &[&str]) -> WadFile {
+    WadFile::load_stack(paths).unwrap_or_else(|error| {
+        eprintln!("Couldn't load WADs: {}", error);
+        std::process::exit(1);
+    })
+}
+
+fn load_level(wad_file: &mut WadFile, name: Option<&String>) -> Level {
+    let name = name.map(String::as_str).unwrap_or("");
+    match wad_file.level_index(name) {
+        Some(idx) => Level::new(wad_file.level(idx)),
+        None => {
+            eprintln!("No level named '{}' in this WAD", name);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_diff(diff: &LevelDiff) {
+    for (label, elements) in [
+        ("Vertexes", &diff.vertexes),
+        ("Linedefs", &diff.linedefs),
+        ("Sectors", &diff.sectors),
+    ] {
+        println!(
+            "{:<9} +{} -{} ~{}",
+            label, elements.added, elements.removed, elements.changed
+        );
+        for (field, count) in elements.field_changes.iter() {
+            println!("    {:<15} {}", field, count);
+        }
+    }
+}
+
+fn print_memory(wad_file: &mut WadFile) {
+    // Parse everything so the level figures cover the whole WAD
+    wad_file.parse_levels(|done, total| eprint!("\rParsing levels {}/{}", done, total));
+    eprintln!();
+    let report = wad_file.memory_report();
+    for (label, size) in [
+        ("Raw bytes", report.raw_bytes),
+        ("Directory", report.directory),
+        ("Levels", report.levels),
+        ("Textures", report.textures),
+        ("Flats", report.flats),
+        ("Total", report.total()),
+    ] {
+        println!("{:<10} {:>10} bytes", label, size);
+    }
+    wad_file.drop_raw_bytes();
+    println!(
+        "{:<10} {:>10} bytes without raw bytes",
+        "Total",
+        wad_file.memory_report().total()
+    );
+}
+
+fn print_flats(level: &Level) {
+    let mut usage: Vec<(String, Vec<usize>)> = level.flat_usage().into_iter().collect();
+    usage.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+    for (flat, sectors) in usage {
+        println!(
+            "{:<8} {:>4} {} {:?}",
+            flat,
+            sectors.len(),
+            "#".repeat(sectors.len()),
+            sectors
+        );
+    }
+}
+
+fn print_triggers(level: &Level) {
+    let mut tags: Vec<usize> = level.sectors.iter().map(|s| s.tag).collect();
+    tags.sort_unstable();
+    tags.dedup();
+    for tag in tags.into_iter().filter(|&tag| tag != 0) {
+        let sectors: Vec<usize> = level
+            .sectors
+            .iter()
+            .filter(|s| s.tag == tag)
+            .map(|s| s.id)
+            .collect();
+        println!("Tag {:<4} sectors {:?}", tag, sectors);
+        for idx in level.sector_triggers(tag) {
+            let linedef = &level.linedefs[idx];
+            let (x, y) = linedef.midpoint(level);
+            println!(
+                "    linedef {:>4} special {:<3} at ({}, {}), {} long",
+                idx,
+                linedef.special_type,
+                x,
+                y,
+                linedef.length(level).round()
+            );
+        }
+        // Where a teleport line with this tag would send the player
+        for (_, (x, y)) in level
+            .teleport_destinations()
+            .into_iter()
+            .filter(|(sector, _)| sectors.contains(sector))
+        {
+            println!("    teleport destination ({}, {})", x, y);
+        }
+    }
+}
+
+fn print_light(level: &Level) {
+    for (bin, count) in level.light_histogram().iter().enumerate() {
+        println!(
+            "{:>3}-{:<3} {:>4} {}",
+            bin * 16,
+            bin * 16 + 15,
+            count,
+            "#".repeat(*count)
+        );
+    }
+}
+
+fn print_textures(level: &Level) {
+    let mut usage: Vec<(String, usize)> = level.texture_usage().into_iter().collect();
+    usage.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    for (texture, count) in usage {
+        println!("{:<8} {:>4} {}", texture, count, "#".repeat(count));
+    }
 }
@@ -0,0 +1,145 @@
+use std::f32::consts;
+
+// Wraps any angle into [0, 2π). rem_euclid alone can round a tiny negative angle up to
+// exactly 2π, which would fail a `< 2π` check, so that case folds back to 0.
+pub fn normalize_angle(angle: f32) -> f32 {
+    let wrapped = angle.rem_euclid(2. * consts::PI);
+    if wrapped >= 2. * consts::PI {
+        0.
+    } else {
+        wrapped
+    }
+}
+
+// Where segment p1-p2 meets segment p3-p4, as (t, point) with t the fraction of the way
+// from p1 to p2. Touching at an end point counts; parallel and collinear segments don't.
+pub fn seg_intersect(
+    p1: (f32, f32),
+    p2: (f32, f32),
+    p3: (f32, f32),
+    p4: (f32, f32),
+) -> Option<(f32, (f32, f32))> {
+    let (d1x, d1y) = (p2.0 - p1.0, p2.1 - p1.1);
+    let (d2x, d2y) = (p4.0 - p3.0, p4.1 - p3.1);
+    let denominator = d1x * d2y - d1y * d2x;
+    if denominator == 0. {
+        return None;
+    }
+    let (ox, oy) = (p3.0 - p1.0, p3.1 - p1.1);
+    let t = (ox * d2y - oy * d2x) / denominator;
+    let u = (ox * d1y - oy * d1x) / denominator;
+    if !(0. ..=1.).contains(&t) || !(0. ..=1.).contains(&u) {
+        return None;
+    }
+    Some((t, (p1.0 + t * d1x, p1.1 + t * d1y)))
+}
+
+// The part of the infinite line through origin along delta that lies inside the box from
+// min to max, or None if it misses the box (or delta is zero)
+pub fn extend_line(
+    origin: (f32, f32),
+    delta: (f32, f32),
+    min: (f32, f32),
+    max: (f32, f32),
+) -> Option<((f32, f32), (f32, f32))> {
+    if delta == (0., 0.) {
+        return None;
+    }
+    let mut t_min = f32::NEG_INFINITY;
+    let mut t_max = f32::INFINITY;
+    for (o, d, lo, hi) in [
+        (origin.0, delta.0, min.0, max.0),
+        (origin.1, delta.1, min.1, max.1),
+    ] {
+        if d == 0. {
+            if o < lo || o > hi {
+                return None;
+            }
+            continue;
+        }
+        let (t1, t2) = ((lo - o) / d, (hi - o) / d);
+        t_min = t_min.max(t1.min(t2));
+        t_max = t_max.min(t1.max(t2));
+    }
+    if t_min > t_max {
+        return None;
+    }
+    let at = |t: f32| (origin.0 + t * delta.0, origin.1 + t * delta.1);
+    Some((at(t_min), at(t_max)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angles_wrap_into_one_turn() {
+        let turn = 2. * consts::PI;
+        for (angle, wrapped) in [
+            (0., 0.),
+            (1., 1.),
+            (-1., turn - 1.),
+            (turn, 0.),
+            (turn + 1., 1.),
+            (-turn - 1., turn - 1.),
+            (10. * turn + 0.5, 0.5),
+            (-7. * turn + 0.5, 0.5),
+        ] {
+            assert!(
+                (normalize_angle(angle) - wrapped).abs() < 1e-4,
+                "{} wrapped to {}",
+                angle,
+                normalize_angle(angle)
+            );
+        }
+        // Too small a step below zero to wrap to anything under 2π
+        assert_eq!(normalize_angle(-f32::EPSILON * 1e-3), 0.);
+        assert!((0. ..turn).contains(&normalize_angle(-1e-7)));
+    }
+
+    #[test]
+    fn segment_intersections() {
+        // Crossing in the middle of both
+        let (t, point) = seg_intersect((0., 0.), (10., 10.), (0., 10.), (10., 0.)).unwrap();
+        assert_eq!((t, point), (0.5, (5., 5.)));
+        // Crossing a quarter of the way along the first
+        let (t, point) = seg_intersect((0., 0.), (8., 0.), (2., -1.), (2., 1.)).unwrap();
+        assert_eq!((t, point), (0.25, (2., 0.)));
+        // Touching at an end point
+        let (t, point) = seg_intersect((0., 0.), (4., 0.), (4., 0.), (4., 5.)).unwrap();
+        assert_eq!((t, point), (1., (4., 0.)));
+        // Parallel, collinear and overlapping, and apart
+        assert_eq!(seg_intersect((0., 0.), (4., 0.), (0., 1.), (4., 1.)), None);
+        assert_eq!(seg_intersect((0., 0.), (4., 0.), (2., 0.), (6., 0.)), None);
+        assert_eq!(seg_intersect((0., 0.), (4., 0.), (5., -1.), (5., 1.)), None);
+        // The lines cross, but past the end of the second segment
+        assert_eq!(seg_intersect((0., 0.), (4., 0.), (2., 1.), (2., 3.)), None);
+    }
+
+    #[test]
+    fn partitions_extend_to_the_box_edges() {
+        let (min, max) = ((0., 0.), (512., 256.));
+        // Vertical and horizontal partitions run edge to edge whichever way they point
+        assert_eq!(
+            extend_line((128., 64.), (0., 64.), min, max),
+            Some(((128., 0.), (128., 256.)))
+        );
+        assert_eq!(
+            extend_line((128., 64.), (-32., 0.), min, max),
+            Some(((512., 64.), (0., 64.)))
+        );
+        // A diagonal from a corner leaves through the top edge
+        assert_eq!(
+            extend_line((0., 0.), (1., 1.), min, max),
+            Some(((0., 0.), (256., 256.)))
+        );
+        // An origin outside the box still works if the line crosses it
+        assert_eq!(
+            extend_line((-100., 100.), (1., 0.), min, max),
+            Some(((0., 100.), (512., 100.)))
+        );
+        assert_eq!(extend_line((600., 0.), (0., 1.), min, max), None);
+        assert_eq!(extend_line((0., 300.), (1., 1.), min, max), None);
+        assert_eq!(extend_line((10., 10.), (0., 0.), min, max), None);
+    }
+}
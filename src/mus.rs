@@ -0,0 +1,216 @@
+// Doom's MUS music format, converted to a standard MIDI file so an ordinary MIDI player can
+// play it. MUS is MIDI squeezed down: one byte per event holds its type and channel, the
+// last event before a pause says so with its top bit, and time runs at 140 ticks a second.
+
+const MIDI_PERCUSSION: u8 = 9;
+const MUS_PERCUSSION: u8 = 15;
+
+// MIDI controllers for MUS controllers 1-14. Controller 0 is an instrument change instead.
+const CONTROLLERS: [u8; 14] = [
+    0x20, // Bank select
+    0x01, // Modulation
+    0x07, // Volume
+    0x0A, // Pan
+    0x0B, // Expression
+    0x5B, // Reverb
+    0x5D, // Chorus
+    0x40, // Sustain pedal
+    0x43, // Soft pedal
+    0x78, // All sounds off
+    0x7B, // All notes off
+    0x7E, // Mono
+    0x7F, // Poly
+    0x79, // Reset all controllers
+];
+
+// Converts a MUS lump to a single-track MIDI file, or None if it isn't MUS or runs short
+pub fn to_midi(mus: &[u8]) -> Option<Vec<u8>> {
+    if mus.get(..4)? != b"MUS\x1A" {
+        return None;
+    }
+    let score_start = usize::from(u16::from_le_bytes([*mus.get(6)?, *mus.get(7)?]));
+    let mut bytes = mus.get(score_start..)?.iter().copied();
+    let mut track = Vec::new();
+    let mut channels: [Option<u8>; 16] = [None; 16];
+    let mut velocities = [127u8; 16];
+    let mut delay = 0;
+
+    loop {
+        let descriptor = bytes.next()?;
+        if descriptor & 0x70 == 0x60 {
+            break; // End of the score
+        }
+        let mus_channel = descriptor & 0x0F;
+        let channel = match channels[usize::from(mus_channel)] {
+            Some(channel) => channel,
+            None => {
+                let channel = allocate_channel(&channels, mus_channel)?;
+                channels[usize::from(mus_channel)] = Some(channel);
+                channel
+            }
+        };
+        let event = match descriptor & 0x70 {
+            0x00 => Some([0x80 | channel, bytes.next()? & 0x7F, 0].to_vec()),
+            0x10 => {
+                let note = bytes.next()?;
+                if note & 0x80 != 0 {
+                    velocities[usize::from(mus_channel)] = bytes.next()? & 0x7F;
+                }
+                let velocity = velocities[usize::from(mus_channel)];
+                Some([0x90 | channel, note & 0x7F, velocity].to_vec())
+            }
+            0x20 => {
+                // MUS bends in 256 steps with 128 centred, MIDI in 16384 with 8192 centred
+                let bend = u16::from(bytes.next()?) * 64;
+                Some([0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8].to_vec())
+            }
+            0x30 => {
+                let controller = bytes.next()?;
+                match controller {
+                    10..=14 => {
+                        Some([0xB0 | channel, CONTROLLERS[controller as usize - 1], 0].to_vec())
+                    }
+                    _ => None,
+                }
+            }
+            0x40 => {
+                let (controller, value) = (bytes.next()?, bytes.next()?.min(127));
+                match controller {
+                    0 => Some([0xC0 | channel, value].to_vec()),
+                    1..=9 => {
+                        Some([0xB0 | channel, CONTROLLERS[controller as usize - 1], value].to_vec())
+                    }
+                    _ => None,
+                }
+            }
+            0x50 => None, // End of a measure, which MIDI doesn't mark
+            _ => return None,
+        };
+        if let Some(event) = event {
+            push_varint(&mut track, delay);
+            track.extend(event);
+            delay = 0;
+        }
+        if descriptor & 0x80 != 0 {
+            let mut pause = 0;
+            loop {
+                let byte = bytes.next()?;
+                pause = pause * 128 + u32::from(byte & 0x7F);
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            delay += pause;
+        }
+    }
+    push_varint(&mut track, delay);
+    track.extend([0xFF, 0x2F, 0x00]);
+
+    // Format 0 with one track, at 70 ticks a quarter note. MIDI's default of 120 beats a
+    // minute makes that MUS's 140 ticks a second.
+    let mut midi = b"MThd".to_vec();
+    midi.extend(6u32.to_be_bytes());
+    midi.extend([0, 0, 0, 1, 0, 70]);
+    midi.extend(b"MTrk");
+    midi.extend((track.len() as u32).to_be_bytes());
+    midi.extend(track);
+    Some(midi)
+}
+
+// MUS percussion is on channel 15 and MIDI's on 9, so the other MUS channels take the
+// MIDI channels in the order they're first used, skipping 9
+fn allocate_channel(channels: &[Option<u8>; 16], mus_channel: u8) -> Option<u8> {
+    if mus_channel == MUS_PERCUSSION {
+        return Some(MIDI_PERCUSSION);
+    }
+    (0..16).find(|&channel| channel != MIDI_PERCUSSION && !channels.contains(&Some(channel)))
+}
+
+// MIDI's variable-length numbers: seven bits a byte, most significant first, with the top
+// bit set on all but the last
+fn push_varint(bytes: &mut Vec<u8>, value: u32) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mus(score: &[u8]) -> Vec<u8> {
+        // Header with no instruments, so the score starts straight after it at byte 16
+        let mut mus = b"MUS\x1A".to_vec();
+        mus.extend((score.len() as u16).to_le_bytes());
+        mus.extend(16u16.to_le_bytes());
+        mus.extend([1, 0, 0, 0, 0, 0, 0, 0]);
+        mus.extend(score);
+        mus
+    }
+
+    fn track(midi: &[u8]) -> &[u8] {
+        assert_eq!(&midi[..14], b"MThd\0\0\0\x06\0\0\0\x01\0\x46");
+        assert_eq!(&midi[14..18], b"MTrk");
+        let length = u32::from_be_bytes(midi[18..22].try_into().unwrap()) as usize;
+        assert_eq!(midi.len(), 22 + length);
+        &midi[22..]
+    }
+
+    #[test]
+    fn notes_carry_their_volume_and_delay() {
+        // Play middle C (60, with the top bit set for a volume) at 100 and wait 128 ticks,
+        // release it, then play E with the volume kept from before
+        let score = [0x90, 0xBC, 100, 0x81, 0x00, 0x00, 60, 0x10, 64, 0x60];
+        let midi = to_midi(&mus(&score)).unwrap();
+        assert_eq!(
+            track(&midi),
+            [
+                0x00, 0x90, 60, 100, // Note on
+                0x81, 0x00, 0x80, 60, 0, // 128 ticks later, note off
+                0x00, 0x90, 64, 100, // Note on, same velocity
+                0x00, 0xFF, 0x2F, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn percussion_moves_to_channel_nine_and_others_fill_in_order() {
+        let midi = to_midi(&mus(&[
+            0x4F, 0, 35, // Percussion: program change
+            0x43, 3, 90, // MUS channel 3 is the first melodic one, so MIDI channel 0
+            0x25, 128, // Channel 5 gets MIDI channel 1, and a centred pitch bend
+            0x33, 11, // All notes off on MUS channel 3
+            0x60,
+        ]))
+        .unwrap();
+        assert_eq!(
+            track(&midi),
+            [
+                0x00, 0xC9, 35, // Program change on channel 9
+                0x00, 0xB0, 0x07, 90, // Volume on channel 0
+                0x00, 0xE1, 0x00, 0x40, // Bend of 8192 on channel 1
+                0x00, 0xB0, 0x7B, 0, // All notes off
+                0x00, 0xFF, 0x2F, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_or_short_lumps_are_rejected() {
+        assert!(to_midi(b"MThd").is_none());
+        assert!(to_midi(&mus(&[0x10])).is_none());
+        // No score end
+        assert!(to_midi(&mus(&[0x10, 60])).is_none());
+    }
+
+    #[test]
+    fn doom1_music_converts() {
+        let wad = crate::wad::WadFile::load_from(concat!(env!("CARGO_MANIFEST_DIR"), "/doom1.wad"));
+        let midi = to_midi(wad.lump_bytes("D_E1M1").unwrap()).unwrap();
+        assert!(track(&midi).ends_with(&[0xFF, 0x2F, 0x00]));
+    }
+}
@@ -0,0 +1,180 @@
+// Doom's picture format (patches, sprites) and the palette its pixels index into
+
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pub colors: Vec<[u8; 3]>, // 256 RGB triples
+}
+
+impl Palette {
+    // The first of PLAYPAL's 14 palettes is the normal one
+    pub fn from_playpal(bytes: &[u8]) -> Option<Self> {
+        let colors = bytes
+            .get(..768)?
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        Some(Palette { colors })
+    }
+
+    pub fn rgb(&self, index: u8) -> [u8; 3] {
+        self.colors[index as usize]
+    }
+
+    // The palette entry closest to an arbitrary colour
+    pub fn nearest(&self, rgb: [u8; 3]) -> u8 {
+        let distance = |color: &[u8; 3]| -> i32 {
+            (0..3)
+                .map(|c| (i32::from(color[c]) - i32::from(rgb[c])).pow(2))
+                .sum()
+        };
+        (0..self.colors.len())
+            .min_by_key(|&index| distance(&self.colors[index]))
+            .unwrap_or(0) as u8
+    }
+}
+
+// Palette remaps, one per light level from brightest to darkest. The IWAD's COLORMAP has
+// 34: 32 light levels, the invulnerability effect and one all black.
+#[derive(Clone, Debug)]
+pub struct Colormap {
+    pub name: String,
+    pub maps: Vec<[u8; 256]>,
+}
+
+impl Colormap {
+    pub fn from_lump(name: &str, bytes: &[u8]) -> Option<Self> {
+        let maps: Vec<[u8; 256]> = bytes
+            .chunks_exact(256)
+            .map(|map| map.try_into().unwrap())
+            .collect();
+        if maps.is_empty() {
+            return None;
+        }
+        Some(Colormap {
+            name: name.to_string(),
+            maps,
+        })
+    }
+}
+
+// Looks a palette index up in one row of a colormap, row 0 being full brightness
+pub fn map_color(
+    palette: &Palette,
+    colormap: &Colormap,
+    color_index: u8,
+    light_row: usize,
+) -> (u8, u8, u8) {
+    let map = &colormap.maps[light_row.min(colormap.maps.len() - 1)];
+    let [r, g, b] = palette.rgb(map[color_index as usize]);
+    (r, g, b)
+}
+
+#[derive(Clone, Debug)]
+pub struct Picture {
+    pub width: usize,
+    pub height: usize,
+    pub left_offset: i16,
+    pub top_offset: i16,
+    pub pixels: Vec<Option<u8>>, // Row by row, None where transparent
+}
+
+impl Picture {
+    pub fn new(width: usize, height: usize) -> Self {
+        Picture {
+            width,
+            height,
+            left_offset: 0,
+            top_offset: 0,
+            pixels: vec![None; width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        self.pixels[y * self.width + x]
+    }
+
+    // Copies the opaque pixels of other over this picture with other's top left at (x, y)
+    pub fn blit(&mut self, other: &Picture, x: i32, y: i32) {
+        for src_y in 0..other.height {
+            let dest_y = y + src_y as i32;
+            if dest_y < 0 || dest_y >= self.height as i32 {
+                continue;
+            }
+            for src_x in 0..other.width {
+                let dest_x = x + src_x as i32;
+                if dest_x < 0 || dest_x >= self.width as i32 {
+                    continue;
+                }
+                if let Some(index) = other.pixel(src_x, src_y) {
+                    self.pixels[dest_y as usize * self.width + dest_x as usize] = Some(index);
+                }
+            }
+        }
+    }
+}
+
+fn get_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([
+        *bytes.get(offset)?,
+        *bytes.get(offset + 1)?,
+    ]))
+}
+
+// Header, one offset per column, then each column is a list of posts:
+// top row, pixel count, a padding byte, the pixels, another padding byte. 0xFF ends a column.
+pub fn decode_patch(bytes: &[u8]) -> Option<Picture> {
+    let width = get_u16(bytes, 0)? as usize;
+    let height = get_u16(bytes, 2)? as usize;
+    let mut picture = Picture::new(width, height);
+    picture.left_offset = get_u16(bytes, 4)? as i16;
+    picture.top_offset = get_u16(bytes, 6)? as i16;
+
+    for column in 0..width {
+        let column_offset = bytes.get(8 + column * 4..12 + column * 4)?;
+        let mut offset = u32::from_le_bytes(column_offset.try_into().ok()?) as usize;
+        loop {
+            let top = *bytes.get(offset)?;
+            if top == 0xFF {
+                break;
+            }
+            let length = *bytes.get(offset + 1)? as usize;
+            let post = bytes.get(offset + 3..offset + 3 + length)?;
+            for (row, index) in post.iter().enumerate() {
+                let y = top as usize + row;
+                if y < height {
+                    picture.pixels[y * width + column] = Some(*index);
+                }
+            }
+            offset += length + 4;
+        }
+    }
+    Some(picture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wad::WadFile;
+
+    #[test]
+    fn colormap_rows_darken_towards_31() {
+        let wad = WadFile::load_from(concat!(env!("CARGO_MANIFEST_DIR"), "/doom1.wad"));
+        let (palette, colormap) = (wad.palette().unwrap(), wad.colormap().unwrap());
+        let brightness = |(r, g, b): (u8, u8, u8)| u32::from(r) + u32::from(g) + u32::from(b);
+        // 176 is a bright red, 4 is white
+        for color_index in [176, 4] {
+            let row = |light_row| map_color(&palette, &colormap, color_index, light_row);
+            let [r, g, b] = palette.rgb(color_index);
+            assert_eq!(row(0), (r, g, b));
+            assert!(brightness(row(16)) < brightness(row(0)));
+            assert!(brightness(row(31)) < brightness(row(16)));
+            let [r, g, b] = palette.rgb(colormap.maps[31][color_index as usize]);
+            assert_eq!(row(31), (r, g, b));
+        }
+        // Rows past the end stick at the last, the all-black map (32 is invulnerability)
+        assert_eq!(
+            map_color(&palette, &colormap, 176, 99),
+            map_color(&palette, &colormap, 176, 33)
+        );
+    }
+}
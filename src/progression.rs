@@ -0,0 +1,68 @@
+// Which map follows which, as the games decide at the intermission. In Doom a secret exit
+// from any map goes to that episode's M9, and M9 returns to the map after the one with the
+// real secret exit. Doom II only has secret exits on MAP15 and MAP31; elsewhere they act
+// like normal ones.
+
+// The map with the secret exit in each episode
+const DOOM_SECRET_EXITS: [usize; 4] = [3, 5, 6, 2];
+
+pub fn next_level(current: &str, took_secret_exit: bool) -> Option<String> {
+    let current = current.to_ascii_uppercase();
+    if let Some(number) = current.strip_prefix("MAP") {
+        let number: usize = number.parse().ok()?;
+        let next = match (number, took_secret_exit) {
+            (15, true) => 31,
+            (31, true) => 32,
+            (31 | 32, _) => 16,
+            (30, _) => return None, // The end of the game
+            (1..=29, _) => number + 1,
+            _ => return None,
+        };
+        return Some(format!("MAP{:02}", next));
+    }
+    let (episode, number) = current.strip_prefix('E')?.split_once('M')?;
+    let episode: usize = episode.parse().ok()?;
+    let number: usize = number.parse().ok()?;
+    let secret_exit = *DOOM_SECRET_EXITS.get(episode.checked_sub(1)?)?;
+    let next = match (number, took_secret_exit) {
+        (1..=7, true) => 9,
+        (8, _) => return None, // The end of the episode, which has no secret exit
+        (9, _) => secret_exit + 1,
+        (1..=7, false) => number + 1,
+        _ => return None,
+    };
+    Some(format!("E{}M{}", episode, next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doom2_secret_levels() {
+        let next = |map, secret| next_level(map, secret);
+        assert_eq!(next("MAP15", true).as_deref(), Some("MAP31"));
+        assert_eq!(next("MAP15", false).as_deref(), Some("MAP16"));
+        // MAP16 has no secret exit, so taking one is just the normal exit
+        assert_eq!(next("MAP16", true).as_deref(), Some("MAP17"));
+        assert_eq!(next("MAP31", true).as_deref(), Some("MAP32"));
+        assert_eq!(next("MAP31", false).as_deref(), Some("MAP16"));
+        assert_eq!(next("MAP32", true).as_deref(), Some("MAP16"));
+        assert_eq!(next("map01", false).as_deref(), Some("MAP02"));
+        assert_eq!(next("MAP30", false), None);
+    }
+
+    #[test]
+    fn doom_secret_levels_return_after_the_secret_exit() {
+        let next = |map, secret| next_level(map, secret);
+        assert_eq!(next("E1M3", true).as_deref(), Some("E1M9"));
+        assert_eq!(next("E1M9", false).as_deref(), Some("E1M4"));
+        assert_eq!(next("E2M5", true).as_deref(), Some("E2M9"));
+        assert_eq!(next("E2M9", false).as_deref(), Some("E2M6"));
+        assert_eq!(next("E4M9", false).as_deref(), Some("E4M3"));
+        assert_eq!(next("E1M8", false), None);
+        assert_eq!(next("E1M8", true), None);
+        assert_eq!(next("E5M1", false), None);
+        assert_eq!(next("TITLEPIC", false), None);
+    }
+}
@@ -1,38 +1,301 @@
-use std::f32::consts;
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    f32::consts,
+    rc::Rc,
+};
 
 use sdl2::{
     pixels::Color,
     rect::{Point, Rect},
-    render::WindowCanvas,
+    render::{BlendMode, WindowCanvas},
 };
 
 use crate::{
+    color,
+    config::Config,
+    framebuffer::{FrameTexture, Framebuffer},
     interface::{Interface, Player},
-    level::{self, Child, Level, Node},
+    level::{self, Child, Level, LineClass, Linedef, Node},
+    lighting::Lighting,
+    math::{self, normalize_angle},
+    picture::{self, Colormap, Palette, Picture},
+    text,
+    theme::Theme,
+    thing_info::{self, KeyColor, ThingCategory},
     wad::*,
 };
 
+// Interface toggles that change what the automap overlays
+pub struct AutomapOptions<'a> {
+    pub bsp_render: Option<u32>,  // BSP search depth
+    pub bsp_subtree: Option<i16>, // Node whose subtree is drawn instead of the player's path
+    pub thing_filter: &'a HashSet<ThingCategory>,
+    pub key_locator: bool,
+    pub marks: &'a [(f32, f32)],
+    pub tic: u32, // For animating markers
+    pub reveal: AutomapReveal,
+    pub visited: &'a HashSet<usize>, // Subsectors the player has been in
+}
+
+// Whether the automap shows the whole level or, like the game, only where you've been
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutomapReveal {
+    All,
+    Explored,
+}
+
+// The children find_partial reaches at each depth below the root for one point, kept
+// between frames for stepping through the depths
+struct SearchPath {
+    root: Rc<Node>, // Which level it was found in
+    position: (i16, i16),
+    path: Vec<Child>,
+}
+
 pub struct Renderer {
+    width: u32, // Logical screen size, drawn multiplier times larger in the window
+    height: u32,
+    multiplier: u32,
     x_offset: i16,
     y_offset: i16,
     level_width: i16,
     level_height: i16,
     x_multiplier: f32,
     y_multiplier: f32,
+    zoom: f32,
+    pan_x: f32,
+    pan_y: f32,
+    render_width: u32, // Internal resolution of the 3D view
+    render_height: u32,
+    aspect_ratio: f32,
+    fov: f32,        // Horizontal field of view in radians
+    wireframe: bool, // Outline the 3D view's walls instead of filling them
+    follow: bool,    // Keep the automap centred on the player
+    fov_fill: bool,
+    show_grid: bool,
+    grid_labels: bool, // World coordinates at some grid intersections
+    height_legend: bool,
+    flip_y: bool,        // Screen y runs down, so level y is flipped to keep north up
+    sector_colors: bool, // Tint the visible subsectors by sector instead of all yellow
+    partitions: bool,
+    location_path: bool,
+    anti_alias: bool,      // Smooth automap lines in software instead of SDL's
+    bsp_index_order: bool, // Draw subsectors by number rather than walking the BSP
+    reveal_secrets: bool,  // Show secret lines as what they are, like a cheat
+    theme: Theme,
+    palette: Option<Palette>,
+    colormap: Option<Colormap>, // The IWAD's COLORMAP, used for shading when present
+    colormaps: Vec<Colormap>,   // Boom colormaps from the WAD
+    sector_colormaps: HashMap<usize, usize>, // Sector to colormap index, from transfer lines
+    dirty_tracking: bool,       // Skip drawing frames identical to the one on screen
+    drawn_view: Option<(Player, f32, f32, f32)>, // Player, zoom and pan of the frame on screen
+    search_path: Option<SearchPath>,
 }
 
 impl Renderer {
-    pub fn new() -> Self {
+    const PLAYER_VIEW_HEIGHT: f32 = 41.;
+    pub const MAX_PITCH: f32 = 0.5; // Slope of the steepest look up/down
+    const GRID_SPACING: i16 = 128;
+
+    pub fn new(config: &Config) -> Self {
         Renderer {
+            width: config.width,
+            height: config.height,
+            multiplier: config.multiplier,
             x_offset: 0,
             y_offset: 0,
-            level_width: Interface::WIDTH as i16,
-            level_height: Interface::HEIGHT as i16,
+            level_width: config.width as i16,
+            level_height: config.height as i16,
             x_multiplier: 1.0,
             y_multiplier: 1.0,
+            zoom: 1.0,
+            pan_x: 0.0,
+            pan_y: 0.0,
+            render_width: config.render_width,
+            render_height: config.render_height,
+            aspect_ratio: config.aspect_ratio,
+            fov: config.fov.to_radians(),
+            wireframe: false,
+            follow: config.follow,
+            fov_fill: config.fov_fill,
+            show_grid: config.show_grid,
+            grid_labels: config.grid_labels,
+            height_legend: config.height_legend,
+            flip_y: config.flip_y,
+            sector_colors: config.sector_colors,
+            partitions: false,
+            location_path: false,
+            anti_alias: false,
+            bsp_index_order: false,
+            reveal_secrets: false,
+            theme: Theme::named(&config.theme).unwrap_or_else(Theme::classic),
+            palette: None,
+            colormap: None,
+            colormaps: Vec::new(),
+            sector_colormaps: HashMap::new(),
+            dirty_tracking: false,
+            drawn_view: None,
+            search_path: None,
+        }
+    }
+
+    // Copies the toggles back for saving
+    pub fn store_settings(&self, config: &mut Config) {
+        config.follow = self.follow;
+        config.fov_fill = self.fov_fill;
+        config.show_grid = self.show_grid;
+        config.grid_labels = self.grid_labels;
+        config.height_legend = self.height_legend;
+        config.flip_y = self.flip_y;
+        config.sector_colors = self.sector_colors;
+        config.theme = self.theme.name.to_string();
+    }
+
+    pub fn reset_view(&mut self, level: &Level, center_on: Option<&Player>) {
+        self.find_bounds(level);
+        self.zoom = 1.0;
+        self.pan_x = 0.0;
+        self.pan_y = 0.0;
+        if let Some(player) = center_on {
+            self.center_on(player.x, player.y);
+        }
+    }
+
+    pub fn center_on(&mut self, x: f32, y: f32) {
+        self.pan_x = x - (f32::from(self.x_offset) + f32::from(self.level_width) / 2.);
+        self.pan_y = y - (f32::from(self.y_offset) + f32::from(self.level_height) / 2.);
+    }
+
+    // In follow mode the view pans to keep the player in the middle
+    fn follow_player(&mut self, player: &Player) {
+        if self.follow {
+            self.center_on(player.x, player.y);
         }
     }
 
+    pub fn toggle_wireframe(&mut self) {
+        self.wireframe = !self.wireframe;
+        self.invalidate();
+    }
+
+    pub fn toggle_follow(&mut self) {
+        self.follow = !self.follow;
+        self.invalidate();
+    }
+
+    pub fn toggle_fov_fill(&mut self) {
+        self.fov_fill = !self.fov_fill;
+        self.invalidate();
+    }
+
+    pub fn toggle_grid(&mut self) {
+        self.show_grid = !self.show_grid;
+        self.invalidate();
+    }
+
+    pub fn toggle_grid_labels(&mut self) {
+        self.grid_labels = !self.grid_labels;
+        self.invalidate();
+    }
+
+    pub fn toggle_height_legend(&mut self) {
+        self.height_legend = !self.height_legend;
+        self.invalidate();
+    }
+
+    pub fn set_colormaps(
+        &mut self,
+        palette: Option<Palette>,
+        colormap: Option<Colormap>,
+        colormaps: Vec<Colormap>,
+    ) {
+        self.palette = palette;
+        self.colormap = colormap;
+        self.colormaps = colormaps;
+    }
+
+    // Works out which sectors the level's transfer lines give their own colormap
+    pub fn load_level_colormaps(&mut self, level: &Level) {
+        self.sector_colormaps.clear();
+        for (sector, name) in level.colormap_transfers() {
+            if let Some(index) = self
+                .colormaps
+                .iter()
+                .position(|colormap| colormap.name.eq_ignore_ascii_case(&name))
+            {
+                self.sector_colormaps.insert(sector, index);
+            }
+        }
+    }
+
+    pub fn toggle_flip_y(&mut self) {
+        self.flip_y = !self.flip_y;
+        self.invalidate();
+    }
+
+    pub fn toggle_sector_colors(&mut self) {
+        self.sector_colors = !self.sector_colors;
+        self.invalidate();
+    }
+
+    pub fn toggle_partitions(&mut self) {
+        self.partitions = !self.partitions;
+        self.invalidate();
+    }
+
+    pub fn toggle_location_path(&mut self) {
+        self.location_path = !self.location_path;
+        self.invalidate();
+    }
+
+    pub fn toggle_anti_alias(&mut self) {
+        self.anti_alias = !self.anti_alias;
+        self.invalidate();
+    }
+
+    pub fn toggle_bsp_index_order(&mut self) {
+        self.bsp_index_order = !self.bsp_index_order;
+        self.invalidate();
+    }
+
+    pub fn toggle_reveal_secrets(&mut self) {
+        self.reveal_secrets = !self.reveal_secrets;
+        self.invalidate();
+    }
+
+    pub fn next_theme(&mut self) {
+        let presets = Theme::presets();
+        let current = presets.iter().position(|theme| *theme == self.theme);
+        let next = current.map_or(0, |idx| (idx + 1) % presets.len());
+        self.theme = presets[next].clone();
+        self.invalidate();
+    }
+
+    pub fn toggle_dirty_tracking(&mut self) {
+        self.dirty_tracking = !self.dirty_tracking;
+        self.invalidate();
+    }
+
+    // Forces the next frame to be drawn
+    pub fn invalidate(&mut self) {
+        self.drawn_view = None;
+    }
+
+    // With dirty tracking on, true when the frame on screen already shows this player and
+    // view, so the caller can skip drawing and presenting. Anything else that changes the
+    // picture has to invalidate.
+    pub fn frame_unchanged(&mut self, player: &Player) -> bool {
+        let view = (*player, self.zoom, self.pan_x, self.pan_y);
+        let unchanged = self.dirty_tracking && self.drawn_view == Some(view);
+        self.drawn_view = Some(view);
+        unchanged
+    }
+
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).clamp(0.25, 16.0);
+    }
+
     pub fn find_bounds(&mut self, level: &Level) {
         let mut min_x = i16::MAX;
         let mut max_x = i16::MIN;
@@ -56,47 +319,189 @@ impl Renderer {
         self.y_offset = min_y;
         self.level_width = max_x - min_x;
         self.level_height = max_y - min_y;
-        self.x_multiplier = ((Interface::WIDTH - 12) * Interface::MULTIPLIER) as f32
-            / f32::from(self.level_width)
-            * 1000.;
-        self.y_multiplier = ((Interface::HEIGHT - 12) * Interface::MULTIPLIER) as f32
-            / self.level_height as f32
-            * 1000.;
+        self.x_multiplier =
+            ((self.width - 12) * self.multiplier) as f32 / f32::from(self.level_width) * 1000.;
+        self.y_multiplier =
+            ((self.height - 12) * self.multiplier) as f32 / self.level_height as f32 * 1000.;
     }
 
     pub fn draw(
         &mut self,
-        bsp_render: Option<u32>,
+        options: &AutomapOptions,
         player: &Player,
         level: &Level,
+        lighting: &Lighting,
         canvas: &mut WindowCanvas,
+        frame_texture: &mut FrameTexture,
     ) {
+        self.follow_player(player);
+        canvas.set_draw_color(self.theme.background);
+        canvas.fill_rect(self.playfield()).unwrap();
         self.draw_grid(canvas);
-        self._draw_lines(&level, canvas);
+        let explored = match options.reveal {
+            AutomapReveal::All => None,
+            AutomapReveal::Explored => Some(level.explored_linedefs(options.visited)),
+        };
+        self.draw_lines_classified(&level, explored.as_ref(), canvas, frame_texture);
+        if self.partitions {
+            self.draw_partitions(level, canvas);
+        }
         self.draw_verts(&level, canvas);
+        self.draw_things(level, options.thing_filter, canvas);
+        self.draw_marks(options.marks, canvas);
+        if options.key_locator {
+            self.draw_key_locator(player, level, options.tic, canvas);
+        }
+        if self.location_path {
+            self.draw_location_path(player, level, canvas);
+        }
+        if self.fov_fill {
+            self.draw_fov_fill(player, canvas);
+        }
         self.draw_player(&player, canvas);
-        self.draw_node(&player, &level, canvas);
-        if let Some(bsp_render_depth) = bsp_render {
-            self.draw_bsp_search(bsp_render_depth, &player, &level, canvas)
+        if self.height_legend {
+            self.draw_height_legend(level, canvas);
+        }
+        match options.bsp_subtree {
+            Some(node_index) => self.draw_bsp_from(node_index, level, player, lighting, canvas),
+            None => self.draw_node(&player, &level, lighting, canvas),
+        }
+        if let Some(bsp_render_depth) = options.bsp_render {
+            self.draw_bsp_search(bsp_render_depth, &player, &level, lighting, canvas)
         }
     }
 
     fn adjust_coord(&self, x: i16, y: i16) -> (i32, i32) {
-        let drawn_x = (x - self.x_offset) as i32 * self.x_multiplier.floor() as i32 / 1000;
-        let drawn_y = (y - self.y_offset) as i32 * self.y_multiplier.floor() as i32 / 1000;
+        // Level is scaled around the centre of its bounds, shifted by the current pan
+        let center_x = f32::from(self.x_offset) + f32::from(self.level_width) / 2. + self.pan_x;
+        let center_y = f32::from(self.y_offset) + f32::from(self.level_height) / 2. + self.pan_y;
+        let drawn_x = (f32::from(x) - center_x) * self.x_multiplier.floor() * self.zoom / 1000.;
+        let drawn_y = (f32::from(y) - center_y) * self.y_multiplier.floor() * self.zoom / 1000.;
+        let (screen_x, screen_y) = self.viewport_center();
+        let drawn_y = if self.flip_y { -drawn_y } else { drawn_y };
+        (screen_x + drawn_x as i32, screen_y + drawn_y as i32)
+    }
+
+    // Inverse of adjust_coord, for turning clicks into level coordinates
+    pub fn screen_to_world(&self, x: i32, y: i32) -> (f32, f32) {
+        let center_x = f32::from(self.x_offset) + f32::from(self.level_width) / 2. + self.pan_x;
+        let center_y = f32::from(self.y_offset) + f32::from(self.level_height) / 2. + self.pan_y;
+        let (screen_x, screen_y) = self.viewport_center();
+        let flip = if self.flip_y { -1. } else { 1. };
+        (
+            center_x + (x - screen_x) as f32 * 1000. / (self.x_multiplier.floor() * self.zoom),
+            center_y
+                + flip * (y - screen_y) as f32 * 1000. / (self.y_multiplier.floor() * self.zoom),
+        )
+    }
+
+    fn viewport_center(&self) -> (i32, i32) {
         (
-            12 + drawn_x as i32 + Interface::MULTIPLIER as i32,
-            ((Interface::HEIGHT - 12) * Interface::MULTIPLIER) as i32 - drawn_y as i32
-                + Interface::MULTIPLIER as i32,
+            12 + self.multiplier as i32 + ((self.width - 12) * self.multiplier) as i32 / 2,
+            self.multiplier as i32 + ((self.height - 12) * self.multiplier) as i32 / 2,
+        )
+    }
+
+    // The part of the window the automap draws into
+    fn playfield(&self) -> Rect {
+        Rect::new(
+            0,
+            0,
+            self.width * self.multiplier,
+            self.height * self.multiplier,
         )
     }
 
+    // Cohen-Sutherland: trims the line to the rect, or None if none of it is inside
+    pub fn clip_line(rect: Rect, p0: Point, p1: Point) -> Option<(Point, Point)> {
+        const LEFT: u8 = 1;
+        const RIGHT: u8 = 2;
+        const TOP: u8 = 4;
+        const BOTTOM: u8 = 8;
+        let (x_min, y_min) = (f64::from(rect.left()), f64::from(rect.top()));
+        let (x_max, y_max) = (f64::from(rect.right() - 1), f64::from(rect.bottom() - 1));
+        let outcode = |x: f64, y: f64| {
+            let mut code = 0;
+            if x < x_min {
+                code |= LEFT;
+            } else if x > x_max {
+                code |= RIGHT;
+            }
+            if y < y_min {
+                code |= TOP;
+            } else if y > y_max {
+                code |= BOTTOM;
+            }
+            code
+        };
+
+        let (mut x0, mut y0) = (f64::from(p0.x()), f64::from(p0.y()));
+        let (mut x1, mut y1) = (f64::from(p1.x()), f64::from(p1.y()));
+        let mut code0 = outcode(x0, y0);
+        let mut code1 = outcode(x1, y1);
+        loop {
+            if code0 | code1 == 0 {
+                return Some((
+                    Point::new(x0.round() as i32, y0.round() as i32),
+                    Point::new(x1.round() as i32, y1.round() as i32),
+                ));
+            }
+            if code0 & code1 != 0 {
+                // Both ends off the same side
+                return None;
+            }
+            // Move whichever end is outside onto the edge it crosses
+            let code = if code0 != 0 { code0 } else { code1 };
+            let (x, y) = if code & TOP != 0 {
+                (x0 + (x1 - x0) * (y_min - y0) / (y1 - y0), y_min)
+            } else if code & BOTTOM != 0 {
+                (x0 + (x1 - x0) * (y_max - y0) / (y1 - y0), y_max)
+            } else if code & RIGHT != 0 {
+                (x_max, y0 + (y1 - y0) * (x_max - x0) / (x1 - x0))
+            } else {
+                (x_min, y0 + (y1 - y0) * (x_min - x0) / (x1 - x0))
+            };
+            if code == code0 {
+                (x0, y0) = (x, y);
+                code0 = outcode(x0, y0);
+            } else {
+                (x1, y1) = (x, y);
+                code1 = outcode(x1, y1);
+            }
+        }
+    }
+
+    fn draw_clipped_line(&self, canvas: &mut WindowCanvas, p0: Point, p1: Point) {
+        if let Some((p0, p1)) = Self::clip_line(self.playfield(), p0, p1) {
+            canvas.draw_line(p0, p1).unwrap();
+        }
+    }
+
     fn adjust_dimension(&self, x: i16, y: i16) -> (u32, u32) {
-        let drawn_x = x as i32 * self.x_multiplier.floor() as i32 / 1000;
-        let drawn_y = y as i32 * self.y_multiplier.floor() as i32 / 1000;
+        let drawn_x = f32::from(x) * self.x_multiplier.floor() * self.zoom / 1000.;
+        let drawn_y = f32::from(y) * self.y_multiplier.floor() * self.zoom / 1000.;
         (drawn_x as u32, drawn_y as u32)
     }
 
+    // Shades through the sector's Boom colormap or else COLORMAP, the way the game does. The
+    // colour is matched to the palette first. Without a colormap it's just scaled towards black.
+    fn shade(&self, color: Color, light_level: i16, sector: usize) -> Color {
+        let colormap = self
+            .sector_colormaps
+            .get(&sector)
+            .map(|&index| &self.colormaps[index])
+            .or(self.colormap.as_ref());
+        if let (Some(palette), Some(colormap)) = (&self.palette, colormap) {
+            let light_row = (255 - usize::from(light_level.clamp(0, 255) as u8)) / 8;
+            let index = palette.nearest([color.r, color.g, color.b]);
+            let (r, g, b) = picture::map_color(palette, colormap, index, light_row);
+            return Color::RGB(r, g, b);
+        }
+        let light = i32::from(light_level.clamp(0, 255));
+        let scale = |c: u8| (i32::from(c) * light / 255) as u8;
+        Color::RGB(scale(color.r), scale(color.g), scale(color.b))
+    }
+
     fn bbox_to_rect(&self, bbox: &BBox) -> Rect {
         let (x, y, w, h) = (bbox.left, bbox.top, bbox.width, bbox.height);
         let (drawn_x, drawn_y) = self.adjust_coord(x, y);
@@ -107,119 +512,411 @@ impl Renderer {
     fn angle_to_vertex(player: &Player, v: &Vertex) -> f32 {
         let dx = v.x as f32 - player.x;
         let dy = v.y as f32 - player.y;
-        let angle = dy.atan2(dx);
-
-        if angle < 0. {
-            return angle + (2. * consts::PI);
-        }
-
-        angle
+        normalize_angle(dy.atan2(dx))
     }
 
+    // Where the line of sight at angle crosses the seg, or None if it passes beyond either
+    // end. Crossings just past an end, from rounding in the clip angles, snap to the end.
     fn find_intersection(player: &Player, angle: f32, v1: &Vertex, v2: &Vertex) -> Option<Vertex> {
-        let (x_min, y_min, x_max, y_max) = if v1.x < v2.x {
-            (v1.x as f32, v1.y as f32, v2.x as f32, v2.y as f32)
-        } else {
-            (v2.x as f32, v2.y as f32, v1.x as f32, v1.y as f32)
-        };
-
-        // y = mx + b form for trace from player position
-        let m1 = angle.tan();
-        let b1 = (player.y as f32) - m1 * (player.x as f32);
-
-        if v1.x == v2.x {
-            // Seg is sticking straight up the Y axis
-            let (x_intersect, y_intersect) = (v1.x as f32, m1 * v1.x as f32 + b1);
-            return Some(Vertex {
-                x: x_intersect.trunc() as i16,
-                y: y_intersect.trunc() as i16,
-            });
-        }
-
-        // y = mx + b form for Seg
-        let m2 = (y_max - y_min) / (x_max - x_min);
-        let b2 = y_min - m2 * x_min;
-        //println!("{}x + {};  {}x + {}", m1, b1, m2, b2);
-
-        // Check if the lines are parallel
-        if m1 == m2 {
-            // Technically the whole Seg is in view,
+        const END_TOLERANCE: f32 = 0.01; // Fraction of the seg's length
+        let (dx, dy) = (angle.cos(), angle.sin());
+        let (seg_x, seg_y) = (
+            f32::from(v2.x) - f32::from(v1.x),
+            f32::from(v2.y) - f32::from(v1.y),
+        );
+        let denominator = dx * seg_y - dy * seg_x;
+        if denominator == 0. {
+            // Parallel: technically the whole Seg is in view,
             // but it will render as 0 pixels, so skip it.
             return None;
         }
-
-        let x_intersect = if m1 == f32::INFINITY || m1 == f32::NEG_INFINITY {
-            // Angle-to-vertex is stick straight up the Y axis
-            v1.x as f32
-        } else {
-            (b2 - b1) / (m1 - m2)
-        };
-        let y_intersect = m1 * x_intersect + b1;
-
+        let (offset_x, offset_y) = (f32::from(v1.x) - player.x, f32::from(v1.y) - player.y);
+        // How far along the seg from v1 the line of sight crosses it
+        let along = (dy * offset_x - dx * offset_y) / denominator;
+        if !(-END_TOLERANCE..=1. + END_TOLERANCE).contains(&along) {
+            return None;
+        }
+        let along = along.clamp(0., 1.);
         Some(Vertex {
-            x: x_intersect.trunc() as i16,
-            y: y_intersect.trunc() as i16,
+            x: (f32::from(v1.x) + along * seg_x).trunc() as i16,
+            y: (f32::from(v1.y) + along * seg_y).trunc() as i16,
         })
     }
 
-    fn is_seg_visible(player: &Player, v1: &mut Vertex, v2: &mut Vertex) -> Option<(f32, f32)> {
+    fn is_seg_visible(
+        player: &Player,
+        fov: f32,
+        v1: &mut Vertex,
+        v2: &mut Vertex,
+    ) -> Option<(f32, f32)> {
         let mut a1 = Self::angle_to_vertex(player, v1);
         let mut a2 = Self::angle_to_vertex(player, v2);
 
-        if a2 > a1 {
-            return None;
-        }
-
-        let angle_diff = a1 - a2;
+        // Wrapped like Doom's unsigned angle subtraction, so a seg straddling angle 0
+        // (a1 just above 0, a2 just below 2π) still has a small positive span
+        let angle_diff = normalize_angle(a1 - a2);
         if angle_diff >= consts::PI {
             return None;
         }
 
-        // "Player FOV" is now 0°-90° (0-π/2) and tests will be dead simple
-        let mut rotated_a1 = a1 - player.angle + consts::FRAC_PI_4;
-        if rotated_a1 < 0. {
-            rotated_a1 += consts::PI * 2.;
-        } else if rotated_a1 > consts::PI * 2. {
-            rotated_a1 -= consts::PI * 2.;
-        }
+        // Turned so the view runs from 0 at its right edge to fov at its left
+        let half_fov = fov / 2.;
+        let mut rotated_a1 = normalize_angle(a1 - player.angle + half_fov);
 
-        if rotated_a1 > consts::FRAC_PI_2 {
-            rotated_a1 -= consts::FRAC_PI_2;
+        if rotated_a1 > fov {
+            rotated_a1 -= fov;
             // a1 further left than distance a1-a2
             // both points are left of view
             if rotated_a1 >= angle_diff {
                 return None;
             }
             // To be used for clipping later
-            a1 = player.angle + consts::FRAC_PI_4;
+            a1 = player.angle + half_fov;
         }
 
-        let mut rotated_a2 = a2 - player.angle;
-        if rotated_a2 < 0. {
-            rotated_a2 += consts::PI * 2.;
-        } else if rotated_a2 > consts::PI * 2. {
-            rotated_a2 -= consts::PI * 2.;
+        let mut rotated_a2 = normalize_angle(a2 - player.angle);
+        // distance from left edge to our angle, if greater than FOV
+        rotated_a2 = normalize_angle(half_fov - rotated_a2);
+        if rotated_a2 > fov {
+            // Mirrors the a1 check: both points are right of view
+            if rotated_a2 - fov >= angle_diff {
+                return None;
+            }
+            // To be used for clipping later
+            a2 = player.angle - half_fov;
         }
+        Some((a1, a2))
+    }
+
+    fn player_eye_height(player: &Player, level: &Level) -> f32 {
+        let floor = level
+            .sector_at(player.x.trunc() as i16, player.y.trunc() as i16)
+            .map_or(0., |sector| f32::from(level.sectors[sector].floor_height));
+        floor + Self::PLAYER_VIEW_HEIGHT
+    }
+
+    // (front, back) sectors as seen from the seg's right-hand side
+    fn seg_sectors(seg: &level::Segment) -> (Option<Rc<Sector>>, Option<Rc<Sector>>) {
+        (
+            seg.front_sidedef().map(|side| Rc::clone(&side.sector)),
+            seg.back_sidedef().map(|side| Rc::clone(&side.sector)),
+        )
+    }
+
+    // Distance to a screen the render width across that just fits the FOV. At 90° that's
+    // half the width.
+    fn focal_length(&self) -> f32 {
+        self.render_width as f32 / 2. / (self.fov / 2.).tan()
+    }
+
+    // Looking up or down shears the view like Doom does, moving the horizon instead of
+    // rotating the camera
+    fn horizon_offset(&self, pitch: f32) -> i32 {
+        (pitch.clamp(-Self::MAX_PITCH, Self::MAX_PITCH) * self.focal_length()) as i32
+    }
 
-        rotated_a2 = consts::FRAC_PI_4 - rotated_a2; // distance from left edge to our angle
-                                                     // if greater than FOV
-        if rotated_a2 < 0. {
-            rotated_a2 += consts::PI * 2.;
-        } else if rotated_a2 > consts::PI * 2. {
-            rotated_a2 -= consts::PI * 2.;
+    // Perspective projection onto a screen at the focal length. Points on or behind the near
+    // plane can't be projected.
+    fn project(&self, player: &Player, eye_z: f32, x: f32, y: f32, z: f32) -> Option<(i32, i32)> {
+        let half_width = self.render_width as f32 / 2.;
+        let half_height = self.render_height as f32 / 2.;
+        let focal_length = self.focal_length();
+        let (dx, dy) = (x - player.x, y - player.y);
+        let depth = dx * player.angle.cos() + dy * player.angle.sin();
+        if depth < 1. {
+            return None;
         }
-        if rotated_a2 > consts::FRAC_PI_2 {
-            // To be used for clipping later
-            a2 = player.angle - consts::FRAC_PI_4;
+        let lateral = dy * player.angle.cos() - dx * player.angle.sin();
+        Some((
+            (half_width - lateral / depth * focal_length) as i32,
+            (half_height - (z - eye_z) / depth * focal_length) as i32
+                + self.horizon_offset(player.pitch),
+        ))
+    }
+
+    // Largest aspect-preserving scale of the render size that fits the window, and where
+    // the scaled image lands centred in it
+    fn upscale_rect(render_size: (u32, u32), window_size: (u32, u32)) -> (f32, Rect) {
+        let scale = f32::min(
+            window_size.0 as f32 / render_size.0 as f32,
+            window_size.1 as f32 / render_size.1 as f32,
+        );
+        let width = (render_size.0 as f32 * scale) as u32;
+        let height = (render_size.1 as f32 * scale) as u32;
+        (
+            scale,
+            Rect::new(
+                ((window_size.0 - width) / 2) as i32,
+                ((window_size.1 - height) / 2) as i32,
+                width,
+                height,
+            ),
+        )
+    }
+
+    // The largest rect of the given aspect ratio centred in the window. A wider window gets
+    // bars either side, a taller one above and below.
+    pub fn aspect_rect(aspect_ratio: f32, window_size: (u32, u32)) -> Rect {
+        let (window_width, window_height) = window_size;
+        let (width, height) = if window_width as f32 > window_height as f32 * aspect_ratio {
+            (
+                (window_height as f32 * aspect_ratio).round() as u32,
+                window_height,
+            )
+        } else {
+            (
+                window_width,
+                (window_width as f32 / aspect_ratio).round() as u32,
+            )
+        };
+        Rect::new(
+            ((window_width - width) / 2) as i32,
+            ((window_height - height) / 2) as i32,
+            width.max(1),
+            height.max(1),
+        )
+    }
+
+    // The 3D view is drawn at the internal resolution and then stretched to the configured
+    // aspect ratio, as Doom shows its 320x200 at 4:3, with the rest of the window black
+    pub fn draw_3d(
+        &self,
+        player: &Player,
+        level: &Level,
+        canvas: &mut WindowCanvas,
+        frame_texture: &mut FrameTexture,
+    ) {
+        let mut framebuffer = Framebuffer::new(self.render_width, self.render_height);
+        framebuffer.clear(Color::BLACK);
+        if self.wireframe {
+            self.draw_3d_wireframe(player, level, &mut framebuffer);
+        } else {
+            self.draw_3d_walls(player, level, &mut framebuffer);
+        }
+        let window = canvas.output_size().unwrap();
+        let dest = Self::aspect_rect(self.aspect_ratio, window);
+        canvas.set_draw_color(Color::BLACK);
+        let bars = [
+            Rect::new(0, 0, dest.x() as u32, window.1),
+            Rect::new(0, 0, window.0, dest.y() as u32),
+            Rect::new(dest.right(), 0, window.0 - dest.right() as u32, window.1),
+            Rect::new(0, dest.bottom(), window.0, window.1 - dest.bottom() as u32),
+        ];
+        // SDL makes empty bars a pixel wide, but the view is drawn over them anyway
+        canvas.fill_rects(&bars).unwrap();
+        framebuffer.present(canvas, frame_texture, dest);
+    }
+
+    // Which texel of a 64x64 flat lands on a screen pixel when tiled at the given scale
+    pub fn flat_texel(x: u32, y: u32, scale: u32) -> (usize, usize) {
+        (((x / scale) % 64) as usize, ((y / scale) % 64) as usize)
+    }
+
+    // Tiles the flat across the whole window
+    pub fn draw_flat_preview(
+        &self,
+        flat: &Flat,
+        palette: &Palette,
+        canvas: &mut WindowCanvas,
+        frame_texture: &mut FrameTexture,
+    ) {
+        let scale = self.multiplier;
+        let (width, height) = (self.width * self.multiplier, self.height * self.multiplier);
+        let mut framebuffer = Framebuffer::new(width / scale, height / scale);
+        for y in 0..framebuffer.height {
+            for x in 0..framebuffer.width {
+                let (u, v) = Self::flat_texel(x * scale, y * scale, scale);
+                let [r, g, b] = palette.rgb(flat.pixels[v * 64 + u]);
+                framebuffer.put(x as i32, y as i32, Color::RGB(r, g, b));
+            }
+        }
+        framebuffer.present(canvas, frame_texture, Rect::new(0, 0, width, height));
+    }
+
+    // Scales a picture up as far as it fits whole in the window and centres it, with a label
+    pub fn draw_picture(
+        &self,
+        picture: &Picture,
+        palette: &Palette,
+        label: &str,
+        canvas: &mut WindowCanvas,
+        frame_texture: &mut FrameTexture,
+    ) {
+        let mut framebuffer = Framebuffer::new(picture.width as u32, picture.height as u32);
+        framebuffer.clear(Color::RGB(32, 32, 32));
+        for y in 0..picture.height {
+            for x in 0..picture.width {
+                if let Some(index) = picture.pixel(x, y) {
+                    let [r, g, b] = palette.rgb(index);
+                    framebuffer.put(x as i32, y as i32, Color::RGB(r, g, b));
+                }
+            }
+        }
+        let window = (self.width * self.multiplier, self.height * self.multiplier);
+        let (scale, _) = Self::upscale_rect((framebuffer.width, framebuffer.height), window);
+        let scale = scale.floor().max(1.) as u32;
+        let (width, height) = (framebuffer.width * scale, framebuffer.height * scale);
+        let dest = Rect::new(
+            (window.0 as i32 - width as i32) / 2,
+            (window.1 as i32 - height as i32) / 2,
+            width,
+            height,
+        );
+        framebuffer.present(canvas, frame_texture, dest);
+        text::draw_text(canvas, label, 12, 12, self.multiplier / 2, Color::WHITE);
+    }
+
+    // The (x, top, bottom) span of each on-screen column of a wall section between heights top
+    // and bottom. A planar wall's edges stay straight once projected, so the spans can be
+    // interpolated in screen space.
+    fn wall_columns(
+        &self,
+        player: &Player,
+        eye_z: f32,
+        v1: &Vertex,
+        v2: &Vertex,
+        top: i16,
+        bottom: i16,
+    ) -> Vec<(i32, i32, i32)> {
+        let corner = |v: &Vertex, z: i16| {
+            self.project(player, eye_z, f32::from(v.x), f32::from(v.y), f32::from(z))
+        };
+        let corners = (
+            corner(v1, top),
+            corner(v2, top),
+            corner(v1, bottom),
+            corner(v2, bottom),
+        );
+        let (Some((x1, top1)), Some((x2, top2)), Some((_, bottom1)), Some((_, bottom2))) = corners
+        else {
+            return Vec::new();
+        };
+        if x2 <= x1 {
+            return Vec::new();
+        }
+        (cmp::max(x1, 0)..cmp::min(x2, self.render_width as i32))
+            .map(|x| {
+                let t = (x - x1) as f32 / (x2 - x1) as f32;
+                let y_top = top1 as f32 + (top2 - top1) as f32 * t;
+                let y_bottom = bottom1 as f32 + (bottom2 - bottom1) as f32 * t;
+                (x, y_top as i32, y_bottom as i32)
+            })
+            .collect()
+    }
+
+    // The seg's ends clipped to the field of view, with the sectors in front and behind it
+    fn visible_seg(
+        &self,
+        player: &Player,
+        level: &Level,
+        seg: &level::Segment,
+    ) -> Option<(Vertex, Vertex, Rc<Sector>, Option<Rc<Sector>>)> {
+        let (front, back) = match Self::seg_sectors(seg) {
+            (Some(front), back) => (front, back),
+            (None, _) => return None,
+        };
+        let mut v1 = level.vertexes[seg.start_vert];
+        let mut v2 = level.vertexes[seg.end_vert];
+        let (a1, a2) = Self::is_seg_visible(player, self.fov, &mut v1, &mut v2)?;
+        let new_v1 = Renderer::find_intersection(player, a1, &v1, &v2).unwrap_or(v1);
+        let new_v2 = Renderer::find_intersection(player, a2, &v1, &v2).unwrap_or(v2);
+        Some((new_v1, new_v2, front, back))
+    }
+
+    // Walks the subsectors front to back like Doom, so each column only keeps the rows that
+    // nearer walls have left open. A solid wall closes its columns, and the steps of a
+    // two-sided line narrow them to the gap between the two sectors.
+    fn draw_3d_walls(&self, player: &Player, level: &Level, framebuffer: &mut Framebuffer) {
+        let eye_z = Self::player_eye_height(player, level);
+        let mut open = vec![(0, framebuffer.height as i32 - 1); framebuffer.width as usize];
+        let segs = level
+            .visible_subsectors(player, self.fov)
+            .into_iter()
+            .flat_map(|ssec| level.subsectors[ssec].segments.iter());
+        for seg in segs {
+            let (v1, v2, front, back) = match self.visible_seg(player, level, seg) {
+                Some(visible) => visible,
+                None => continue,
+            };
+            let columns =
+                |top: i16, bottom: i16| self.wall_columns(player, eye_z, &v1, &v2, top, bottom);
+            let mut fill = |(open_top, open_bottom), x, top, bottom, color| {
+                let (top, bottom) = (cmp::max(top, open_top), cmp::min(bottom, open_bottom));
+                if top <= bottom {
+                    framebuffer.vline(
+                        x,
+                        top,
+                        bottom,
+                        self.shade(color, front.light_level, front.id),
+                    );
+                }
+            };
+            match back {
+                None => {
+                    for (x, top, bottom) in columns(front.ceiling_height, front.floor_height) {
+                        fill(open[x as usize], x, top, bottom, Color::GRAY);
+                        open[x as usize] = (1, 0);
+                    }
+                }
+                Some(back) => {
+                    // Only the steps between the two sectors are solid, and the front sector's
+                    // ceiling and floor hide whatever is beyond them
+                    let gap_top = cmp::min(front.ceiling_height, back.ceiling_height);
+                    for (x, top, bottom) in columns(front.ceiling_height, gap_top) {
+                        if back.ceiling_height < front.ceiling_height {
+                            fill(open[x as usize], x, top, bottom, Color::RED);
+                        }
+                        let column = &mut open[x as usize];
+                        column.0 = cmp::max(column.0, bottom + 1);
+                    }
+                    let gap_bottom = cmp::max(front.floor_height, back.floor_height);
+                    for (x, top, bottom) in columns(gap_bottom, front.floor_height) {
+                        if back.floor_height > front.floor_height {
+                            fill(open[x as usize], x, top, bottom, Color::BLUE);
+                        }
+                        let column = &mut open[x as usize];
+                        column.1 = cmp::min(column.1, top - 1);
+                    }
+                }
+            }
+        }
+    }
+
+    // Outlines every visible seg's front sector from floor to ceiling, with nothing hidden
+    fn draw_3d_wireframe(&self, player: &Player, level: &Level, framebuffer: &mut Framebuffer) {
+        let eye_z = Self::player_eye_height(player, level);
+        for seg in level.segments.iter() {
+            let (v1, v2, front, _) = match self.visible_seg(player, level, seg) {
+                Some(visible) => visible,
+                None => continue,
+            };
+            let corner = |v: &Vertex, z: i16| {
+                self.project(player, eye_z, f32::from(v.x), f32::from(v.y), f32::from(z))
+            };
+            let corners = [
+                corner(&v1, front.ceiling_height),
+                corner(&v2, front.ceiling_height),
+                corner(&v2, front.floor_height),
+                corner(&v1, front.floor_height),
+            ];
+            if let [Some(top1), Some(top2), Some(bottom2), Some(bottom1)] = corners {
+                for (from, to) in [
+                    (top1, top2),
+                    (top2, bottom2),
+                    (bottom2, bottom1),
+                    (bottom1, top1),
+                ] {
+                    framebuffer.line(from.0, from.1, to.0, to.1, Color::WHITE);
+                }
+            }
         }
-        Some((a1, a2))
     }
 
     fn draw_player(&self, player: &Player, canvas: &mut WindowCanvas) {
         // println!("{} ({})", player.angle, player.angle.to_degrees());
         let (x, y) = self.adjust_coord(player.x.trunc() as i16, player.y.trunc() as i16);
         let (x1, y1) = (x - 2, y - 2);
-        canvas.set_draw_color(Color::GREEN);
+        canvas.set_draw_color(self.theme.player);
         canvas.draw_rect(Rect::new(x1, y1, 4, 4)).unwrap();
 
         let (view_x1, view_y1) = self.adjust_coord(
@@ -234,36 +931,247 @@ impl Renderer {
             .draw_line(Point::new(view_x1, view_y1), Point::new(view_x2, view_y2))
             .unwrap();
 
-        canvas.set_draw_color(Color::CYAN);
-        let (left_los_x, left_los_y) = self.adjust_coord(
-            ((f32::cos(player.angle + consts::FRAC_PI_4) * 4000.) + player.x).trunc() as i16,
-            ((f32::sin(player.angle + consts::FRAC_PI_4) * 4000.) + player.y).trunc() as i16,
-        );
-        let (right_los_x, right_los_y) = self.adjust_coord(
-            ((f32::cos(player.angle - consts::FRAC_PI_4) * 4000.) + player.x).trunc() as i16,
-            ((f32::sin(player.angle - consts::FRAC_PI_4) * 4000.) + player.y).trunc() as i16,
-        );
+        canvas.set_draw_color(self.theme.view);
+        let [eye, left_los, right_los] = self.fov_triangle(player);
+        canvas.draw_line(eye, left_los).unwrap();
+        canvas.draw_line(eye, right_los).unwrap();
+    }
 
-        canvas
-            .draw_line(Point::new(x, y), Point::new(left_los_x, left_los_y))
-            .unwrap();
-        canvas
-            .draw_line(Point::new(x, y), Point::new(right_los_x, right_los_y))
-            .unwrap();
+    // Player position and the far ends of the left and right line-of-sight edges
+    fn fov_triangle(&self, player: &Player) -> [Point; 3] {
+        let (x, y) = self.adjust_coord(player.x.trunc() as i16, player.y.trunc() as i16);
+        let los = |angle: f32| {
+            let (los_x, los_y) = self.adjust_coord(
+                ((angle.cos() * 4000.) + player.x).trunc() as i16,
+                ((angle.sin() * 4000.) + player.y).trunc() as i16,
+            );
+            Point::new(los_x, los_y)
+        };
+        [
+            Point::new(x, y),
+            los(player.angle + self.fov / 2.),
+            los(player.angle - self.fov / 2.),
+        ]
+    }
+
+    // Fills the FOV wedge one scanline at a time, limited to the playfield rows
+    pub fn draw_fov_fill(&self, player: &Player, canvas: &mut WindowCanvas) {
+        let corners = self.fov_triangle(player);
+        let playfield = self.playfield();
+        let top = corners
+            .iter()
+            .map(|p| p.y())
+            .min()
+            .unwrap()
+            .max(playfield.top());
+        let bottom = corners
+            .iter()
+            .map(|p| p.y())
+            .max()
+            .unwrap()
+            .min(playfield.bottom() - 1);
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        let view = self.theme.view;
+        canvas.set_draw_color(Color::RGBA(view.r, view.g, view.b, 48));
+        for y in top..=bottom {
+            let mut xs = [0, 1, 2].iter().filter_map(|&i| {
+                let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                if (a.y() <= y) == (b.y() <= y) {
+                    return None; // Edge doesn't cross this row
+                }
+                let t = (y - a.y()) as f32 / (b.y() - a.y()) as f32;
+                Some(a.x() + ((b.x() - a.x()) as f32 * t) as i32)
+            });
+            if let (Some(x1), Some(x2)) = (xs.next(), xs.next()) {
+                self.draw_clipped_line(canvas, Point::new(x1, y), Point::new(x2, y));
+            }
+        }
+        canvas.set_blend_mode(BlendMode::None);
     }
 
     fn draw_verts(&self, level: &Level, canvas: &mut WindowCanvas) {
-        canvas.set_draw_color(Color::CYAN);
+        canvas.set_draw_color(self.theme.vertex);
         level.vertexes.iter().for_each(|Vertex { x, y }| {
             let (drawn_x, drawn_y) = self.adjust_coord(*x, *y);
 
-            canvas.draw_point(Point::new(drawn_x, drawn_y)).unwrap();
+            if self.playfield().contains_point((drawn_x, drawn_y)) {
+                canvas.draw_point(Point::new(drawn_x, drawn_y)).unwrap();
+            }
         });
     }
 
+    // The line picked with a right click, with its number and distance beside it
+    pub fn draw_picked_linedef(
+        &self,
+        level: &Level,
+        (idx, distance): (usize, f32),
+        canvas: &mut WindowCanvas,
+    ) {
+        let linedef = &level.linedefs[idx];
+        let v1 = level.vertexes[linedef.start_vert];
+        let v2 = level.vertexes[linedef.end_vert];
+        let (x1, y1) = self.adjust_coord(v1.x, v1.y);
+        let (x2, y2) = self.adjust_coord(v2.x, v2.y);
+        canvas.set_draw_color(self.theme.highlight);
+        canvas
+            .draw_line(Point::new(x1, y1), Point::new(x2, y2))
+            .unwrap();
+        text::draw_text(
+            canvas,
+            &format!("linedef {} ({:.1} away)", idx, distance),
+            (x1 + x2) / 2 + 4,
+            (y1 + y2) / 2 + 4,
+            self.multiplier / 2,
+            self.theme.highlight,
+        );
+    }
+
+    fn draw_things(
+        &self,
+        level: &Level,
+        thing_filter: &HashSet<ThingCategory>,
+        canvas: &mut WindowCanvas,
+    ) {
+        for thing in level.things.iter() {
+            let category = ThingCategory::of(thing.thing_type);
+            if !thing_filter.contains(&category) {
+                continue;
+            }
+            canvas.set_draw_color(self.theme.thing_color(category));
+            if let ThingCategory::Monster | ThingCategory::Decoration = category {
+                self.draw_thing_arrow(thing, canvas);
+            } else {
+                let (x, y) = self.adjust_coord(thing.x, thing.y);
+                canvas.draw_rect(Rect::new(x - 1, y - 1, 3, 3)).unwrap();
+            }
+        }
+    }
+
+    // Pulsing ring on every key, with a line from the player to the nearest of each colour
+    fn draw_key_locator(
+        &self,
+        player: &Player,
+        level: &Level,
+        tic: u32,
+        canvas: &mut WindowCanvas,
+    ) {
+        let pulse = 4 + (tic % 18).abs_diff(9) as i32; // Grows and shrinks about twice a second
+        let mut nearest: HashMap<KeyColor, (f32, &Thing)> = HashMap::new();
+        for (idx, key_color) in level.keys() {
+            let thing = &level.things[idx];
+            let distance = (f32::from(thing.x) - player.x).hypot(f32::from(thing.y) - player.y);
+            let closest = nearest.entry(key_color).or_insert((distance, thing));
+            if distance < closest.0 {
+                *closest = (distance, thing);
+            }
+
+            canvas.set_draw_color(Self::key_color(key_color));
+            let (x, y) = self.adjust_coord(thing.x, thing.y);
+            canvas
+                .draw_rect(Rect::new(
+                    x - pulse,
+                    y - pulse,
+                    2 * pulse as u32 + 1,
+                    2 * pulse as u32 + 1,
+                ))
+                .unwrap();
+        }
+
+        let (player_x, player_y) =
+            self.adjust_coord(player.x.trunc() as i16, player.y.trunc() as i16);
+        for (key_color, (_, thing)) in nearest {
+            canvas.set_draw_color(Self::key_color(key_color));
+            let (x, y) = self.adjust_coord(thing.x, thing.y);
+            self.draw_clipped_line(canvas, Point::new(player_x, player_y), Point::new(x, y));
+        }
+    }
+
+    // Numbered crosses, like the original automap's marks
+    fn draw_marks(&self, marks: &[(f32, f32)], canvas: &mut WindowCanvas) {
+        for (idx, (x, y)) in marks.iter().enumerate() {
+            let (x, y) = self.adjust_coord(x.round() as i16, y.round() as i16);
+            canvas.set_draw_color(self.theme.highlight);
+            self.draw_clipped_line(canvas, Point::new(x - 3, y - 3), Point::new(x + 3, y + 3));
+            self.draw_clipped_line(canvas, Point::new(x - 3, y + 3), Point::new(x + 3, y - 3));
+            text::draw_text(
+                canvas,
+                &idx.to_string(),
+                x + 5,
+                y - 3,
+                1,
+                self.theme.highlight,
+            );
+        }
+    }
+
+    fn key_color(key_color: KeyColor) -> Color {
+        match key_color {
+            KeyColor::Blue => Color::RGB(64, 64, 255),
+            KeyColor::Yellow => Color::YELLOW,
+            KeyColor::Red => Color::RED,
+        }
+    }
+
+    // Triangle in level coordinates, tip first, pointing the way the thing faces
+    fn thing_arrow(thing: &Thing) -> [(f32, f32); 3] {
+        let radius = f32::from(thing_info::dimensions(thing.thing_type).0);
+        let (x, y) = (f32::from(thing.x), f32::from(thing.y));
+        let corner = |angle: f32| (x + radius * angle.cos(), y + radius * angle.sin());
+        let facing = thing.facing_radians();
+        let spread = 0.75 * consts::PI;
+        [
+            corner(facing),
+            corner(facing + spread),
+            corner(facing - spread),
+        ]
+    }
+
+    fn draw_thing_arrow(&self, thing: &Thing, canvas: &mut WindowCanvas) {
+        let [tip, left, right] = Self::thing_arrow(thing).map(|(x, y)| {
+            let (x, y) = self.adjust_coord(x.round() as i16, y.round() as i16);
+            Point::new(x, y)
+        });
+        self.draw_clipped_line(canvas, tip, left);
+        self.draw_clipped_line(canvas, left, right);
+        self.draw_clipped_line(canvas, right, tip);
+    }
+
+    pub fn draw_measurement(
+        &self,
+        anchor: (f32, f32),
+        end: Option<(f32, f32)>,
+        canvas: &mut WindowCanvas,
+    ) {
+        let to_screen = |(x, y): (f32, f32)| {
+            let (x, y) = self.adjust_coord(x.round() as i16, y.round() as i16);
+            Point::new(x, y)
+        };
+        let start = to_screen(anchor);
+        canvas.set_draw_color(self.theme.highlight);
+        canvas
+            .draw_rect(Rect::new(start.x() - 2, start.y() - 2, 5, 5))
+            .unwrap();
+        if let Some(end) = end {
+            canvas.draw_line(start, to_screen(end)).unwrap();
+            let (dx, dy, distance) = Interface::measure(anchor, end);
+            text::draw_text(
+                canvas,
+                &format!("{:.1} (dx {:.0}, dy {:.0})", distance, dx, dy),
+                12,
+                12,
+                self.multiplier / 2,
+                self.theme.highlight,
+            );
+        }
+    }
+
     fn _draw_lines(&self, level: &Level, canvas: &mut WindowCanvas) {
         canvas.set_draw_color(Color::RED);
         level.linedefs.iter().for_each(|ld| {
+            if ld.hidden_on_map() {
+                return;
+            }
             let v1 = level.vertexes[ld.start_vert];
             let v2 = level.vertexes[ld.end_vert];
             let (drawn_x1, drawn_y1) = self.adjust_coord(v1.x, v1.y);
@@ -278,47 +1186,224 @@ impl Renderer {
         });
     }
 
+    // The pixels of a line from p0 to p1 that a dash pattern of `on` drawn then `off` skipped
+    // leaves, stepping like Bresenham so the ends are the same as a solid line's
+    pub fn dash_points(p0: Point, p1: Point, on: u32, off: u32) -> Vec<Point> {
+        let (dx, dy) = ((p1.x() - p0.x()).abs(), -(p1.y() - p0.y()).abs());
+        let step_x = if p0.x() < p1.x() { 1 } else { -1 };
+        let step_y = if p0.y() < p1.y() { 1 } else { -1 };
+        let (mut x, mut y) = (p0.x(), p0.y());
+        let mut error = dx + dy;
+        let period = (on + off).max(1);
+        let mut points = Vec::new();
+        for index in 0.. {
+            if index % period < on {
+                points.push(Point::new(x, y));
+            }
+            if x == p1.x() && y == p1.y() {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+        points
+    }
+
+    pub fn draw_dashed_line(
+        canvas: &mut WindowCanvas,
+        p0: Point,
+        p1: Point,
+        color: Color,
+        on: u32,
+        off: u32,
+    ) {
+        canvas.set_draw_color(color);
+        canvas
+            .draw_points(Self::dash_points(p0, p1, on, off).as_slice())
+            .unwrap();
+    }
+
+    // Colour for a line on the automap, and whether it's drawn bold
+    pub fn line_style(&self, linedef: &Linedef) -> (Color, bool) {
+        match Self::map_class(linedef, self.reveal_secrets) {
+            LineClass::OneSided => (self.theme.wall, true),
+            LineClass::TwoSided if linedef.is_self_referencing() => {
+                (self.theme.self_referencing, true)
+            }
+            LineClass::TwoSided => (self.theme.two_sided, false),
+            LineClass::NoSides => (self.theme.no_sides, true),
+        }
+    }
+
+    // How the automap shows a line: secret ones pass for one-sided walls unless revealed
+    pub fn map_class(linedef: &Linedef, reveal_secrets: bool) -> LineClass {
+        match linedef.classify() {
+            LineClass::TwoSided if linedef.is_secret_on_map() && !reveal_secrets => {
+                LineClass::OneSided
+            }
+            class => class,
+        }
+    }
+
+    // Lines only get drawn once explored, when that's being tracked, unless their flags say
+    // they're always or never on the map
+    pub fn line_revealed(explored: Option<&HashSet<usize>>, idx: usize, linedef: &Linedef) -> bool {
+        if linedef.hidden_on_map() {
+            return false;
+        }
+        linedef.always_on_map() || explored.is_none_or(|explored| explored.contains(&idx))
+    }
+
+    pub fn draw_lines_classified(
+        &self,
+        level: &Level,
+        explored: Option<&HashSet<usize>>,
+        canvas: &mut WindowCanvas,
+        frame_texture: &mut FrameTexture,
+    ) {
+        // Lines are drawn in software, anti-aliased or not, into a transparent buffer laid over
+        // the canvas at the end
+        let playfield = self.playfield();
+        let mut framebuffer = Framebuffer::new(playfield.width(), playfield.height());
+        framebuffer.clear(Color::RGBA(0, 0, 0, 0));
+        level.linedefs.iter().enumerate().for_each(|(idx, ld)| {
+            if !Self::line_revealed(explored, idx, ld) {
+                return;
+            }
+            let v1 = level.vertexes[ld.start_vert];
+            let v2 = level.vertexes[ld.end_vert];
+            let (drawn_x1, drawn_y1) = self.adjust_coord(v1.x, v1.y);
+            let (drawn_x2, drawn_y2) = self.adjust_coord(v2.x, v2.y);
+            let (color, bold) = self.line_style(ld);
+            // Telling lines apart by style as well as colour, for themes that want it
+            let dashed = self.theme.dashed_two_sided
+                && !bold
+                && Self::map_class(ld, self.reveal_secrets) == LineClass::TwoSided;
+            let mut lines = vec![(
+                Point::new(drawn_x1, drawn_y1),
+                Point::new(drawn_x2, drawn_y2),
+            )];
+            if bold {
+                // Thicken by doubling the line one pixel across its shorter axis
+                let (dx, dy) = if (drawn_x2 - drawn_x1).abs() > (drawn_y2 - drawn_y1).abs() {
+                    (0, 1)
+                } else {
+                    (1, 0)
+                };
+                lines.push((
+                    Point::new(drawn_x1 + dx, drawn_y1 + dy),
+                    Point::new(drawn_x2 + dx, drawn_y2 + dy),
+                ));
+            }
+            for (p0, p1) in lines {
+                let (p0, p1) = match Self::clip_line(playfield, p0, p1) {
+                    Some(clipped) => clipped,
+                    None => continue,
+                };
+                if self.anti_alias {
+                    framebuffer.wu_line(
+                        p0.x() as f32,
+                        p0.y() as f32,
+                        p1.x() as f32,
+                        p1.y() as f32,
+                        color,
+                    );
+                } else if dashed {
+                    Self::draw_dashed_line(canvas, p0, p1, color, 4, 3);
+                } else {
+                    framebuffer.line(p0.x(), p0.y(), p1.x(), p1.y(), color);
+                }
+            }
+        });
+        framebuffer.present(canvas, frame_texture, playfield);
+    }
+
     fn draw_sector(
         &self,
         ssec: &level::SubSector,
         level: &Level,
         player: &Player,
+        lighting: &Lighting,
         canvas: &mut WindowCanvas,
     ) {
         ssec.segments.iter().for_each(|seg| {
+            if let Some(sidedef) = seg.front_sidedef() {
+                let light_level = lighting.light_level(sidedef.sector.id);
+                let color = if self.sector_colors {
+                    color::sector_color(sidedef.sector.id)
+                } else {
+                    self.theme.highlight
+                };
+                canvas.set_draw_color(self.shade(color, light_level, sidedef.sector.id));
+            }
             let mut v1 = level.vertexes[seg.start_vert].clone();
             let mut v2 = level.vertexes[seg.end_vert].clone();
-            if let Some((a1, a2)) = Self::is_seg_visible(player, &mut v1, &mut v2) {
+            if let Some((a1, a2)) = Self::is_seg_visible(player, self.fov, &mut v1, &mut v2) {
                 let new_v1 = Renderer::find_intersection(player, a1, &v1, &v2).unwrap_or(v1);
                 let new_v2 = Renderer::find_intersection(player, a2, &v1, &v2).unwrap_or(v2);
                 let (drawn_x1, drawn_y1) = self.adjust_coord(new_v1.x, new_v1.y);
                 let (drawn_x2, drawn_y2) = self.adjust_coord(new_v2.x, new_v2.y);
 
-                canvas
-                    .draw_line(
-                        Point::new(drawn_x1, drawn_y1),
-                        Point::new(drawn_x2, drawn_y2),
-                    )
-                    .unwrap();
+                self.draw_clipped_line(
+                    canvas,
+                    Point::new(drawn_x1, drawn_y1),
+                    Point::new(drawn_x2, drawn_y2),
+                );
             }
         })
     }
-    fn draw_bsp(&self, node: &Node, level: &Level, player: &Player, canvas: &mut WindowCanvas) {
-        match &node.left_child {
-            Some(Child::NODE(n)) => self.draw_bsp(&n, level, player, canvas),
-            Some(Child::SUBSECTOR(ssec)) => self.draw_sector(ssec, level, player, canvas),
-            None => (),
+
+    pub fn draw_bsp_from(
+        &self,
+        node_index: i16,
+        level: &Level,
+        player: &Player,
+        lighting: &Lighting,
+        canvas: &mut WindowCanvas,
+    ) {
+        if let Some(node) = level.nodes.get(&node_index) {
+            canvas.set_draw_color(self.theme.left_bbox);
+            canvas
+                .draw_rect(self.bbox_to_rect(&node.left_bbox))
+                .unwrap();
+            canvas.set_draw_color(self.theme.right_bbox);
+            canvas
+                .draw_rect(self.bbox_to_rect(&node.right_bbox))
+                .unwrap();
         }
-        match &node.right_child {
-            Some(Child::NODE(n)) => self.draw_bsp(&n, level, player, canvas),
-            Some(Child::SUBSECTOR(ssec)) => self.draw_sector(ssec, level, player, canvas),
-            None => (),
+        for ssec in level.subtree_subsectors(node_index) {
+            self.draw_sector(&level.subsectors[ssec], level, player, lighting, canvas);
+        }
+    }
+
+    // Subsectors in the order draw_node draws them. Walking the BSP always takes the player's
+    // side of each partition first, so the order only depends on where the player is,
+    // skipping children whose bbox is out of view
+    fn draw_order(&self, player: &Player, level: &Level) -> Vec<usize> {
+        if self.bsp_index_order {
+            (0..level.subsectors.len()).collect()
+        } else {
+            level.visible_subsectors(player, self.fov)
         }
     }
 
-    fn draw_node(&self, player: &Player, level: &Level, canvas: &mut WindowCanvas) {
-        canvas.set_draw_color(Color::YELLOW);
-        self.draw_bsp(&level.root_node, level, player, canvas);
+    fn draw_node(
+        &self,
+        player: &Player,
+        level: &Level,
+        lighting: &Lighting,
+        canvas: &mut WindowCanvas,
+    ) {
+        for ssec in self.draw_order(player, level) {
+            self.draw_sector(&level.subsectors[ssec], level, player, lighting, canvas);
+        }
         // if let Some(Child::NODE(n)) = &level.nodes.left_child {
         //     let (x1, y1) = self.adjust_coord(&n.left_bbox.left, &n.left_bbox.top);
         //     let (w1, h1) = self.adjust_dim(&n.left_bbox.width, &n.left_bbox.height);
@@ -336,43 +1421,194 @@ impl Renderer {
         // }
     }
 
+    // Walks from the root to the subsector at (x, y) again only when the point or the level
+    // has changed since the last walk
+    fn update_search_path(&mut self, level: &Level, x: i16, y: i16) {
+        if let Some(cached) = &self.search_path {
+            if cached.position == (x, y) && Rc::ptr_eq(&cached.root, &level.root_node) {
+                return;
+            }
+        }
+        // Re-walking from the root for each depth is quadratic, but only runs on a miss
+        let mut path = Vec::new();
+        for depth in 1.. {
+            match level.root_node.find_partial(x, y, depth) {
+                Child::NODE(n) => path.push(Child::NODE(Rc::clone(n))),
+                Child::SUBSECTOR(ssec) => {
+                    path.push(Child::SUBSECTOR(Rc::clone(ssec)));
+                    break;
+                }
+            }
+        }
+        self.search_path = Some(SearchPath {
+            root: Rc::clone(&level.root_node),
+            position: (x, y),
+            path,
+        });
+    }
+
     fn draw_bsp_search(
-        &self,
+        &mut self,
         depth: u32,
         player: &Player,
         level: &Level,
+        lighting: &Lighting,
         canvas: &mut WindowCanvas,
     ) {
         if depth == 0 {
-            canvas.set_draw_color(Color::CYAN);
+            canvas.set_draw_color(self.theme.left_bbox);
             canvas
                 .draw_rect(self.bbox_to_rect(&level.root_node.left_bbox))
                 .unwrap();
-            canvas.set_draw_color(Color::GREEN);
+            canvas.set_draw_color(self.theme.right_bbox);
             canvas
                 .draw_rect(self.bbox_to_rect(&level.root_node.right_bbox))
                 .unwrap();
             return;
         }
-        let child =
-            level
-                .root_node
-                .find_partial(player.x.trunc() as i16, player.y.trunc() as i16, depth);
-        match child {
+        self.update_search_path(level, player.x.trunc() as i16, player.y.trunc() as i16);
+        let path = &self.search_path.as_ref().unwrap().path;
+        // Past the subsector, deeper searches keep finding it, like find_partial
+        match &path[(depth as usize - 1).min(path.len() - 1)] {
             Child::NODE(n) => {
-                canvas.set_draw_color(Color::CYAN);
+                canvas.set_draw_color(self.theme.left_bbox);
                 canvas.draw_rect(self.bbox_to_rect(&n.left_bbox)).unwrap();
-                canvas.set_draw_color(Color::GREEN);
+                canvas.set_draw_color(self.theme.right_bbox);
                 canvas.draw_rect(self.bbox_to_rect(&n.right_bbox)).unwrap();
             }
-            Child::SUBSECTOR(s) => self.draw_sector(s, level, player, canvas),
+            Child::SUBSECTOR(s) => self.draw_sector(s, level, player, lighting, canvas),
         }
     }
 
-    fn draw_grid(&self, canvas: &mut WindowCanvas) {
-        const GRID_SPACING: i16 = 128;
-        let d_start_x = self.x_offset.rem_euclid(GRID_SPACING);
-        let d_start_y = self.x_offset.rem_euclid(GRID_SPACING);
+    // Every node's partition line across the whole level, fading with depth in the tree
+    pub fn draw_partitions(&self, level: &Level, canvas: &mut WindowCanvas) {
+        let min = (f32::from(self.x_offset), f32::from(self.y_offset));
+        let max = (
+            min.0 + f32::from(self.level_width),
+            min.1 + f32::from(self.level_height),
+        );
+        let mut stack = vec![(&*level.root_node, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let origin = (f32::from(node.partition_x), f32::from(node.partition_y));
+            let delta = (f32::from(node.delta_x), f32::from(node.delta_y));
+            if let Some((start, end)) = math::extend_line(origin, delta, min, max) {
+                let brightness = (255. * 0.8_f32.powi(depth)).max(48.) as u8;
+                canvas.set_draw_color(Color::RGB(brightness, 0, brightness));
+                let (x1, y1) = self.adjust_coord(start.0.round() as i16, start.1.round() as i16);
+                let (x2, y2) = self.adjust_coord(end.0.round() as i16, end.1.round() as i16);
+                self.draw_clipped_line(canvas, Point::new(x1, y1), Point::new(x2, y2));
+            }
+            for child in [&node.left_child, &node.right_child] {
+                if let Some(Child::NODE(n)) = child {
+                    stack.push((n, depth + 1));
+                }
+            }
+        }
+    }
+
+    // Each decision finding the player's subsector: the partition in white, the side taken
+    // in green and the one ruled out in red
+    pub fn draw_location_path(&self, player: &Player, level: &Level, canvas: &mut WindowCanvas) {
+        let path = level.location_path(player.x.trunc() as i16, player.y.trunc() as i16);
+        for (node, behind) in path {
+            let (chosen, other) = if behind {
+                (&node.left_bbox, &node.right_bbox)
+            } else {
+                (&node.right_bbox, &node.left_bbox)
+            };
+            canvas.set_draw_color(Color::RED);
+            canvas.draw_rect(self.bbox_to_rect(other)).unwrap();
+            canvas.set_draw_color(Color::GREEN);
+            canvas.draw_rect(self.bbox_to_rect(chosen)).unwrap();
+
+            // The partition only matters inside the two boxes it splits
+            let min = (
+                f32::from(chosen.left.min(other.left)),
+                f32::from((chosen.top - chosen.height).min(other.top - other.height)),
+            );
+            let max = (
+                f32::from((chosen.left + chosen.width).max(other.left + other.width)),
+                f32::from(chosen.top.max(other.top)),
+            );
+            let origin = (f32::from(node.partition_x), f32::from(node.partition_y));
+            let delta = (f32::from(node.delta_x), f32::from(node.delta_y));
+            if let Some((start, end)) = math::extend_line(origin, delta, min, max) {
+                canvas.set_draw_color(Color::WHITE);
+                let (x1, y1) = self.adjust_coord(start.0.round() as i16, start.1.round() as i16);
+                let (x2, y2) = self.adjust_coord(end.0.round() as i16, end.1.round() as i16);
+                self.draw_clipped_line(canvas, Point::new(x1, y1), Point::new(x2, y2));
+            }
+        }
+    }
+
+    // Blue for the lowest floors round the hue wheel through green to red for the highest
+    pub fn height_color(height: i16, min: i16, max: i16) -> Color {
+        let t = if max > min {
+            (f32::from(height) - f32::from(min)) / (f32::from(max) - f32::from(min))
+        } else {
+            0.
+        };
+        let (r, g, b) = color::hsv_to_rgb(240. * (1. - t.clamp(0., 1.)), 0.8, 1.);
+        Color::RGB(r, g, b)
+    }
+
+    // Labels for the top, middle and bottom of the legend bar
+    pub fn height_legend_labels(min: i16, max: i16) -> [i16; 3] {
+        [max, ((i32::from(min) + i32::from(max)) / 2) as i16, min]
+    }
+
+    // Gradient bar down the right of the screen, highest floor at the top
+    pub fn draw_height_legend(&self, level: &Level, canvas: &mut WindowCanvas) {
+        let Some((min, max)) = level.floor_height_range() else {
+            return;
+        };
+        let bar_height = (self.height * self.multiplier / 2) as i32;
+        let top = (self.height * self.multiplier / 4) as i32;
+        let left = ((self.width - 16) * self.multiplier) as i32;
+        for row in 0..bar_height {
+            let height =
+                f32::from(max) - (f32::from(max) - f32::from(min)) * row as f32 / bar_height as f32;
+            canvas.set_draw_color(Self::height_color(height as i16, min, max));
+            canvas
+                .fill_rect(Rect::new(left, top + row, 3 * self.multiplier, 1))
+                .unwrap();
+        }
+        let labels = Self::height_legend_labels(min, max);
+        for (label, y) in labels
+            .iter()
+            .zip([top, top + bar_height / 2, top + bar_height])
+        {
+            let text = label.to_string();
+            let (width, height) = text::text_size(&text, self.multiplier / 2);
+            text::draw_text(
+                canvas,
+                &text,
+                left - width as i32 - 4,
+                y - height as i32 / 2,
+                self.multiplier / 2,
+                self.theme.text,
+            );
+        }
+    }
+
+    // Label every Nth grid line, N a power of two so labels are far enough apart to read.
+    // None when even every 16th line would crowd them.
+    pub fn grid_label_step(pixels_per_unit: f32) -> Option<i16> {
+        const MIN_LABEL_GAP: f32 = 72.; // Screen pixels, room for "-1024,-1024" at scale 1
+        [1, 2, 4, 8, 16]
+            .into_iter()
+            .find(|step| f32::from(step * 128) * pixels_per_unit >= MIN_LABEL_GAP)
+    }
+
+    // World position and screen point of every grid intersection in the playfield, none
+    // when the grid is hidden
+    fn grid_points(&self) -> Vec<(i16, i16, Point)> {
+        let mut points = Vec::new();
+        if !self.show_grid {
+            return points;
+        }
+        let d_start_x = self.x_offset.rem_euclid(Self::GRID_SPACING);
+        let d_start_y = self.x_offset.rem_euclid(Self::GRID_SPACING);
 
         let mut x = if self.x_offset < 0 {
             self.x_offset + d_start_x
@@ -388,12 +1624,563 @@ impl Renderer {
         while y < self.y_offset + self.level_height {
             while x < self.x_offset + self.level_width {
                 let (x1, y1) = self.adjust_coord(x, y);
-                canvas.set_draw_color(Color::WHITE);
-                canvas.draw_point(Point::new(x1, y1)).unwrap();
-                x += 128;
+                if self.playfield().contains_point((x1, y1)) {
+                    points.push((x, y, Point::new(x1, y1)));
+                }
+                x += Self::GRID_SPACING;
             }
             x = origin_x;
-            y += 128;
+            y += Self::GRID_SPACING;
+        }
+        points
+    }
+
+    fn draw_grid(&self, canvas: &mut WindowCanvas) {
+        let pixels_per_unit = self.x_multiplier.floor() * self.zoom / 1000.;
+        let label_step = if self.grid_labels {
+            Self::grid_label_step(pixels_per_unit)
+        } else {
+            None
+        };
+        for (x, y, point) in self.grid_points() {
+            canvas.set_draw_color(self.theme.grid);
+            canvas.draw_point(point).unwrap();
+            if let Some(step) = label_step {
+                if (x / Self::GRID_SPACING) % step == 0 && (y / Self::GRID_SPACING) % step == 0 {
+                    text::draw_text(
+                        canvas,
+                        &format!("{},{}", x, y),
+                        point.x() + 2,
+                        point.y() + 2,
+                        1,
+                        Color::RGB(96, 96, 96),
+                    );
+                }
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_level, level_data, player_at, MiniLevel};
+
+    fn view(renderer: &Renderer) -> (f32, f32, f32, f32, f32) {
+        (
+            renderer.x_multiplier,
+            renderer.y_multiplier,
+            renderer.zoom,
+            renderer.pan_x,
+            renderer.pan_y,
+        )
+    }
+
+    #[test]
+    fn reset_view_restores_fitted_view_after_panning() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 256));
+        let mut renderer = Renderer::new(&Config::default());
+        renderer.reset_view(&level, None);
+        let fitted = view(&renderer);
+        let corner = renderer.adjust_coord(0, 0);
+
+        renderer.zoom_by(2.);
+        renderer.center_on(700., 100.);
+        assert_ne!(renderer.adjust_coord(0, 0), corner);
+
+        renderer.reset_view(&level, None);
+        assert_eq!(view(&renderer), fitted);
+        assert_eq!(renderer.adjust_coord(0, 0), corner);
+    }
+
+    #[test]
+    fn screen_to_world_inverts_adjust_coord() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 256));
+        let mut renderer = Renderer::new(&Config::default());
+        renderer.reset_view(&level, None);
+        renderer.zoom_by(2.);
+        let (x, y) = renderer.adjust_coord(300, 100);
+        let (world_x, world_y) = renderer.screen_to_world(x, y);
+        // Screen pixels are coarser than map units once zoomed in this far
+        assert!((world_x - 300.).abs() < 1., "{world_x}");
+        assert!((world_y - 100.).abs() < 1., "{world_y}");
+    }
+
+    #[test]
+    fn project_wall_corners() {
+        let mut renderer = Renderer::new(&Config::default());
+        (renderer.render_width, renderer.render_height) = (320, 200);
+        // A wall 100 units ahead spanning 50 either side, from the floor to 100 up, seen
+        // from eye height 41. The focal length is 160, half the width.
+        let player = player_at(0., 0., 0.);
+        let corner = |y, z| renderer.project(&player, 41., 100., y, z);
+        assert_eq!(corner(50., 100.), Some((80, 5)));
+        assert_eq!(corner(-50., 100.), Some((240, 5)));
+        assert_eq!(corner(50., 0.), Some((80, 165)));
+        assert_eq!(corner(-50., 0.), Some((240, 165)));
+        assert_eq!(renderer.project(&player, 41., -100., 0., 0.), None);
+    }
+
+    #[test]
+    fn fov_from_config_sets_the_focal_length() {
+        // At 120° the edges of the view are tan(60°) out for every unit ahead
+        let mut renderer = Renderer::new(&Config::parse("fov = 120"));
+        (renderer.render_width, renderer.render_height) = (320, 200);
+        let player = player_at(0., 0., 0.);
+        let edge = 100. * 3_f32.sqrt();
+        assert_eq!(
+            renderer.project(&player, 0., 100., edge, 0.),
+            Some((0, 100))
+        );
+        // The focal length is 160 / tan(60°), about 92
+        assert_eq!(
+            renderer.project(&player, 0., 100., 0., 100.),
+            Some((160, 7))
+        );
+        // Clipping follows it, so a seg that far out to the left just reaches the view
+        let mut v1 = Vertex { x: 100, y: 173 };
+        let mut v2 = Vertex { x: 100, y: -10 };
+        let (a1, _) = Renderer::is_seg_visible(&player, renderer.fov, &mut v1, &mut v2).unwrap();
+        assert!((a1 - 173_f32.atan2(100.)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn horizon_offset_scales_and_clamps_pitch() {
+        let mut renderer = Renderer::new(&Config::default());
+        renderer.render_width = 320;
+        let max = Renderer::MAX_PITCH;
+        let full = (max * 160.) as i32;
+        assert_eq!(renderer.horizon_offset(0.), 0);
+        assert_eq!(renderer.horizon_offset(max), full);
+        assert_eq!(renderer.horizon_offset(-max), -full);
+        assert_eq!(renderer.horizon_offset(max * 4.), full);
+        assert_eq!(renderer.horizon_offset(-max * 4.), -full);
+    }
+
+    #[test]
+    fn upscale_fills_the_window_on_the_limiting_axis() {
+        let (scale, dest) = Renderer::upscale_rect((320, 200), (1280, 960));
+        assert_eq!(scale, 4.);
+        assert_eq!(dest, Rect::new(0, 80, 1280, 800));
+
+        let (scale, dest) = Renderer::upscale_rect((160, 100), (1000, 1000));
+        assert_eq!(scale, 6.25);
+        assert_eq!(dest, Rect::new(0, 187, 1000, 625));
+
+        let (scale, dest) = Renderer::upscale_rect((320, 200), (320, 200));
+        assert_eq!(scale, 1.);
+        assert_eq!(dest, Rect::new(0, 0, 320, 200));
+    }
+
+    #[test]
+    fn aspect_rect_pillarboxes_wide_windows_and_letterboxes_tall_ones() {
+        // 16:9 is wider than 4:3, so the bars go either side
+        assert_eq!(
+            Renderer::aspect_rect(4. / 3., (1920, 1080)),
+            Rect::new(240, 0, 1440, 1080)
+        );
+        // A square window is taller, so they go above and below
+        assert_eq!(
+            Renderer::aspect_rect(4. / 3., (900, 900)),
+            Rect::new(0, 112, 900, 675)
+        );
+        assert_eq!(
+            Renderer::aspect_rect(16. / 9., (1280, 720)),
+            Rect::new(0, 0, 1280, 720)
+        );
+    }
+
+    #[test]
+    fn follow_mode_keeps_player_centred() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 256));
+        let mut renderer = Renderer::new(&Config::default());
+        renderer.reset_view(&level, None);
+        renderer.zoom_by(2.);
+        renderer.follow = false;
+        renderer.follow_player(&player_at(100., 100., 0.));
+        assert_eq!((renderer.pan_x, renderer.pan_y), (0., 0.));
+
+        renderer.follow = true;
+        for (x, y) in [(100, 100), (700, 20), (0, 255), (-300, 900)] {
+            renderer.follow_player(&player_at(f32::from(x), f32::from(y), 0.));
+            assert_eq!(renderer.adjust_coord(x, y), renderer.viewport_center());
+        }
+    }
+
+    #[test]
+    fn clip_line_inside_outside_and_straddling() {
+        let clip = |rect, (x0, y0), (x1, y1)| {
+            Renderer::clip_line(rect, Point::new(x0, y0), Point::new(x1, y1))
+                .map(|(p0, p1)| ((p0.x(), p0.y()), (p1.x(), p1.y())))
+        };
+        // Covers x 10 to 109 and y 10 to 59
+        let rect = Rect::new(10, 10, 100, 50);
+        assert_eq!(clip(rect, (20, 20), (80, 40)), Some(((20, 20), (80, 40))));
+
+        assert_eq!(clip(rect, (0, 0), (5, 100)), None);
+        assert_eq!(clip(rect, (200, 0), (300, 100)), None);
+        // Ends off different sides, passing outside the corner
+        assert_eq!(clip(rect, (0, 55), (20, 100)), None);
+
+        assert_eq!(clip(rect, (0, 30), (200, 30)), Some(((10, 30), (109, 30))));
+        assert_eq!(clip(rect, (50, 100), (50, 0)), Some(((50, 59), (50, 10))));
+        assert_eq!(clip(rect, (60, 30), (60, -500)), Some(((60, 30), (60, 10))));
+        let square = Rect::new(0, 0, 100, 100);
+        assert_eq!(
+            clip(square, (-50, -50), (150, 150)),
+            Some(((0, 0), (99, 99)))
+        );
+    }
+
+    #[test]
+    fn fov_triangle_spans_the_view() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 256));
+        let mut renderer = Renderer::new(&Config::default());
+        renderer.reset_view(&level, None);
+        let point = |x, y| {
+            let (x, y) = renderer.adjust_coord(x, y);
+            Point::new(x, y)
+        };
+        // Line of sight edges 45° either side, 4000 units out
+        assert_eq!(
+            renderer.fov_triangle(&player_at(0., 0., 0.)),
+            [point(0, 0), point(2828, 2828), point(2828, -2828)]
+        );
+        assert_eq!(
+            renderer.fov_triangle(&player_at(100., 50., consts::FRAC_PI_2)),
+            [point(100, 50), point(-2728, 2878), point(2928, 2878)]
+        );
+    }
+
+    #[test]
+    fn thing_arrow_tip_points_along_facing() {
+        let close = |(x0, y0): (f32, f32), (x1, y1): (f32, f32)| (x0 - x1).hypot(y0 - y1) < 1e-3;
+        for degrees in [0., 45., 90., 200., 315.] {
+            // An imp, radius 20
+            let imp = Thing {
+                x: 100,
+                y: 50,
+                angle_facing: f32::to_radians(degrees),
+                thing_type: 3001,
+                flags: 7,
+            };
+            let [tip, left, right] = Renderer::thing_arrow(&imp);
+            let facing = imp.angle_facing;
+            assert!(close(
+                tip,
+                (100. + 20. * facing.cos(), 50. + 20. * facing.sin())
+            ));
+            // The back corners sit either side of the facing line, behind the centre
+            let midpoint = ((left.0 + right.0) / 2., (left.1 + right.1) / 2.);
+            let behind = 20. * (0.75 * consts::PI).cos();
+            assert!(close(
+                midpoint,
+                (100. + behind * facing.cos(), 50. + behind * facing.sin())
+            ));
+        }
+    }
+
+    #[test]
+    fn flat_texels_wrap_every_64_scaled_pixels() {
+        assert_eq!(Renderer::flat_texel(0, 0, 1), (0, 0));
+        assert_eq!(Renderer::flat_texel(63, 64, 1), (63, 0));
+        assert_eq!(Renderer::flat_texel(130, 200, 1), (2, 8));
+        // At scale 3 each texel covers three screen pixels
+        assert_eq!(Renderer::flat_texel(5, 2, 3), (1, 0));
+        assert_eq!(Renderer::flat_texel(191, 192, 3), (63, 0));
+    }
+
+    #[test]
+    fn grid_labels_thin_out_as_the_map_zooms_out() {
+        // A 128 unit grid square needs to be 72 pixels across for every line to get a label
+        assert_eq!(Renderer::grid_label_step(1.), Some(1));
+        assert_eq!(Renderer::grid_label_step(0.5625), Some(1));
+        assert_eq!(Renderer::grid_label_step(0.5), Some(2));
+        assert_eq!(Renderer::grid_label_step(0.1), Some(8));
+        assert_eq!(Renderer::grid_label_step(0.04), Some(16));
+        assert_eq!(Renderer::grid_label_step(0.03), None);
+    }
+
+    #[test]
+    fn hidden_grid_has_no_points_to_draw() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 256));
+        let mut renderer = Renderer::new(&Config::default());
+        renderer.reset_view(&level, None);
+        let points = renderer.grid_points();
+        // The level spans 768 by 256 units, a grid point every 128
+        assert!(points.len() >= 6 * 2);
+        assert!(points.iter().all(|&(x, y, _)| x % 128 == 0 && y % 128 == 0));
+
+        renderer.toggle_grid();
+        assert!(renderer.grid_points().is_empty());
+        renderer.toggle_grid();
+        assert_eq!(renderer.grid_points(), points);
+    }
+
+    #[test]
+    fn height_legend_spans_the_level_floors() {
+        let mut mini = MiniLevel::row_of_rooms("MAP01", 3, 128);
+        for (room, floor) in mini.rooms.iter_mut().zip([-24, 96, 8]) {
+            room.floor_height = floor;
+        }
+        let (min, max) = build_level(&mini).floor_height_range().unwrap();
+        let labels = Renderer::height_legend_labels(min, max);
+        assert_eq!((labels[0], labels[2]), (96, -24));
+        assert_eq!(labels[1], 36);
+        // The bar's ends are red and blue, and floors off the ends take the end colours
+        assert_eq!(Renderer::height_color(max, min, max).r, 255);
+        assert_eq!(Renderer::height_color(min, min, max).b, 255);
+        assert_eq!(
+            Renderer::height_color(max + 100, min, max),
+            Renderer::height_color(max, min, max)
+        );
+    }
+
+    #[test]
+    fn seg_visibility_around_angle_zero() {
+        let player = player_at(0., 0., 0.);
+        let visible = |(x1, y1), (x2, y2)| {
+            Renderer::is_seg_visible(
+                &player,
+                consts::FRAC_PI_2,
+                &mut Vertex { x: x1, y: y1 },
+                &mut Vertex { x: x2, y: y2 },
+            )
+        };
+        let close =
+            |(a, b): (f32, f32), (c, d): (f32, f32)| (a - c).abs() < 1e-4 && (b - d).abs() < 1e-4;
+        let half = 0.5_f32.atan();
+
+        // Straight ahead and straddling angle 0, which used to be thrown away
+        let ahead = visible((100, 50), (100, -50)).unwrap();
+        assert!(close(ahead, (half, 2. * consts::PI - half)));
+        // Wider than the view, so both ends are clipped to its edges
+        let wide = visible((100, 200), (100, -200)).unwrap();
+        assert!(close(wide, (consts::FRAC_PI_4, -consts::FRAC_PI_4)));
+        // Just behind-left and just behind-right of the player
+        assert_eq!(visible((-50, 100), (10, 100)), None);
+        assert_eq!(visible((10, -100), (-50, -100)), None);
+        // Seen from behind
+        assert_eq!(visible((100, -50), (100, 50)), None);
+    }
+
+    #[test]
+    fn unchanged_frames_are_skipped_only_with_dirty_tracking() {
+        let mut renderer = Renderer::new(&Config::default());
+        let player = player_at(64., 64., 0.);
+        renderer.frame_unchanged(&player);
+        assert!(!renderer.frame_unchanged(&player));
+
+        renderer.toggle_dirty_tracking();
+        assert!(!renderer.frame_unchanged(&player));
+        assert!(renderer.frame_unchanged(&player));
+        // Moving, turning, zooming and invalidating all need a redraw
+        assert!(!renderer.frame_unchanged(&player_at(65., 64., 0.)));
+        assert!(!renderer.frame_unchanged(&player_at(65., 64., 0.5)));
+        renderer.zoom_by(2.);
+        assert!(!renderer.frame_unchanged(&player_at(65., 64., 0.5)));
+        renderer.invalidate();
+        assert!(!renderer.frame_unchanged(&player_at(65., 64., 0.5)));
+        assert!(renderer.frame_unchanged(&player_at(65., 64., 0.5)));
+        // So do the toggles that change the picture
+        renderer.toggle_grid();
+        assert!(!renderer.frame_unchanged(&player_at(65., 64., 0.5)));
+        renderer.next_theme();
+        assert!(!renderer.frame_unchanged(&player_at(65., 64., 0.5)));
+        assert!(renderer.frame_unchanged(&player_at(65., 64., 0.5)));
+    }
+
+    #[test]
+    fn intersections_stay_on_the_seg() {
+        let player = player_at(0., 0., 0.);
+        let (v1, v2) = (Vertex { x: 100, y: -50 }, Vertex { x: 100, y: 50 });
+        let cross =
+            |angle: f32| Renderer::find_intersection(&player, angle, &v1, &v2).map(|v| (v.x, v.y));
+        assert_eq!(cross(0.), Some((100, 0)));
+        assert_eq!(cross(0.3), Some((100, 30)));
+        // The infinite line is crossed at y 68, well past the seg's end
+        assert_eq!(cross(0.6), None);
+        assert_eq!(cross(-0.6), None);
+        // Half a unit past the end is rounding, so it snaps to the end
+        assert_eq!(cross(0.505_f32.atan()), Some((100, 50)));
+        // Looking parallel to the seg never crosses it
+        assert_eq!(cross(consts::FRAC_PI_2), None);
+    }
+
+    #[test]
+    fn renderer_takes_render_size_from_config() {
+        let config = Config::parse("render_width = 160\nrender_height = 100\n");
+        let renderer = Renderer::new(&config);
+        assert_eq!((renderer.render_width, renderer.render_height), (160, 100));
+        assert_eq!(Config::parse(&config.merge_into("")), config);
+        // Zero would leave nothing to draw, so it falls back to the default
+        let config = Config::parse("render_width = 0\n");
+        assert_eq!((config.render_width, config.render_height), (320, 200));
+    }
+
+    #[test]
+    fn flip_y_mirrors_about_the_viewport_centre() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 3, 256));
+        let mut renderer = Renderer::new(&Config::default());
+        renderer.reset_view(&level, None);
+        let (_, centre_y) = renderer.viewport_center();
+        let (x, flipped) = renderer.adjust_coord(100, 200);
+        renderer.toggle_flip_y();
+        let (unflipped_x, unflipped) = renderer.adjust_coord(100, 200);
+        assert_eq!(unflipped_x, x);
+        assert_eq!(flipped - centre_y, centre_y - unflipped);
+        // North is up by default
+        assert!(flipped < centre_y);
+        let (wx, wy) = renderer.screen_to_world(x, unflipped);
+        assert!((wx - 100.).abs() < 2. && (wy - 200.).abs() < 2.);
+    }
+
+    #[test]
+    fn search_path_matches_find_partial_until_the_player_moves() {
+        fn same(a: &Child, b: &Child) -> bool {
+            match (a, b) {
+                (Child::NODE(a), Child::NODE(b)) => Rc::ptr_eq(a, b),
+                (Child::SUBSECTOR(a), Child::SUBSECTOR(b)) => Rc::ptr_eq(a, b),
+                _ => false,
+            }
+        }
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 4, 128));
+        let mut renderer = Renderer::new(&Config::default());
+        for (x, y) in [(64, 64), (450, 64)] {
+            renderer.update_search_path(&level, x, y);
+            let cached = renderer.search_path.as_ref().unwrap();
+            assert_eq!(cached.position, (x, y));
+            // Depths past the subsector keep finding it, so go one further than the path
+            for depth in 1..=cached.path.len() + 1 {
+                let found = level.root_node.find_partial(x, y, depth as u32);
+                assert!(same(
+                    &cached.path[(depth - 1).min(cached.path.len() - 1)],
+                    found
+                ));
+            }
+        }
+        // The last walk was for the fourth room, so the first room is a fresh walk
+        let before = renderer.search_path.as_ref().unwrap().path.len();
+        renderer.update_search_path(&level, 64, 64);
+        assert_ne!(renderer.search_path.as_ref().unwrap().path.len(), before);
+        // A different level with the same player position walks again
+        let other = build_level(&MiniLevel::row_of_rooms("MAP02", 4, 128));
+        renderer.update_search_path(&other, 64, 64);
+        let cached = renderer.search_path.as_ref().unwrap();
+        assert!(Rc::ptr_eq(&cached.root, &other.root_node));
+    }
+
+    #[test]
+    fn draw_order_only_depends_on_the_player() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 5, 128));
+        let mut renderer = Renderer::new(&Config::default());
+        let player = player_at(320., 64., 0.);
+        let order = renderer.draw_order(&player, &level);
+        assert_eq!(order, [2, 3, 4]);
+        assert_eq!(renderer.draw_order(&player, &level), order);
+        renderer.toggle_bsp_index_order();
+        assert_eq!(renderer.draw_order(&player, &level), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn nearer_ceiling_hides_a_step_beyond_it() {
+        // Looking east from the far west of room 0, room 1's raised ceiling steps down to
+        // room 2's. Room 0's ceiling reaches lower on screen than the top of that step.
+        let mut rooms = MiniLevel::row_of_rooms("MAP01", 3, 512);
+        rooms.rooms[1].ceiling_height = 256;
+        let level = build_level(&rooms);
+        let renderer = Renderer::new(&Config::default());
+        let player = player_at(16., 256., 0.);
+        let mut framebuffer = Framebuffer::new(renderer.render_width, renderer.render_height);
+        renderer.draw_3d_walls(&player, &level, &mut framebuffer);
+        let centre = |y: usize| framebuffer.pixels[y * framebuffer.width as usize + 160];
+        // The step runs from about row 66 to 86 but is cut off below room 0's ceiling at 72
+        assert_eq!(centre(68), 0);
+        assert_ne!(centre(80), 0);
+
+        let mut wireframe = Framebuffer::new(renderer.render_width, renderer.render_height);
+        renderer.draw_3d_wireframe(&player, &level, &mut wireframe);
+        assert!(wireframe.pixels.iter().any(|&pixel| pixel != 0));
+        assert_ne!(wireframe.pixels, framebuffer.pixels);
+    }
+
+    #[test]
+    fn secret_lines_pass_for_walls_until_revealed() {
+        let mut data = level_data(&MiniLevel::row_of_rooms("MAP01", 2, 128));
+        data.linedefs[3].flags |= 0x0020;
+        let level = Level::new(&data);
+        let secret = &level.linedefs[3];
+        assert!(secret.is_secret_on_map());
+        assert_eq!(Renderer::map_class(secret, false), LineClass::OneSided);
+        assert_eq!(Renderer::map_class(secret, true), LineClass::TwoSided);
+
+        let mut renderer = Renderer::new(&Config::default());
+        let wall = renderer.line_style(&level.linedefs[0]);
+        assert_eq!(renderer.line_style(secret), wall);
+        renderer.toggle_reveal_secrets();
+        assert_eq!(
+            renderer.line_style(secret),
+            (renderer.theme.two_sided, false)
+        );
+    }
+
+    #[test]
+    fn map_flags_override_what_has_been_explored() {
+        let mut data = level_data(&MiniLevel::one_room("MAP01", 128));
+        data.linedefs[1].flags |= 0x0080;
+        data.linedefs[2].flags |= 0x0100;
+        let level = Level::new(&data);
+        let drawn = |explored: Option<&HashSet<usize>>| -> Vec<usize> {
+            (0..level.linedefs.len())
+                .filter(|&idx| Renderer::line_revealed(explored, idx, &level.linedefs[idx]))
+                .collect()
+        };
+        // The hidden north wall never shows, the south wall always does
+        assert_eq!(drawn(None), [0, 2, 3]);
+        assert_eq!(drawn(Some(&HashSet::from([0, 1]))), [0, 2]);
+        assert_eq!(drawn(Some(&HashSet::new())), [2]);
+    }
+
+    #[test]
+    fn switching_themes_changes_line_colours() {
+        let level = build_level(&MiniLevel::row_of_rooms("MAP01", 2, 128));
+        let (wall, two_sided) = (&level.linedefs[0], &level.linedefs[3]);
+        let mut renderer = Renderer::new(&Config::default());
+        assert_eq!(renderer.theme, Theme::classic());
+        let classic = (renderer.line_style(wall), renderer.line_style(two_sided));
+        renderer.next_theme();
+        assert_eq!(renderer.theme, Theme::high_contrast());
+        assert_ne!(
+            (renderer.line_style(wall), renderer.line_style(two_sided)),
+            classic
+        );
+        assert_eq!(renderer.line_style(wall), (renderer.theme.wall, true));
+        // Past the last preset it comes back round to the first
+        for _ in 1..Theme::presets().len() {
+            renderer.next_theme();
+        }
+        assert_eq!(
+            (renderer.line_style(wall), renderer.line_style(two_sided)),
+            classic
+        );
+    }
+
+    #[test]
+    fn dashes_alternate_on_and_off_along_the_line() {
+        let xs = |points: Vec<Point>| -> Vec<i32> { points.iter().map(|p| p.x()).collect() };
+        // Three on, two off across eleven pixels, so the last dash is cut short
+        let points = Renderer::dash_points(Point::new(0, 5), Point::new(10, 5), 3, 2);
+        assert!(points.iter().all(|p| p.y() == 5));
+        assert_eq!(xs(points), [0, 1, 2, 5, 6, 7, 10]);
+        // Drawn the other way the pattern starts from the other end
+        let points = Renderer::dash_points(Point::new(10, 5), Point::new(0, 5), 3, 2);
+        assert_eq!(xs(points), [10, 9, 8, 5, 4, 3, 0]);
+        // A diagonal steps both ways at once, and no gap gives a solid line
+        let points = Renderer::dash_points(Point::new(0, 0), Point::new(3, 3), 1, 0);
+        assert_eq!(points, (0..4).map(|i| Point::new(i, i)).collect::<Vec<_>>());
+        assert_eq!(
+            Renderer::dash_points(Point::new(0, 0), Point::new(0, 7), 2, 2),
+            [0, 1, 4, 5].map(|y| Point::new(0, y))
+        );
+    }
+}
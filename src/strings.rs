@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use crate::{dehacked::DehackedPatch, level_names};
+
+// Doom's default English strings (d_englsh.h), keyed by their BEX mnemonics
+const DEFAULT_STRINGS: &[(&str, &str)] = &[
+    ("PRESSKEY", "press a key."),
+    ("PRESSYN", "press y or n."),
+    ("QUITMSG", "are you sure you want to\nquit this great game?"),
+    ("LOADNET", "you can't do load while in a net game!\n\npress a key."),
+    ("QLOADNET", "you can't quickload during a netgame!\n\npress a key."),
+    ("QSAVESPOT", "you haven't picked a quicksave slot yet!\n\npress a key."),
+    ("SAVEDEAD", "you can't save if you aren't playing!\n\npress a key."),
+    ("QSPROMPT", "quicksave over your game named\n\n'%s'?\n\npress y or n."),
+    ("QLPROMPT", "do you want to quickload the game named\n\n'%s'?\n\npress y or n."),
+    ("NEWGAME", "you can't start a new game\nwhile in a network game.\n\npress a key."),
+    ("NIGHTMARE", "are you sure? this skill level\nisn't even remotely fair.\n\npress y or n."),
+    ("SWSTRING", "this is the shareware version of doom.\n\nyou need to order the entire trilogy.\n\npress a key."),
+    ("MSGOFF", "Messages OFF"),
+    ("MSGON", "Messages ON"),
+    ("NETEND", "you can't end a netgame!\n\npress a key."),
+    ("ENDGAME", "are you sure you want to end the game?\n\npress y or n."),
+    ("DOSY", "(press y to quit to dos.)"),
+    ("DETAILHI", "High detail"),
+    ("DETAILLO", "Low detail"),
+    ("GAMMALVL0", "Gamma correction OFF"),
+    ("GAMMALVL1", "Gamma correction level 1"),
+    ("GAMMALVL2", "Gamma correction level 2"),
+    ("GAMMALVL3", "Gamma correction level 3"),
+    ("GAMMALVL4", "Gamma correction level 4"),
+    ("EMPTYSTRING", "empty slot"),
+    ("GOTARMOR", "Picked up the armor."),
+    ("GOTMEGA", "Picked up the MegaArmor!"),
+    ("GOTHTHBONUS", "Picked up a health bonus."),
+    ("GOTARMBONUS", "Picked up an armor bonus."),
+    ("GOTSTIM", "Picked up a stimpack."),
+    ("GOTMEDINEED", "Picked up a medikit that you REALLY need!"),
+    ("GOTMEDIKIT", "Picked up a medikit."),
+    ("GOTSUPER", "Supercharge!"),
+    ("GOTBLUECARD", "Picked up a blue keycard."),
+    ("GOTYELWCARD", "Picked up a yellow keycard."),
+    ("GOTREDCARD", "Picked up a red keycard."),
+    ("GOTBLUESKUL", "Picked up a blue skull key."),
+    ("GOTYELWSKUL", "Picked up a yellow skull key."),
+    ("GOTREDSKULL", "Picked up a red skull key."),
+    ("PD_BLUEK", "You need a blue key to open this door"),
+    ("PD_REDK", "You need a red key to open this door"),
+    ("PD_YELLOWK", "You need a yellow key to open this door"),
+];
+
+pub struct Strings {
+    table: HashMap<String, String>,
+}
+
+impl Default for Strings {
+    fn default() -> Self {
+        let mut table: HashMap<String, String> = DEFAULT_STRINGS
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        for episode in 1..=3 {
+            for map in 1..=9 {
+                let name = format!("E{}M{}", episode, map);
+                table.insert(format!("HUSTR_{}", name), level_names::display_name(&name));
+            }
+        }
+        Strings { table }
+    }
+}
+
+impl Strings {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.table.get(key).map(String::as_str)
+    }
+
+    pub fn apply_dehacked(&mut self, patch: &DehackedPatch) {
+        // Old-style Text patches replace by matching the original string
+        for replacement in patch.texts.iter() {
+            self.table
+                .values_mut()
+                .filter(|value| **value == replacement.from)
+                .for_each(|value| *value = replacement.to.clone());
+        }
+        for (key, value) in patch.strings.iter() {
+            self.table.insert(key.to_ascii_uppercase(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dehacked_overrides_known_strings() {
+        let mut strings = Strings::default();
+        assert_eq!(strings.get("HUSTR_E1M1"), Some("E1M1: Hangar"));
+        let patch = DehackedPatch::parse(
+            "Text 12 11\nE1M1: HangarE1M1: Depot\n[STRINGS]\nquitmsg = leaving so soon?\n",
+        );
+        strings.apply_dehacked(&patch);
+        assert_eq!(strings.get("QUITMSG"), Some("leaving so soon?"));
+        assert_eq!(strings.get("HUSTR_E1M1"), Some("E1M1: Depot"));
+        assert_eq!(strings.get("PRESSYN"), Some("press y or n."));
+        assert_eq!(strings.get("NOSUCHKEY"), None);
+    }
+}
@@ -0,0 +1,476 @@
+// Builds tiny levels and WADs from scratch, so parsing and level queries can be tested
+// without the commercial IWADs. A level is a row of rectangular rooms side by side along x,
+// each its own sector and subsector, with one BSP node splitting off each shared wall.
+
+use crate::{
+    interface::Player,
+    level::Level,
+    wad::{
+        BBox, Blockmap, ChildIdx, LevelData, Linedef, MapNode, Sector, Segment, Sidedef, SubSector,
+        Thing, Vertex,
+    },
+};
+
+pub struct MiniRoom {
+    pub width: i16,
+    pub floor_height: i16,
+    pub ceiling_height: i16,
+    pub light_level: i16,
+}
+
+pub struct MiniLevel {
+    pub name: String,
+    pub depth: i16,                   // Every room runs from y = 0 up to this
+    pub rooms: Vec<MiniRoom>,         // West to east, the first starting at x = 0
+    pub things: Vec<(i16, i16, i16)>, // x, y, thing type
+}
+
+impl MiniLevel {
+    // A square room with player 1 starting in the middle
+    pub fn one_room(name: &str, size: i16) -> Self {
+        MiniLevel::row_of_rooms(name, 1, size)
+    }
+
+    // Square rooms joined by open two-sided lines, with player 1 in the middle of the first
+    pub fn row_of_rooms(name: &str, count: usize, size: i16) -> Self {
+        MiniLevel {
+            name: name.to_string(),
+            depth: size,
+            rooms: (0..count)
+                .map(|_| MiniRoom {
+                    width: size,
+                    floor_height: 0,
+                    ceiling_height: 128,
+                    light_level: 160,
+                })
+                .collect(),
+            things: vec![(size / 2, size / 2, 1)],
+        }
+    }
+}
+
+// Linedefs come room by room: west wall, north wall, south wall, so room k's are 3k to
+// 3k + 2, then the last room's east wall. A west wall after the first room is the open line
+// shared with the room before, with that room on its right.
+pub fn level_data(level: &MiniLevel) -> LevelData {
+    let rooms = level.rooms.len();
+    let depth = level.depth;
+    let mut edges = vec![0];
+    for room in level.rooms.iter() {
+        edges.push(edges[edges.len() - 1] + room.width);
+    }
+
+    // Vertex 2i is the south end of the north-south wall at edges[i], 2i + 1 its north end
+    let vertexes: Vec<Vertex> = edges
+        .iter()
+        .flat_map(|&x| [Vertex { x, y: 0 }, Vertex { x, y: depth }])
+        .collect();
+
+    let sectors: Vec<Sector> = level
+        .rooms
+        .iter()
+        .enumerate()
+        .map(|(id, room)| Sector {
+            floor_height: room.floor_height,
+            ceiling_height: room.ceiling_height,
+            floor_tex: "FLOOR4_8".to_string(),
+            ceiling_tex: "CEIL3_5".to_string(),
+            light_level: room.light_level,
+            special_type: 0,
+            tag: 0,
+            id,
+        })
+        .collect();
+
+    let mut sidedefs = Vec::new();
+    let mut side = |sector: usize, middle_tex: &str| {
+        sidedefs.push(Sidedef {
+            x_off: 0,
+            y_off: 0,
+            upper_tex: "-".to_string(),
+            lower_tex: "-".to_string(),
+            middle_tex: middle_tex.to_string(),
+            sector,
+        });
+        sidedefs.len() - 1
+    };
+    let mut linedefs = Vec::new();
+    // Impassable and one-sided, or two-sided and open
+    let mut line = |start_vert: usize, end_vert: usize, right: usize, left: Option<usize>| {
+        linedefs.push(Linedef {
+            start_vert,
+            end_vert,
+            flags: if left.is_some() { 0x0004 } else { 0x0001 },
+            special_type: 0,
+            sector_tag: 0,
+            right_sidedef: right,
+            left_sidedef: left.unwrap_or(65535),
+        });
+        linedefs.len() - 1
+    };
+    let mut walls = Vec::new(); // (west, north, south) linedefs of each room
+    for room in 0..rooms {
+        let west = if room == 0 {
+            line(0, 1, side(0, "STARTAN3"), None)
+        } else {
+            let (right, left) = (side(room - 1, "-"), side(room, "-"));
+            line(2 * room + 1, 2 * room, right, Some(left))
+        };
+        let north = line(2 * room + 1, 2 * room + 3, side(room, "STARTAN3"), None);
+        let south = line(2 * room + 2, 2 * room, side(room, "STARTAN3"), None);
+        walls.push((west, north, south));
+    }
+    let east = line(2 * rooms + 1, 2 * rooms, side(rooms - 1, "STARTAN3"), None);
+
+    // Each room's segs run clockwise from its west wall, so the room is on their right
+    let mut segs = Vec::new();
+    let mut subsectors = Vec::new();
+    for (room, &(west, north, south)) in walls.iter().enumerate() {
+        let east = walls.get(room + 1).map_or(east, |next| next.0);
+        let corners = [
+            (2 * room, 2 * room + 1, west, room == 0),
+            (2 * room + 1, 2 * room + 3, north, true),
+            (2 * room + 3, 2 * room + 2, east, true),
+            (2 * room + 2, 2 * room, south, true),
+        ];
+        subsectors.push(Box::new(SubSector {
+            segment_count: corners.len(),
+            first_segment: segs.len(),
+        }));
+        for (start_vert, end_vert, linedef, dir_like_linedef) in corners {
+            let (start, end) = (vertexes[start_vert], vertexes[end_vert]);
+            let angle = f32::from(end.y - start.y).atan2(f32::from(end.x - start.x));
+            segs.push(Segment {
+                start_vert,
+                end_vert,
+                angle: angle.rem_euclid(std::f32::consts::TAU),
+                linedef,
+                dir_like_linedef,
+                offset: 0,
+            });
+        }
+    }
+
+    // Node i splits at the wall between rooms rooms - 1 - i and rooms - i, the room to the
+    // west behind it. Children come before parents, so the root is last. A lone room still
+    // gets a node with the room on both sides, as Level needs a root.
+    let room_box = |first: usize, last: usize| BBox {
+        top: depth,
+        left: edges[first],
+        width: edges[last + 1] - edges[first],
+        height: depth,
+    };
+    let nodes: Vec<MapNode> = if rooms == 1 {
+        vec![MapNode {
+            partition_x: 0,
+            partition_y: 0,
+            delta_x: 0,
+            delta_y: depth,
+            right_bbox: room_box(0, 0),
+            left_bbox: room_box(0, 0),
+            right_child: ChildIdx::Subsector(0),
+            left_child: ChildIdx::Subsector(0),
+            id: 0,
+        }]
+    } else {
+        (0..rooms - 1)
+            .map(|id| {
+                let east_room = rooms - 1 - id;
+                MapNode {
+                    partition_x: edges[east_room],
+                    partition_y: 0,
+                    delta_x: 0,
+                    delta_y: depth,
+                    right_bbox: room_box(east_room, rooms - 1),
+                    left_bbox: room_box(east_room - 1, east_room - 1),
+                    right_child: if id == 0 {
+                        ChildIdx::Subsector(east_room as i16)
+                    } else {
+                        ChildIdx::Node(id as i16 - 1)
+                    },
+                    left_child: ChildIdx::Subsector(east_room as i16 - 1),
+                    id,
+                }
+            })
+            .collect()
+    };
+
+    // Every block lists every linedef, which is more than needed but never wrong
+    let columns = (edges[rooms] / 128 + 1) as usize;
+    let rows = (depth / 128 + 1) as usize;
+    let blockmap = Blockmap {
+        origin_x: 0,
+        origin_y: 0,
+        columns,
+        rows,
+        blocks: vec![(0..linedefs.len()).collect(); columns * rows],
+    };
+
+    let things = level
+        .things
+        .iter()
+        .map(|&(x, y, thing_type)| Thing {
+            x,
+            y,
+            angle_facing: 90_f32.to_radians(),
+            thing_type,
+            flags: 7, // On every skill
+        })
+        .collect();
+
+    LevelData {
+        name: level.name.clone(),
+        things,
+        linedefs,
+        sidedefs,
+        vertexes,
+        segs,
+        subsectors,
+        nodes,
+        sectors,
+        blockmap,
+    }
+}
+
+pub fn build_level(level: &MiniLevel) -> Level {
+    Level::new(&level_data(level))
+}
+
+// A player standing at x, y, facing angle radians anticlockwise from east
+pub fn player_at(x: f32, y: f32, angle: f32) -> Player {
+    Player {
+        x,
+        y,
+        angle,
+        pitch: 0.,
+        vx: 0.,
+        vy: 0.,
+    }
+}
+
+fn put_i16(lump: &mut Vec<u8>, value: i16) {
+    lump.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_name(lump: &mut Vec<u8>, name: &str) {
+    let mut padded = [0u8; 8];
+    padded[..name.len().min(8)].copy_from_slice(&name.as_bytes()[..name.len().min(8)]);
+    lump.extend_from_slice(&padded);
+}
+
+fn put_bbox(lump: &mut Vec<u8>, bbox: &BBox) {
+    for value in [
+        bbox.top,
+        bbox.top - bbox.height,
+        bbox.left,
+        bbox.left + bbox.width,
+    ] {
+        put_i16(lump, value);
+    }
+}
+
+fn put_child(lump: &mut Vec<u8>, child: ChildIdx) {
+    match child {
+        ChildIdx::Node(idx) => put_i16(lump, idx),
+        ChildIdx::Subsector(idx) => put_i16(lump, idx | 0x8000u16 as i16),
+    }
+}
+
+// The marker and the ten lumps of a level, in the order the parser expects them
+pub fn level_lumps(level: &LevelData) -> Vec<(String, Vec<u8>)> {
+    let mut things = Vec::new();
+    for thing in level.things.iter() {
+        let angle = thing.angle_facing.to_degrees().round() as i16;
+        for value in [thing.x, thing.y, angle, thing.thing_type, thing.flags] {
+            put_i16(&mut things, value);
+        }
+    }
+
+    let mut linedefs = Vec::new();
+    for linedef in level.linedefs.iter() {
+        for value in [
+            linedef.start_vert,
+            linedef.end_vert,
+            linedef.flags as u16 as usize,
+            linedef.special_type as u16 as usize,
+            linedef.sector_tag,
+            linedef.right_sidedef,
+            linedef.left_sidedef,
+        ] {
+            put_i16(&mut linedefs, value as u16 as i16);
+        }
+    }
+
+    let mut sidedefs = Vec::new();
+    for sidedef in level.sidedefs.iter() {
+        put_i16(&mut sidedefs, sidedef.x_off);
+        put_i16(&mut sidedefs, sidedef.y_off);
+        put_name(&mut sidedefs, &sidedef.upper_tex);
+        put_name(&mut sidedefs, &sidedef.lower_tex);
+        put_name(&mut sidedefs, &sidedef.middle_tex);
+        put_i16(&mut sidedefs, sidedef.sector as i16);
+    }
+
+    let mut vertexes = Vec::new();
+    for vertex in level.vertexes.iter() {
+        put_i16(&mut vertexes, vertex.x);
+        put_i16(&mut vertexes, vertex.y);
+    }
+
+    let mut segs = Vec::new();
+    for seg in level.segs.iter() {
+        let binary_angle = (seg.angle / std::f32::consts::TAU * 65536.) as u32 as u16;
+        for value in [
+            seg.start_vert as i16,
+            seg.end_vert as i16,
+            binary_angle as i16,
+            seg.linedef as i16,
+            i16::from(!seg.dir_like_linedef),
+            seg.offset,
+        ] {
+            put_i16(&mut segs, value);
+        }
+    }
+
+    let mut ssectors = Vec::new();
+    for ssec in level.subsectors.iter() {
+        put_i16(&mut ssectors, ssec.segment_count as i16);
+        put_i16(&mut ssectors, ssec.first_segment as i16);
+    }
+
+    let mut nodes = Vec::new();
+    for node in level.nodes.iter() {
+        for value in [
+            node.partition_x,
+            node.partition_y,
+            node.delta_x,
+            node.delta_y,
+        ] {
+            put_i16(&mut nodes, value);
+        }
+        put_bbox(&mut nodes, &node.right_bbox);
+        put_bbox(&mut nodes, &node.left_bbox);
+        put_child(&mut nodes, node.right_child);
+        put_child(&mut nodes, node.left_child);
+    }
+
+    let mut sectors = Vec::new();
+    for sector in level.sectors.iter() {
+        put_i16(&mut sectors, sector.floor_height);
+        put_i16(&mut sectors, sector.ceiling_height);
+        put_name(&mut sectors, &sector.floor_tex);
+        put_name(&mut sectors, &sector.ceiling_tex);
+        put_i16(&mut sectors, sector.light_level);
+        put_i16(&mut sectors, sector.special_type);
+        put_i16(&mut sectors, sector.tag as i16);
+    }
+
+    // One bit per sector pair, nothing rejected
+    let reject = vec![0u8; level.sectors.len().pow(2).div_ceil(8)];
+
+    let map = &level.blockmap;
+    let mut blockmap = Vec::new();
+    for value in [
+        map.origin_x,
+        map.origin_y,
+        map.columns as i16,
+        map.rows as i16,
+    ] {
+        put_i16(&mut blockmap, value);
+    }
+    let mut list_offset = 4 + map.blocks.len(); // In 2-byte words from the start of the lump
+    for block in map.blocks.iter() {
+        put_i16(&mut blockmap, list_offset as i16);
+        list_offset += block.len() + 2;
+    }
+    for block in map.blocks.iter() {
+        put_i16(&mut blockmap, 0);
+        for &linedef in block.iter() {
+            put_i16(&mut blockmap, linedef as i16);
+        }
+        put_i16(&mut blockmap, -1);
+    }
+
+    [
+        (level.name.as_str(), Vec::new()),
+        ("THINGS", things),
+        ("LINEDEFS", linedefs),
+        ("SIDEDEFS", sidedefs),
+        ("VERTEXES", vertexes),
+        ("SEGS", segs),
+        ("SSECTORS", ssectors),
+        ("NODES", nodes),
+        ("SECTORS", sectors),
+        ("REJECT", reject),
+        ("BLOCKMAP", blockmap),
+    ]
+    .into_iter()
+    .map(|(name, lump)| (name.to_string(), lump))
+    .collect()
+}
+
+// A WAD holding the given lumps in order, id being IWAD or PWAD
+pub fn build_wad(id: &str, lumps: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let data_size: usize = lumps.iter().map(|(_, lump)| lump.len()).sum();
+    let mut wad = id.as_bytes().to_vec();
+    wad.extend_from_slice(&(lumps.len() as i32).to_le_bytes());
+    wad.extend_from_slice(&((12 + data_size) as i32).to_le_bytes());
+    let mut directory = Vec::new();
+    for (name, lump) in lumps.iter() {
+        directory.extend_from_slice(&(wad.len() as i32).to_le_bytes());
+        directory.extend_from_slice(&(lump.len() as i32).to_le_bytes());
+        put_name(&mut directory, name);
+        wad.extend_from_slice(lump);
+    }
+    wad.extend(directory);
+    wad
+}
+
+pub fn build_minimal_wad(level: &MiniLevel) -> Vec<u8> {
+    build_wad("PWAD", &level_lumps(&level_data(level)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wad::WadFile;
+
+    #[test]
+    fn one_room_round_trips() {
+        let bytes = build_minimal_wad(&MiniLevel::one_room("MAP01", 256));
+        let mut wad = WadFile::from_bytes(bytes).unwrap();
+        assert_eq!(wad.level_count(), 1);
+        assert_eq!(wad.level_name(0), "MAP01");
+        let level = wad.level(0);
+        assert_eq!(level.things.len(), 1);
+        assert_eq!(level.linedefs.len(), 4);
+        assert_eq!(level.sidedefs.len(), 4);
+        assert_eq!(level.vertexes.len(), 4);
+        assert_eq!(level.segs.len(), 4);
+        assert_eq!(level.subsectors.len(), 1);
+        assert_eq!(level.nodes.len(), 1);
+        assert_eq!(level.sectors.len(), 1);
+        assert_eq!(level.blockmap.blocks.len(), 9);
+    }
+
+    #[test]
+    fn row_of_rooms_round_trips() {
+        let mini = MiniLevel::row_of_rooms("E1M1", 3, 128);
+        let mut wad = WadFile::from_bytes(build_minimal_wad(&mini)).unwrap();
+        let level = wad.level(0);
+        assert_eq!(level.vertexes.len(), 8);
+        assert_eq!(level.linedefs.len(), 10);
+        assert_eq!(level.sidedefs.len(), 12); // Two sides on each of the two shared walls
+        assert_eq!(level.segs.len(), 12);
+        assert_eq!(level.subsectors.len(), 3);
+        assert_eq!(level.nodes.len(), 2);
+        assert_eq!(level.sectors.len(), 3);
+
+        // Parsed or built directly, every room is its own sector
+        for level in [Level::new(level), build_level(&mini)] {
+            for room in 0..3 {
+                assert_eq!(level.sector_at(room * 128 + 64, 64), Some(room as usize));
+            }
+        }
+    }
+}
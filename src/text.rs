@@ -0,0 +1,174 @@
+use sdl2::{pixels::Color, rect::Rect, render::WindowCanvas};
+
+pub const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 7;
+const ADVANCE: u32 = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: u32 = GLYPH_HEIGHT + 2;
+
+// 5x7 bitmap font, one byte per row with the leftmost pixel in bit 4
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '\'' => [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '<' => [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02],
+        '>' => [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08],
+        // Anything we can't draw shows as a question mark
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+// Size in screen pixels of text drawn at the given scale
+pub fn text_size(text: &str, scale: u32) -> (u32, u32) {
+    let columns = text.lines().map(|l| l.chars().count()).max().unwrap_or(0) as u32;
+    let rows = text.lines().count() as u32;
+    (
+        (columns * ADVANCE).saturating_sub(1) * scale,
+        (rows * LINE_HEIGHT).saturating_sub(2) * scale,
+    )
+}
+
+pub fn draw_text(canvas: &mut WindowCanvas, text: &str, x: i32, y: i32, scale: u32, color: Color) {
+    canvas.set_draw_color(color);
+    for (row_idx, line) in text.lines().enumerate() {
+        let top = y + (row_idx as u32 * LINE_HEIGHT * scale) as i32;
+        for (col_idx, c) in line.chars().enumerate() {
+            let left = x + (col_idx as u32 * ADVANCE * scale) as i32;
+            for (py, bits) in glyph(c).iter().enumerate() {
+                for px in 0..GLYPH_WIDTH {
+                    if bits & (0x10 >> px) != 0 {
+                        canvas
+                            .fill_rect(Rect::new(
+                                left + (px * scale) as i32,
+                                top + (py as u32 * scale) as i32,
+                                scale,
+                                scale,
+                            ))
+                            .unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+// One row of a hex dump: offset, the 16 bytes from it in hex, then as ASCII with anything
+// unprintable as a dot. A short last row is padded so the ASCII column lines up.
+pub fn hex_dump_line(bytes: &[u8], offset: usize) -> String {
+    let row = bytes.get(offset..).unwrap_or(&[]);
+    let row = &row[..row.len().min(16)];
+    let hex: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+    let ascii: String = row
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    format!("{:08X}: {:<47}  {}", offset, hex.join(" "), ascii)
+}
+
+// Text in a filled box centred on the given point
+pub fn draw_text_box(
+    canvas: &mut WindowCanvas,
+    text: &str,
+    center: (i32, i32),
+    scale: u32,
+    color: Color,
+    background: Color,
+) {
+    let (width, height) = text_size(text, scale);
+    let padding = 4 * scale;
+    let x = center.0 - (width / 2) as i32;
+    let y = center.1 - (height / 2) as i32;
+    canvas.set_draw_color(background);
+    canvas
+        .fill_rect(Rect::new(
+            x - padding as i32,
+            y - padding as i32,
+            width + 2 * padding,
+            height + 2 * padding,
+        ))
+        .unwrap();
+    draw_text(canvas, text, x, y, scale, color);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dump_lines_hold_sixteen_bytes_with_an_ascii_gutter() {
+        let bytes: Vec<u8> = b"PWAD\x02\x00\x00\x00"
+            .iter()
+            .copied()
+            .chain(0x3c..0x50)
+            .collect();
+        assert_eq!(
+            hex_dump_line(&bytes, 0),
+            "00000000: 50 57 41 44 02 00 00 00 3C 3D 3E 3F 40 41 42 43  PWAD....<=>?@ABC"
+        );
+        // The short last row is padded out to the full width
+        assert_eq!(
+            hex_dump_line(&bytes, 16),
+            format!(
+                "00000010: 44 45 46 47 48 49 4A 4B 4C 4D 4E 4F{}  DEFGHIJKLMNO",
+                " ".repeat(12)
+            )
+        );
+        assert_eq!(
+            hex_dump_line(&bytes, 64),
+            format!("00000040: {}  ", " ".repeat(47))
+        );
+    }
+}
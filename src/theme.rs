@@ -0,0 +1,156 @@
+use sdl2::pixels::Color;
+
+use crate::thing_info::ThingCategory;
+
+// Automap colours. Classic is what the automap has always used; the others are for
+// screenshots and for seeing the map clearly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub name: &'static str,
+    pub background: Color,
+    pub grid: Color,
+    pub text: Color,
+    pub wall: Color, // One-sided lines
+    pub two_sided: Color,
+    pub self_referencing: Color,
+    pub no_sides: Color,
+    pub vertex: Color,
+    pub player: Color,
+    pub view: Color,      // Line of sight and field of view
+    pub highlight: Color, // Marks, measurements and the subsectors in view
+    pub left_bbox: Color, // Node boxes in the BSP views
+    pub right_bbox: Color,
+    pub player_start: Color,
+    pub monster: Color,
+    pub item: Color,
+    pub key: Color,
+    pub decoration: Color,
+    pub other_thing: Color,
+    pub dashed_two_sided: bool, // Dashes rather than colour alone tell two-sided lines apart
+}
+
+impl Theme {
+    pub fn classic() -> Self {
+        Theme {
+            name: "classic",
+            background: Color::BLACK,
+            grid: Color::WHITE,
+            text: Color::WHITE,
+            wall: Color::WHITE,
+            two_sided: Color::GRAY,
+            self_referencing: Color::RGB(255, 128, 0),
+            no_sides: Color::MAGENTA,
+            vertex: Color::CYAN,
+            player: Color::GREEN,
+            view: Color::CYAN,
+            highlight: Color::YELLOW,
+            left_bbox: Color::CYAN,
+            right_bbox: Color::GREEN,
+            player_start: Color::GREEN,
+            monster: Color::RED,
+            item: Color::CYAN,
+            key: Color::YELLOW,
+            decoration: Color::GRAY,
+            other_thing: Color::MAGENTA,
+            dashed_two_sided: false,
+        }
+    }
+
+    // Fully saturated colours only, with the two-sided lines brighter than classic grey
+    pub fn high_contrast() -> Self {
+        Theme {
+            name: "high-contrast",
+            two_sided: Color::RGB(0, 160, 255),
+            vertex: Color::WHITE,
+            decoration: Color::WHITE,
+            ..Theme::classic()
+        }
+    }
+
+    // Dark lines on white, to print or paste into documents
+    pub fn printer() -> Self {
+        Theme {
+            name: "printer",
+            background: Color::WHITE,
+            grid: Color::RGB(192, 192, 192),
+            text: Color::BLACK,
+            wall: Color::BLACK,
+            two_sided: Color::RGB(128, 128, 128),
+            self_referencing: Color::RGB(192, 96, 0),
+            no_sides: Color::RGB(160, 0, 160),
+            vertex: Color::RGB(0, 96, 160),
+            player: Color::RGB(0, 128, 0),
+            view: Color::RGB(0, 96, 160),
+            highlight: Color::RGB(192, 128, 0),
+            left_bbox: Color::RGB(0, 96, 160),
+            right_bbox: Color::RGB(0, 128, 0),
+            player_start: Color::RGB(0, 128, 0),
+            monster: Color::RGB(192, 0, 0),
+            item: Color::RGB(0, 96, 160),
+            key: Color::RGB(192, 128, 0),
+            decoration: Color::RGB(96, 96, 96),
+            other_thing: Color::RGB(160, 0, 160),
+            dashed_two_sided: false,
+        }
+    }
+
+    // Okabe-Ito colours, which stay distinct with red-green colour blindness. Red and green
+    // pairs like monsters and player starts become vermillion and blue.
+    pub fn colorblind() -> Self {
+        let orange = Color::RGB(230, 159, 0);
+        let sky_blue = Color::RGB(86, 180, 233);
+        let yellow = Color::RGB(240, 228, 66);
+        let blue = Color::RGB(0, 114, 178);
+        let vermillion = Color::RGB(213, 94, 0);
+        let purple = Color::RGB(204, 121, 167);
+        Theme {
+            name: "colorblind",
+            background: Color::BLACK,
+            grid: Color::RGB(128, 128, 128),
+            text: Color::WHITE,
+            wall: Color::WHITE,
+            two_sided: sky_blue,
+            self_referencing: orange,
+            no_sides: purple,
+            vertex: sky_blue,
+            player: yellow,
+            view: sky_blue,
+            highlight: yellow,
+            left_bbox: sky_blue,
+            right_bbox: orange,
+            player_start: blue,
+            monster: vermillion,
+            item: sky_blue,
+            key: yellow,
+            decoration: Color::RGB(160, 160, 160),
+            other_thing: purple,
+            dashed_two_sided: true,
+        }
+    }
+
+    pub fn presets() -> Vec<Theme> {
+        vec![
+            Theme::classic(),
+            Theme::high_contrast(),
+            Theme::printer(),
+            Theme::colorblind(),
+        ]
+    }
+
+    pub fn named(name: &str) -> Option<Theme> {
+        Theme::presets()
+            .into_iter()
+            .find(|theme| theme.name == name)
+    }
+
+    pub fn thing_color(&self, category: ThingCategory) -> Color {
+        match category {
+            ThingCategory::PlayerStart => self.player_start,
+            ThingCategory::Monster => self.monster,
+            ThingCategory::Item => self.item,
+            ThingCategory::Key => self.key,
+            ThingCategory::Decoration => self.decoration,
+            ThingCategory::Other => self.other_thing,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ThingCategory {
+    PlayerStart,
+    Monster,
+    Item, // Weapons, ammo, health, armor and powerups
+    Key,
+    Decoration,
+    Other, // Teleport destinations, anything we don't recognise
+}
+
+impl ThingCategory {
+    pub fn of(thing_type: i16) -> Self {
+        match thing_type {
+            1..=4 | 11 => ThingCategory::PlayerStart,
+            7 | 9 | 16 | 58 | 64..=69 | 71 | 72 | 84 | 88 | 89 | 3001..=3006 => {
+                ThingCategory::Monster
+            }
+            5 | 6 | 13 | 38..=40 => ThingCategory::Key,
+            8
+            | 17
+            | 82
+            | 83
+            | 2001..=2008
+            | 2010..=2015
+            | 2018
+            | 2019
+            | 2022..=2026
+            | 2045..=2049 => ThingCategory::Item,
+            10 | 12 | 15 | 18..=37 | 41..=63 | 70 | 73..=81 | 85 | 86 | 2028 | 2035 => {
+                ThingCategory::Decoration
+            }
+            _ => ThingCategory::Other,
+        }
+    }
+}
+
+// (radius, height) in map units, from Doom's mobjinfo table
+pub fn dimensions(thing_type: i16) -> (i16, i16) {
+    match thing_type {
+        1..=4 | 11 => (16, 56),                          // Player starts
+        3004 | 9 | 65 | 3001 | 66 | 64 | 84 => (20, 56), // Zombies, Imp, Revenant, Arch-vile, SS
+        3002 | 58 => (30, 56),                           // Demon, Spectre
+        3006 => (16, 56),                                // Lost Soul
+        3005 | 71 => (31, 56),                           // Cacodemon, Pain Elemental
+        3003 | 69 => (24, 64),                           // Baron, Hell Knight
+        68 => (64, 64),                                  // Arachnotron
+        67 => (48, 64),                                  // Mancubus
+        16 => (40, 110),                                 // Cyberdemon
+        7 => (128, 100),                                 // Spider Mastermind
+        72 => (16, 72),                                  // Commander Keen
+        89 => (20, 32),                                  // Monster spawner
+        2035 => (10, 42),                                // Barrel
+        54 => (32, 16),                                  // Big tree
+        _ => match ThingCategory::of(thing_type) {
+            ThingCategory::Item | ThingCategory::Key => (20, 16),
+            _ => (16, 16),
+        },
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyColor {
+    Blue,
+    Yellow,
+    Red,
+}
+
+impl KeyColor {
+    // Keycards and skull keys of the same colour open the same doors
+    pub fn of(thing_type: i16) -> Option<Self> {
+        match thing_type {
+            5 | 40 => Some(KeyColor::Blue),
+            6 | 39 => Some(KeyColor::Yellow),
+            13 | 38 => Some(KeyColor::Red),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimensions_of_well_known_actors() {
+        let table = [
+            (1, (16, 56)),    // Player 1 start
+            (3004, (20, 56)), // Zombieman
+            (9, (20, 56)),    // Shotgun guy
+            (3001, (20, 56)), // Imp
+            (3002, (30, 56)), // Demon
+            (3006, (16, 56)), // Lost soul
+            (3005, (31, 56)), // Cacodemon
+            (3003, (24, 64)), // Baron of Hell
+            (68, (64, 64)),   // Arachnotron
+            (67, (48, 64)),   // Mancubus
+            (16, (40, 110)),  // Cyberdemon
+            (7, (128, 100)),  // Spider Mastermind
+            (2035, (10, 42)), // Barrel
+            (2011, (20, 16)), // Stimpack
+            (9999, (16, 16)), // Unknown
+            (-1, (16, 16)),   // Nonsense
+        ];
+        for (thing_type, expected) in table {
+            assert_eq!(dimensions(thing_type), expected, "type {}", thing_type);
+        }
+    }
+}
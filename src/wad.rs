@@ -1,5 +1,18 @@
+use std::{
+    f32::consts,
+    fmt,
+    mem::size_of,
+    time::{Duration, Instant},
+};
+
 use regex::Regex;
 
+use crate::{
+    dehacked::DehackedPatch,
+    math::normalize_angle,
+    picture::{self, Colormap, Palette, Picture},
+};
+
 #[derive(Clone, Debug)]
 pub struct Header {
     pub id: String,
@@ -23,6 +36,36 @@ pub struct Thing {
     pub flags: i16,
 }
 
+impl Thing {
+    pub fn facing_radians(&self) -> f32 {
+        normalize_angle(self.angle_facing)
+    }
+
+    // 0 is east, counting anticlockwise in 45° steps; halfway angles round up
+    pub fn facing_octant(&self) -> u8 {
+        ((self.facing_radians() / consts::FRAC_PI_4).round() as u8) % 8
+    }
+
+    // Single player only, so multiplayer-only things never appear
+    pub fn appears_on(&self, skill: Skill) -> bool {
+        let skill_bit = match skill {
+            Skill::Baby | Skill::Easy => 0x01,
+            Skill::Medium => 0x02,
+            Skill::Hard | Skill::Nightmare => 0x04,
+        };
+        self.flags & skill_bit != 0 && self.flags & 0x10 == 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Skill {
+    Baby,      // I'm too young to die
+    Easy,      // Hey, not too rough
+    Medium,    // Hurt me plenty
+    Hard,      // Ultra-Violence
+    Nightmare, // Nightmare!
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Linedef {
     pub start_vert: usize,
@@ -103,9 +146,39 @@ pub struct Sector {
     pub light_level: i16,
     pub special_type: i16,
     pub tag: usize,
+    pub id: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Blockmap {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    pub columns: usize,
+    pub rows: usize,
+    pub blocks: Vec<Vec<usize>>, // Linedef indexes for each 128x128 cell, row by row
+}
+
+impl Blockmap {
+    pub const CELL_SIZE: i32 = 128;
+
+    pub fn cell(&self, x: f32, y: f32) -> (i32, i32) {
+        (
+            ((x - f32::from(self.origin_x)) / Self::CELL_SIZE as f32).floor() as i32,
+            ((y - f32::from(self.origin_y)) / Self::CELL_SIZE as f32).floor() as i32,
+        )
+    }
+
+    pub fn block(&self, column: i32, row: i32) -> Option<&Vec<usize>> {
+        if column < 0 || row < 0 || column as usize >= self.columns || row as usize >= self.rows {
+            return None;
+        }
+        self.blocks
+            .get(row as usize * self.columns + column as usize)
+    }
 }
 
 pub struct LevelData {
+    pub name: String,
     pub things: Vec<Thing>,
     pub linedefs: Vec<Linedef>,
     pub sidedefs: Vec<Sidedef>,
@@ -114,13 +187,149 @@ pub struct LevelData {
     pub subsectors: Vec<Box<SubSector>>,
     pub nodes: Vec<MapNode>,
     pub sectors: Vec<Sector>,
+    pub blockmap: Blockmap,
+}
+
+impl LevelData {
+    // Approximate heap footprint: element sizes times counts, plus string contents
+    pub fn memory_size(&self) -> usize {
+        let sidedef_strings: usize = self
+            .sidedefs
+            .iter()
+            .map(|s| s.upper_tex.len() + s.lower_tex.len() + s.middle_tex.len())
+            .sum();
+        let sector_strings: usize = self
+            .sectors
+            .iter()
+            .map(|s| s.floor_tex.len() + s.ceiling_tex.len())
+            .sum();
+        let blocks: usize = self
+            .blockmap
+            .blocks
+            .iter()
+            .map(|block| size_of::<Vec<usize>>() + block.len() * size_of::<usize>())
+            .sum();
+        size_of::<LevelData>()
+            + self.name.len()
+            + self.things.len() * size_of::<Thing>()
+            + self.linedefs.len() * size_of::<Linedef>()
+            + self.sidedefs.len() * size_of::<Sidedef>()
+            + sidedef_strings
+            + self.vertexes.len() * size_of::<Vertex>()
+            + self.segs.len() * size_of::<Segment>()
+            + self.subsectors.len() * (size_of::<Box<SubSector>>() + size_of::<SubSector>())
+            + self.nodes.len() * size_of::<MapNode>()
+            + self.sectors.len() * size_of::<Sector>()
+            + sector_strings
+            + blocks
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryReport {
+    pub raw_bytes: usize,
+    pub directory: usize,
+    pub levels: usize, // Only levels parsed so far
+    pub textures: usize,
+    pub flats: usize,
 }
 
+impl MemoryReport {
+    pub fn total(&self) -> usize {
+        self.raw_bytes + self.directory + self.levels + self.textures + self.flats
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TexturePatch {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    pub patch: usize, // Index into PNAMES
+}
+
+#[derive(Clone, Debug)]
+pub struct TextureDef {
+    pub name: String,
+    pub width: i16,
+    pub height: i16,
+    pub patches: Vec<TexturePatch>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Flat {
+    pub name: String,
+    pub pixels: Vec<u8>, // 64x64 palette indexes
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameVariant {
+    Shareware,  // doom1.wad, episode 1 only
+    Registered, // doom.wad, episodes 1-3
+    Ultimate,   // The Ultimate Doom, adds episode 4
+    Doom2,      // MAPxx levels
+    Pwad,       // Not a game on its own
+}
+
+impl GameVariant {
+    pub fn episodes(&self) -> usize {
+        match self {
+            GameVariant::Shareware | GameVariant::Doom2 => 1,
+            GameVariant::Registered => 3,
+            GameVariant::Ultimate => 4,
+            GameVariant::Pwad => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WadError {
+    Io { path: String, error: std::io::Error },
+    BadHeader(String), // Neither IWAD nor PWAD
+    Truncated,         // The directory or a lump runs past the end of the file, or before it
+    NoFiles,
+}
+
+impl fmt::Display for WadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WadError::Io { path, error } => write!(f, "{}: {}", path, error),
+            WadError::BadHeader(id) => write!(f, "{:?} is neither IWAD nor PWAD", id),
+            WadError::Truncated => write!(f, "directory or lump runs outside the file"),
+            WadError::NoFiles => write!(f, "no WAD files given"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TextureError {
+    MissingTexture { name: String, linedef: usize },
+    MissingFlat { name: String, sector: usize },
+}
+
+// First and last frames of the flat animations hardcoded into Doom
+const ANIMATED_FLATS: [(&str, &str); 9] = [
+    ("NUKAGE1", "NUKAGE3"),
+    ("FWATER1", "FWATER4"),
+    ("SWATER1", "SWATER4"),
+    ("LAVA1", "LAVA4"),
+    ("BLOOD1", "BLOOD3"),
+    ("RROCK05", "RROCK08"),
+    ("SLIME01", "SLIME04"),
+    ("SLIME05", "SLIME08"),
+    ("SLIME09", "SLIME12"),
+];
+
 pub struct WadFile {
-    pub bytes: Vec<u8>,
+    pub bytes: Vec<u8>, // Empty once drop_raw_bytes has been called
     pub header: Header,
     pub directory: Vec<FileLump>,
-    pub levels: Vec<LevelData>,
+    pub levels: Vec<Option<LevelData>>, // Parsed on first access unless loaded eagerly
+    pub level_markers: Vec<usize>,      // Directory index of each level's marker lump
+    parse_count: usize,
+    parse_timings: Option<Vec<(String, Duration)>>, // Only recorded once enabled
+    pub patch_names: Vec<String>,
+    pub texture_defs: Vec<TextureDef>,
+    pub flats: Vec<Flat>,
 }
 
 impl WadFile {
@@ -145,6 +354,11 @@ impl WadFile {
         i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
     }
 
+    // A count, offset or length from the header or directory, which can't be negative
+    fn get_size(bytes: &[u8]) -> Result<usize, WadError> {
+        usize::try_from(WadFile::get_i32(bytes)).map_err(|_| WadError::Truncated)
+    }
+
     fn get_angle(bytes: &[u8]) -> f32 {
         let binary_angle = u16::from_le_bytes([bytes[0], bytes[1]]);
         (360_f32 * f32::from(binary_angle) / f32::from(u16::MAX)).to_radians()
@@ -178,26 +392,78 @@ impl WadFile {
     }
 
     pub fn load_from(path: &str) -> Self {
-        let bytes = std::fs::read(path).unwrap();
+        WadFile::load(path, false)
+    }
+
+    pub fn load(path: &str, eager_levels: bool) -> Self {
+        let mut wad = WadFile::from_bytes(std::fs::read(path).unwrap()).unwrap();
+        if eager_levels {
+            wad.parse_levels(|_, _| ());
+        }
+        wad
+    }
 
+    // Parses every level up front, reporting (levels parsed, total levels) after each one
+    pub fn parse_levels(&mut self, mut on_progress: impl FnMut(usize, usize)) {
+        let total = self.level_count();
+        for idx in 0..total {
+            self.level(idx);
+            on_progress(idx + 1, total);
+        }
+    }
+
+    // An IWAD followed by PWADs, each overriding what came before it
+    pub fn load_stack(paths: &[&str]) -> Result<WadFile, WadError> {
+        let read = |path: &str| {
+            let bytes = std::fs::read(path).map_err(|error| WadError::Io {
+                path: path.to_string(),
+                error,
+            })?;
+            WadFile::from_bytes(bytes)
+        };
+        let (first, rest) = paths.split_first().ok_or(WadError::NoFiles)?;
+        let mut wad = read(first)?;
+        for path in rest {
+            wad.merge(read(path)?);
+        }
+        Ok(wad)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<WadFile, WadError> {
+        if bytes.len() < 12 {
+            return Err(WadError::Truncated);
+        }
         let header = Header {
             id: std::str::from_utf8(&bytes[0..4])
                 .unwrap_or("IWAD")
                 .to_string(),
-            num_lumps: WadFile::get_i32(&bytes[4..8]) as usize,
-            dir_offset: WadFile::get_i32(&bytes[8..12]) as usize,
+            num_lumps: WadFile::get_size(&bytes[4..8])?,
+            dir_offset: WadFile::get_size(&bytes[8..12])?,
         };
+        if header.id != "IWAD" && header.id != "PWAD" {
+            return Err(WadError::BadHeader(header.id));
+        }
+        let dir_end = (header.num_lumps.checked_mul(16))
+            .and_then(|dir_size| header.dir_offset.checked_add(dir_size));
+        if dir_end.is_none_or(|dir_end| dir_end > bytes.len()) {
+            return Err(WadError::Truncated);
+        }
 
         let mut directory = Vec::with_capacity(header.num_lumps);
         for lump_idx in 0..header.num_lumps {
             let entry_idx = header.dir_offset + lump_idx * 16;
             let name_idx = entry_idx + 8;
 
-            directory.push(FileLump {
-                file_pos: WadFile::get_i32(&bytes[entry_idx..entry_idx + 4]) as usize,
-                size: WadFile::get_i32(&bytes[entry_idx + 4..entry_idx + 8]) as usize,
+            let lump = FileLump {
+                file_pos: WadFile::get_size(&bytes[entry_idx..entry_idx + 4])?,
+                size: WadFile::get_size(&bytes[entry_idx + 4..entry_idx + 8])?,
                 name: WadFile::get_8char_string(&bytes[name_idx..name_idx + 8]),
-            })
+            };
+            let lump_end = lump.file_pos.checked_add(lump.size);
+            if lump_end.is_none_or(|lump_end| lump_end > bytes.len()) {
+                return Err(WadError::Truncated);
+            }
+            directory.push(lump);
         }
 
         // println!("Directory\n=========");
@@ -208,191 +474,1040 @@ impl WadFile {
         //     );
         // });
 
-        let mut levels = Vec::with_capacity(9);
-        let re = Regex::new(r"^E[1234]M[0-9]").unwrap();
-        let mut lump_idx: usize = 0;
-        while lump_idx < directory.len() {
-            let lump = &directory[lump_idx];
+        Ok(WadFile::index(bytes, header, directory))
+    }
+
+    // Builds the level list and texture tables from a directory. Where names repeat, as
+    // after a merge, the later lump wins.
+    fn index(bytes: Vec<u8>, header: Header, directory: Vec<FileLump>) -> Self {
+        let re = Regex::new(r"^(E[1234]M[0-9]|MAP[0-9]{2})").unwrap();
+        let mut level_markers: Vec<usize> = Vec::new();
+        for (lump_idx, lump) in directory.iter().enumerate() {
             if !re.is_match(lump.name.as_str()) {
-                lump_idx += 1;
                 continue;
             }
-            //I'm A Level!
-            lump_idx += 1;
-            let things_lump = &directory[lump_idx];
-            debug_assert!(things_lump.name == "THINGS");
-            let mut things: Vec<Thing> = Vec::with_capacity(things_lump.size / 10); // 10 bytes/each
-            for thing_idx in 0..things.capacity() {
-                let thing_offset = things_lump.file_pos + thing_idx * 10;
-                things.push(Thing {
-                    x: WadFile::get_i16(&bytes[thing_offset..thing_offset + 2]),
-                    y: WadFile::get_i16(&bytes[thing_offset + 2..thing_offset + 4]),
-                    angle_facing: f32::from(WadFile::get_i16(
-                        &bytes[thing_offset + 4..thing_offset + 6],
-                    ))
-                    .to_radians(),
-                    thing_type: WadFile::get_i16(&bytes[thing_offset + 6..thing_offset + 8]),
-                    flags: WadFile::get_i16(&bytes[thing_offset + 8..thing_offset + 10]),
-                })
+            // A replaced level keeps its place in the order
+            match level_markers
+                .iter()
+                .position(|&marker| directory[marker].name == lump.name)
+            {
+                Some(slot) => level_markers[slot] = lump_idx,
+                None => level_markers.push(lump_idx),
             }
+        }
+
+        let last_lump = |name: &str| directory.iter().rev().find(|lump| lump.name == name);
+        let patch_names = match last_lump("PNAMES") {
+            Some(lump) => WadFile::get_patch_names(&bytes, lump),
+            None => Vec::new(),
+        };
+        let texture_defs = ["TEXTURE1", "TEXTURE2"]
+            .into_iter()
+            .filter_map(last_lump)
+            .flat_map(|lump| WadFile::get_texture_defs(&bytes, lump))
+            .collect();
+        let mut flats: Vec<Flat> = Vec::new();
+        for flat in WadFile::get_flats(&bytes, &directory) {
+            match flats.iter().position(|existing| existing.name == flat.name) {
+                Some(slot) => flats[slot] = flat,
+                None => flats.push(flat),
+            }
+        }
+
+        WadFile {
+            bytes,
+            header,
+            directory,
+            levels: level_markers.iter().map(|_| None).collect(),
+            level_markers,
+            parse_count: 0,
+            parse_timings: None,
+            patch_names,
+            texture_defs,
+            flats,
+        }
+    }
+
+    // Layers other on top of this file, as if its lumps were appended to our directory.
+    // Levels are parsed again on next access.
+    pub fn merge(&mut self, other: WadFile) {
+        let base = self.bytes.len();
+        let mut bytes = std::mem::take(&mut self.bytes);
+        bytes.extend_from_slice(&other.bytes);
+        let mut directory = std::mem::take(&mut self.directory);
+        directory.extend(other.directory.into_iter().map(|lump| FileLump {
+            file_pos: lump.file_pos + base,
+            ..lump
+        }));
+        let header = Header {
+            num_lumps: directory.len(),
+            ..self.header.clone()
+        };
+        let parse_count = self.parse_count;
+        let parse_timings = self.parse_timings.take();
+        *self = WadFile::index(bytes, header, directory);
+        self.parse_count = parse_count;
+        self.parse_timings = parse_timings;
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.level_markers.len()
+    }
+
+    pub fn level(&mut self, index: usize) -> &LevelData {
+        if self.levels[index].is_none() {
+            let start = self.parse_timings.is_some().then(Instant::now);
+            let data =
+                WadFile::parse_level(&self.bytes, &self.directory, self.level_markers[index]);
+            if let (Some(timings), Some(start)) = (self.parse_timings.as_mut(), start) {
+                timings.push((data.name.clone(), start.elapsed()));
+            }
+            self.levels[index] = Some(data);
+            self.parse_count += 1;
+        }
+        self.levels[index].as_ref().unwrap()
+    }
+
+    // How many levels have had their geometry parsed so far
+    pub fn parse_count(&self) -> usize {
+        self.parse_count
+    }
+
+    // Episodes with ExMy levels in the WAD. An IWAD only offers the ones its version of the
+    // game has, so the shareware one only ever lists episode 1.
+    pub fn episode_list(&self) -> Vec<usize> {
+        let limit = match self.game_variant() {
+            GameVariant::Pwad => usize::MAX,
+            variant => variant.episodes(),
+        };
+        let mut episodes: Vec<usize> = (0..self.level_count())
+            .filter_map(|idx| {
+                let (episode, _) = self.level_name(idx).strip_prefix('E')?.split_once('M')?;
+                episode.parse().ok()
+            })
+            .filter(|&episode| (1..=limit).contains(&episode))
+            .collect();
+        episodes.sort_unstable();
+        episodes.dedup();
+        episodes
+    }
+
+    pub fn game_variant(&self) -> GameVariant {
+        if self.header.id != "IWAD" {
+            return GameVariant::Pwad;
+        }
+        let has_episode = |episode: char| {
+            (0..self.level_count()).any(|idx| {
+                let name = self.level_name(idx);
+                name.starts_with('E') && name[1..].starts_with(episode)
+            })
+        };
+        if (0..self.level_count()).any(|idx| self.level_name(idx).starts_with("MAP")) {
+            GameVariant::Doom2
+        } else if has_episode('4') {
+            GameVariant::Ultimate
+        } else if has_episode('2') || has_episode('3') {
+            GameVariant::Registered
+        } else {
+            GameVariant::Shareware
+        }
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        MemoryReport {
+            raw_bytes: self.bytes.len(),
+            directory: self
+                .directory
+                .iter()
+                .map(|lump| size_of::<FileLump>() + lump.name.len())
+                .sum(),
+            levels: self
+                .levels
+                .iter()
+                .flatten()
+                .map(LevelData::memory_size)
+                .sum(),
+            textures: self
+                .patch_names
+                .iter()
+                .map(|name| size_of::<String>() + name.len())
+                .sum::<usize>()
+                + self
+                    .texture_defs
+                    .iter()
+                    .map(|def| {
+                        size_of::<TextureDef>()
+                            + def.name.len()
+                            + def.patches.len() * size_of::<TexturePatch>()
+                    })
+                    .sum::<usize>(),
+            flats: self
+                .flats
+                .iter()
+                .map(|flat| size_of::<Flat>() + flat.name.len() + flat.pixels.len())
+                .sum(),
+        }
+    }
+
+    // Start timing level parses from now on; levels already parsed aren't included
+    pub fn enable_parse_timings(&mut self) {
+        self.parse_timings.get_or_insert_with(Vec::new);
+    }
+
+    pub fn parse_timings(&self) -> &[(String, Duration)] {
+        self.parse_timings.as_deref().unwrap_or(&[])
+    }
+
+    pub fn level_name(&self, index: usize) -> &str {
+        &self.directory[self.level_markers[index]].name
+    }
+
+    fn parse_level(bytes: &[u8], directory: &[FileLump], marker_idx: usize) -> LevelData {
+        //I'm A Level!
+        let name = directory[marker_idx].name.clone();
+        let mut lump_idx = marker_idx + 1;
+        let things_lump = &directory[lump_idx];
+        debug_assert!(things_lump.name == "THINGS");
+        let thing_count = things_lump.size / 10; // 10 bytes/each
+        let mut things: Vec<Thing> = Vec::with_capacity(thing_count);
+        for thing_idx in 0..thing_count {
+            let thing_offset = things_lump.file_pos + thing_idx * 10;
+            things.push(Thing {
+                x: WadFile::get_i16(&bytes[thing_offset..thing_offset + 2]),
+                y: WadFile::get_i16(&bytes[thing_offset + 2..thing_offset + 4]),
+                angle_facing: f32::from(WadFile::get_i16(
+                    &bytes[thing_offset + 4..thing_offset + 6],
+                ))
+                .to_radians(),
+                thing_type: WadFile::get_i16(&bytes[thing_offset + 6..thing_offset + 8]),
+                flags: WadFile::get_i16(&bytes[thing_offset + 8..thing_offset + 10]),
+            })
+        }
+
+        lump_idx += 1;
+        let linedefs_lump = &directory[lump_idx];
+        debug_assert!(linedefs_lump.name == "LINEDEFS");
+        let linedef_count = linedefs_lump.size / 14; // 14 bytes/each
+        let mut linedefs: Vec<Linedef> = Vec::with_capacity(linedef_count);
+        for linedef_idx in 0..linedef_count {
+            let linedef_offset = linedefs_lump.file_pos + linedef_idx * 14;
+            linedefs.push(Linedef {
+                start_vert: WadFile::get_i16(&bytes[linedef_offset..linedef_offset + 2]) as usize,
+                end_vert: WadFile::get_i16(&bytes[linedef_offset + 2..linedef_offset + 4]) as usize,
+                flags: WadFile::get_i16(&bytes[linedef_offset + 4..linedef_offset + 6]),
+                special_type: WadFile::get_i16(&bytes[linedef_offset + 6..linedef_offset + 8]),
+                sector_tag: WadFile::get_i16(&bytes[linedef_offset + 8..linedef_offset + 10])
+                    as usize,
+                right_sidedef: WadFile::get_i16(&bytes[linedef_offset + 10..linedef_offset + 12])
+                    as usize,
+                left_sidedef: WadFile::get_i16(&bytes[linedef_offset + 12..linedef_offset + 14])
+                    as usize,
+            })
+        }
 
-            lump_idx += 1;
-            let linedefs_lump = &directory[lump_idx];
-            debug_assert!(linedefs_lump.name == "LINEDEFS");
-            let mut linedefs: Vec<Linedef> = Vec::with_capacity(linedefs_lump.size / 14); // 14 bytes/each
-            for linedef_idx in 0..linedefs.capacity() {
-                let linedef_offset = linedefs_lump.file_pos + linedef_idx * 14;
-                linedefs.push(Linedef {
-                    start_vert: WadFile::get_i16(&bytes[linedef_offset..linedef_offset + 2])
-                        as usize,
-                    end_vert: WadFile::get_i16(&bytes[linedef_offset + 2..linedef_offset + 4])
-                        as usize,
-                    flags: WadFile::get_i16(&bytes[linedef_offset + 4..linedef_offset + 6]),
-                    special_type: WadFile::get_i16(&bytes[linedef_offset + 6..linedef_offset + 8]),
-                    sector_tag: WadFile::get_i16(&bytes[linedef_offset + 8..linedef_offset + 10])
-                        as usize,
-                    right_sidedef: WadFile::get_i16(
-                        &bytes[linedef_offset + 10..linedef_offset + 12],
-                    ) as usize,
-                    left_sidedef: WadFile::get_i16(&bytes[linedef_offset + 12..linedef_offset + 14])
-                        as usize,
-                })
+        lump_idx += 1;
+        let sidedefs_lump = &directory[lump_idx];
+        debug_assert!(sidedefs_lump.name == "SIDEDEFS");
+        let sidedef_count = sidedefs_lump.size / 30; // 30 bytes/each
+        let mut sidedefs: Vec<Sidedef> = Vec::with_capacity(sidedef_count);
+        for sidedef_idx in 0..sidedef_count {
+            let sidedef_offset = sidedefs_lump.file_pos + sidedef_idx * 30;
+            sidedefs.push(Sidedef {
+                x_off: WadFile::get_i16(&bytes[sidedef_offset..sidedef_offset + 2]),
+                y_off: WadFile::get_i16(&bytes[sidedef_offset + 2..sidedef_offset + 4]),
+                upper_tex: WadFile::get_8char_string(
+                    &bytes[sidedef_offset + 4..sidedef_offset + 12],
+                ),
+                lower_tex: WadFile::get_8char_string(
+                    &bytes[sidedef_offset + 12..sidedef_offset + 20],
+                ),
+                middle_tex: WadFile::get_8char_string(
+                    &bytes[sidedef_offset + 20..sidedef_offset + 28],
+                ),
+                sector: WadFile::get_i16(&bytes[sidedef_offset + 28..sidedef_offset + 30]) as usize,
+            })
+        }
+
+        lump_idx += 1;
+        let vertexes_lump = &directory[lump_idx];
+        debug_assert!(vertexes_lump.name == "VERTEXES");
+        let vertex_count = vertexes_lump.size / 4; // 4 bytes/each
+        let mut vertexes: Vec<Vertex> = Vec::with_capacity(vertex_count);
+        for vertex_idx in 0..vertex_count {
+            let vertex_offset = vertexes_lump.file_pos + vertex_idx * 4;
+            vertexes.push(Vertex {
+                x: WadFile::get_i16(&bytes[vertex_offset..vertex_offset + 2]),
+                y: WadFile::get_i16(&bytes[vertex_offset + 2..vertex_offset + 4]),
+            })
+        }
+
+        lump_idx += 1;
+        let seg_lump = &directory[lump_idx];
+        debug_assert!(seg_lump.name == "SEGS");
+        let seg_count = seg_lump.size / 12; // 12 bytes/each
+        let mut segs: Vec<Segment> = Vec::with_capacity(seg_count);
+        for seg_idx in 0..seg_count {
+            let seg_offset = seg_lump.file_pos + seg_idx * 12;
+            segs.push(Segment {
+                start_vert: WadFile::get_i16(&bytes[seg_offset..seg_offset + 2]) as usize,
+                end_vert: WadFile::get_i16(&bytes[seg_offset + 2..seg_offset + 4]) as usize,
+                angle: WadFile::get_angle(&bytes[seg_offset + 4..seg_offset + 6]),
+                linedef: WadFile::get_i16(&bytes[seg_offset + 6..seg_offset + 8]) as usize,
+                // The lump's flag is 1 when the seg runs against its linedef
+                dir_like_linedef: WadFile::get_i16(&bytes[seg_offset + 8..seg_offset + 10]) == 0,
+                offset: WadFile::get_i16(&bytes[seg_offset + 10..seg_offset + 12]),
+            })
+        }
+
+        lump_idx += 1;
+        let subsector_lump = &directory[lump_idx];
+        debug_assert!(subsector_lump.name == "SSECTORS");
+        let subsector_count = subsector_lump.size / 4; // 4 bytes/each
+        let mut subsectors: Vec<Box<SubSector>> = Vec::with_capacity(subsector_count);
+        for subsector_idx in 0..subsector_count {
+            let subsector_offset = subsector_lump.file_pos + subsector_idx * 4;
+            subsectors.push(Box::new(SubSector {
+                segment_count: WadFile::get_i16(&bytes[subsector_offset..subsector_offset + 2])
+                    as usize,
+                first_segment: WadFile::get_i16(&bytes[subsector_offset + 2..subsector_offset + 4])
+                    as usize,
+            }))
+        }
+
+        lump_idx += 1;
+        let node_lump = &directory[lump_idx];
+        debug_assert!(node_lump.name == "NODES");
+        let node_count = node_lump.size / 28; // 28 bytes/each
+        let mut map_nodes: Vec<MapNode> = Vec::with_capacity(node_count);
+        for node_idx in 0..node_count {
+            let node_offset = node_lump.file_pos + node_idx * 28;
+            map_nodes.push(MapNode {
+                id: node_idx,
+                partition_x: WadFile::get_i16(&bytes[node_offset..node_offset + 2]),
+                partition_y: WadFile::get_i16(&bytes[node_offset + 2..node_offset + 4]),
+                delta_x: WadFile::get_i16(&bytes[node_offset + 4..node_offset + 6]),
+                delta_y: WadFile::get_i16(&bytes[node_offset + 6..node_offset + 8]),
+                right_bbox: WadFile::get_bbox(&bytes[node_offset + 8..node_offset + 16]),
+                left_bbox: WadFile::get_bbox(&bytes[node_offset + 16..node_offset + 24]),
+                right_child: WadFile::get_child(&bytes[node_offset + 24..node_offset + 26]),
+                left_child: WadFile::get_child(&bytes[node_offset + 26..node_offset + 28]),
+            })
+        }
+
+        lump_idx += 1;
+        let sector_lump = &directory[lump_idx];
+        debug_assert!(sector_lump.name == "SECTORS");
+        let sector_count = sector_lump.size / 26; // 26 bytes/each
+        let mut sectors: Vec<Sector> = Vec::with_capacity(sector_count);
+        for sector_idx in 0..sector_count {
+            let sector_offset = sector_lump.file_pos + sector_idx * 26;
+            sectors.push(Sector {
+                floor_height: WadFile::get_i16(&bytes[sector_offset..sector_offset + 2]),
+                ceiling_height: WadFile::get_i16(&bytes[sector_offset + 2..sector_offset + 4]),
+                floor_tex: WadFile::get_8char_string(&bytes[sector_offset + 4..sector_offset + 12]),
+                ceiling_tex: WadFile::get_8char_string(
+                    &bytes[sector_offset + 12..sector_offset + 20],
+                ),
+                light_level: WadFile::get_i16(&bytes[sector_offset + 20..sector_offset + 22]),
+                special_type: WadFile::get_i16(&bytes[sector_offset + 22..sector_offset + 24]),
+                tag: WadFile::get_i16(&bytes[sector_offset + 24..sector_offset + 26]) as usize,
+                id: sector_idx,
+            })
+        }
+
+        lump_idx += 1;
+        let reject_lump = &directory[lump_idx];
+        debug_assert!(reject_lump.name == "REJECT");
+        // Ignored like a lord
+
+        lump_idx += 1;
+        let blockmap_lump = &directory[lump_idx];
+        debug_assert!(blockmap_lump.name == "BLOCKMAP");
+        let blockmap = WadFile::get_blockmap(&bytes, blockmap_lump);
+
+        LevelData {
+            name,
+            things,
+            linedefs,
+            sidedefs,
+            vertexes,
+            segs,
+            subsectors,
+            nodes: map_nodes,
+            sectors,
+            blockmap,
+        }
+    }
+
+    fn get_blockmap(bytes: &[u8], lump: &FileLump) -> Blockmap {
+        let header = lump.file_pos;
+        let columns = WadFile::get_i16(&bytes[header + 4..header + 6]) as usize;
+        let rows = WadFile::get_i16(&bytes[header + 6..header + 8]) as usize;
+        let blocks = (0..columns * rows)
+            .map(|block_idx| {
+                let offset_idx = header + 8 + block_idx * 2;
+                // Offsets count 2-byte words from the start of the lump
+                let mut list_idx = header
+                    + 2 * u16::from_le_bytes([bytes[offset_idx], bytes[offset_idx + 1]]) as usize;
+                // Every list starts with a 0 and ends with 0xFFFF
+                list_idx += 2;
+                let mut linedefs = Vec::new();
+                loop {
+                    let linedef = u16::from_le_bytes([bytes[list_idx], bytes[list_idx + 1]]);
+                    if linedef == 0xFFFF {
+                        break;
+                    }
+                    linedefs.push(linedef as usize);
+                    list_idx += 2;
+                }
+                linedefs
+            })
+            .collect();
+        Blockmap {
+            origin_x: WadFile::get_i16(&bytes[header..header + 2]),
+            origin_y: WadFile::get_i16(&bytes[header + 2..header + 4]),
+            columns,
+            rows,
+            blocks,
+        }
+    }
+
+    fn get_patch_names(bytes: &[u8], lump: &FileLump) -> Vec<String> {
+        let count = WadFile::get_i32(&bytes[lump.file_pos..lump.file_pos + 4]) as usize;
+        (0..count)
+            .map(|idx| {
+                let name_offset = lump.file_pos + 4 + idx * 8;
+                WadFile::get_8char_string(&bytes[name_offset..name_offset + 8])
+            })
+            .collect()
+    }
+
+    fn get_texture_defs(bytes: &[u8], lump: &FileLump) -> Vec<TextureDef> {
+        let count = WadFile::get_i32(&bytes[lump.file_pos..lump.file_pos + 4]) as usize;
+        (0..count)
+            .map(|idx| {
+                let offset_idx = lump.file_pos + 4 + idx * 4;
+                let tex_offset =
+                    lump.file_pos + WadFile::get_i32(&bytes[offset_idx..offset_idx + 4]) as usize;
+                let patch_count =
+                    WadFile::get_i16(&bytes[tex_offset + 20..tex_offset + 22]) as usize;
+                let patches = (0..patch_count)
+                    .map(|patch_idx| {
+                        let patch_offset = tex_offset + 22 + patch_idx * 10; // 10 bytes/each
+                        TexturePatch {
+                            origin_x: WadFile::get_i16(&bytes[patch_offset..patch_offset + 2]),
+                            origin_y: WadFile::get_i16(&bytes[patch_offset + 2..patch_offset + 4]),
+                            patch: WadFile::get_i16(&bytes[patch_offset + 4..patch_offset + 6])
+                                as usize,
+                        }
+                    })
+                    .collect();
+                TextureDef {
+                    name: WadFile::get_8char_string(&bytes[tex_offset..tex_offset + 8]),
+                    width: WadFile::get_i16(&bytes[tex_offset + 12..tex_offset + 14]),
+                    height: WadFile::get_i16(&bytes[tex_offset + 14..tex_offset + 16]),
+                    patches,
+                }
+            })
+            .collect()
+    }
+
+    fn get_flats(bytes: &[u8], directory: &[FileLump]) -> Vec<Flat> {
+        let mut flats = Vec::new();
+        let mut in_flats = false;
+        for lump in directory.iter() {
+            match lump.name.as_str() {
+                // PWADs use FF_START/FF_END to add to the IWAD's flats
+                "F_START" | "FF_START" => in_flats = true,
+                "F_END" | "FF_END" => in_flats = false,
+                // Skip the F1_START style sub-markers
+                _ if in_flats && lump.size > 0 => flats.push(Flat {
+                    name: lump.name.clone(),
+                    pixels: bytes[lump.file_pos..lump.file_pos + lump.size].to_vec(),
+                }),
+                _ => (),
             }
+        }
+        flats
+    }
 
-            lump_idx += 1;
-            let sidedefs_lump = &directory[lump_idx];
-            debug_assert!(sidedefs_lump.name == "SIDEDEFS");
-            let mut sidedefs: Vec<Sidedef> = Vec::with_capacity(sidedefs_lump.size / 30); // 30 bytes/each
-            for sidedef_idx in 0..sidedefs.capacity() {
-                let sidedef_offset = sidedefs_lump.file_pos + sidedef_idx * 30;
-                sidedefs.push(Sidedef {
-                    x_off: WadFile::get_i16(&bytes[sidedef_offset..sidedef_offset + 2]),
-                    y_off: WadFile::get_i16(&bytes[sidedef_offset + 2..sidedef_offset + 4]),
-                    upper_tex: WadFile::get_8char_string(
-                        &bytes[sidedef_offset + 4..sidedef_offset + 12],
-                    ),
-                    lower_tex: WadFile::get_8char_string(
-                        &bytes[sidedef_offset + 12..sidedef_offset + 20],
-                    ),
-                    middle_tex: WadFile::get_8char_string(
-                        &bytes[sidedef_offset + 20..sidedef_offset + 28],
-                    ),
-                    sector: WadFile::get_i16(&bytes[sidedef_offset + 28..sidedef_offset + 30])
-                        as usize,
-                })
+    // Parses a temporary copy when the level isn't loaded, leaving the cache alone
+    pub fn validate_level_textures(&self, level_index: usize) -> Vec<TextureError> {
+        let parsed;
+        let level = match self.levels[level_index].as_ref() {
+            Some(level) => level,
+            None => {
+                parsed = WadFile::parse_level(
+                    &self.bytes,
+                    &self.directory,
+                    self.level_markers[level_index],
+                );
+                &parsed
+            }
+        };
+        let mut errors = Vec::new();
+        for (linedef_idx, linedef) in level.linedefs.iter().enumerate() {
+            for sidedef_idx in [linedef.right_sidedef, linedef.left_sidedef] {
+                let sidedef = match level.sidedefs.get(sidedef_idx) {
+                    Some(sidedef) => sidedef,
+                    None => continue,
+                };
+                for texture in [&sidedef.upper_tex, &sidedef.lower_tex, &sidedef.middle_tex] {
+                    let known = self
+                        .texture_defs
+                        .iter()
+                        .any(|def| def.name.eq_ignore_ascii_case(texture));
+                    if texture != "-" && !known {
+                        errors.push(TextureError::MissingTexture {
+                            name: texture.clone(),
+                            linedef: linedef_idx,
+                        });
+                    }
+                }
+            }
+        }
+        for sector in level.sectors.iter() {
+            for flat in [&sector.floor_tex, &sector.ceiling_tex] {
+                if !self.flats.iter().any(|f| f.name.eq_ignore_ascii_case(flat)) {
+                    errors.push(TextureError::MissingFlat {
+                        name: flat.clone(),
+                        sector: sector.id,
+                    });
+                }
             }
+        }
+        errors
+    }
+
+    pub fn level_index(&self, name: &str) -> Option<usize> {
+        (0..self.level_count()).position(|idx| self.level_name(idx).eq_ignore_ascii_case(name))
+    }
+
+    // Checks the level's lumps are in the order the parser reads them, then that each parsed
+    // array holds exactly as many records as its lump has room for
+    pub fn verify_counts(&self, level_index: usize) -> Result<(), Vec<String>> {
+        const LUMPS: [(&str, usize); 8] = [
+            ("THINGS", 10),
+            ("LINEDEFS", 14),
+            ("SIDEDEFS", 30),
+            ("VERTEXES", 4),
+            ("SEGS", 12),
+            ("SSECTORS", 4),
+            ("NODES", 28),
+            ("SECTORS", 26),
+        ];
+        let marker_idx = self.level_markers[level_index];
+        let lumps: Vec<Option<&FileLump>> = (0..LUMPS.len())
+            .map(|offset| self.directory.get(marker_idx + 1 + offset))
+            .collect();
+        // Lumps are read by position, so one out of place would be parsed as the wrong kind
+        let order_errors: Vec<String> = LUMPS
+            .iter()
+            .zip(lumps.iter())
+            .filter_map(|(&(name, _), lump)| match lump {
+                None => Some(format!("{}: lump missing", name)),
+                Some(lump) if lump.name != name => {
+                    Some(format!("{}: found {} in its place", name, lump.name))
+                }
+                Some(_) => None,
+            })
+            .collect();
+        if !order_errors.is_empty() {
+            return Err(order_errors);
+        }
 
-            lump_idx += 1;
-            let vertexes_lump = &directory[lump_idx];
-            debug_assert!(vertexes_lump.name == "VERTEXES");
-            let mut vertexes: Vec<Vertex> = Vec::with_capacity(vertexes_lump.size / 4); // 4 bytes/each
-            for vertex_idx in 0..vertexes.capacity() {
-                let vertex_offset = vertexes_lump.file_pos + vertex_idx * 4;
-                vertexes.push(Vertex {
-                    x: WadFile::get_i16(&bytes[vertex_offset..vertex_offset + 2]),
-                    y: WadFile::get_i16(&bytes[vertex_offset + 2..vertex_offset + 4]),
-                })
+        let parsed;
+        let level = match self.levels[level_index].as_ref() {
+            Some(level) => level,
+            None => {
+                parsed = WadFile::parse_level(&self.bytes, &self.directory, marker_idx);
+                &parsed
             }
+        };
+        let counts = [
+            level.things.len(),
+            level.linedefs.len(),
+            level.sidedefs.len(),
+            level.vertexes.len(),
+            level.segs.len(),
+            level.subsectors.len(),
+            level.nodes.len(),
+            level.sectors.len(),
+        ];
+        let errors: Vec<String> = LUMPS
+            .iter()
+            .zip(lumps.iter().flatten())
+            .zip(counts)
+            .filter(|((&(_, record_size), lump), count)| lump.size / record_size != *count)
+            .map(|((&(name, record_size), lump), count)| {
+                format!(
+                    "{}: parsed {} records, lump holds {}",
+                    name,
+                    count,
+                    lump.size / record_size
+                )
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 
-            lump_idx += 1;
-            let seg_lump = &directory[lump_idx];
-            debug_assert!(seg_lump.name == "SEGS");
-            let mut segs: Vec<Segment> = Vec::with_capacity(seg_lump.size / 12); // 12 bytes/each
-            for seg_idx in 0..segs.capacity() {
-                let seg_offset = seg_lump.file_pos + seg_idx * 12;
-                segs.push(Segment {
-                    start_vert: WadFile::get_i16(&bytes[seg_offset..seg_offset + 2]) as usize,
-                    end_vert: WadFile::get_i16(&bytes[seg_offset + 2..seg_offset + 4]) as usize,
-                    angle: WadFile::get_angle(&bytes[seg_offset + 4..seg_offset + 6]),
-                    linedef: WadFile::get_i16(&bytes[seg_offset + 6..seg_offset + 8]) as usize,
-                    dir_like_linedef: WadFile::get_i16(&bytes[seg_offset + 8..seg_offset + 10])
-                        != 0,
-                    offset: WadFile::get_i16(&bytes[seg_offset + 10..seg_offset + 12]),
-                })
+    // Indexes of the frames of the animation a flat belongs to, or just the flat itself.
+    // Like the game, a sequence is every flat between its first and last frame.
+    pub fn flat_animation(&self, flat_index: usize) -> Vec<usize> {
+        let position = |name: &str| self.flats.iter().position(|flat| flat.name == name);
+        for (first, last) in ANIMATED_FLATS {
+            if let (Some(start), Some(end)) = (position(first), position(last)) {
+                if (start..=end).contains(&flat_index) {
+                    return (start..=end).collect();
+                }
             }
+        }
+        vec![flat_index]
+    }
+
+    pub fn palette(&self) -> Option<Palette> {
+        Palette::from_playpal(self.lump_bytes("PLAYPAL")?)
+    }
+
+    pub fn colormap(&self) -> Option<Colormap> {
+        Colormap::from_lump("COLORMAP", self.lump_bytes("COLORMAP")?)
+    }
 
-            lump_idx += 1;
-            let subsector_lump = &directory[lump_idx];
-            debug_assert!(subsector_lump.name == "SSECTORS");
-            let mut subsectors: Vec<Box<SubSector>> = Vec::with_capacity(subsector_lump.size / 4); // 4 bytes/each
-            for subsector_idx in 0..subsectors.capacity() {
-                let subsector_offset = subsector_lump.file_pos + subsector_idx * 4;
-                subsectors.push(Box::new(SubSector {
-                    segment_count: WadFile::get_i16(&bytes[subsector_offset..subsector_offset + 2])
-                        as usize,
-                    first_segment: WadFile::get_i16(
-                        &bytes[subsector_offset + 2..subsector_offset + 4],
-                    ) as usize,
-                }))
+    // Boom's extra colormaps, between C_START and C_END
+    pub fn colormaps(&self) -> Vec<Colormap> {
+        let mut colormaps = Vec::new();
+        let mut in_colormaps = false;
+        for lump in self.directory.iter() {
+            match lump.name.as_str() {
+                "C_START" => in_colormaps = true,
+                "C_END" => in_colormaps = false,
+                _ if in_colormaps && !self.bytes.is_empty() => {
+                    let bytes = &self.bytes[lump.file_pos..lump.file_pos + lump.size];
+                    colormaps.extend(Colormap::from_lump(&lump.name, bytes));
+                }
+                _ => (),
             }
+        }
+        colormaps
+    }
 
-            lump_idx += 1;
-            let node_lump = &directory[lump_idx];
-            debug_assert!(node_lump.name == "NODES");
-            let mut map_nodes: Vec<MapNode> = Vec::with_capacity(node_lump.size / 28); // 28 bytes/each
-            for node_idx in 0..map_nodes.capacity() {
-                let node_offset = node_lump.file_pos + node_idx * 28;
-                map_nodes.push(MapNode {
-                    id: node_idx,
-                    partition_x: WadFile::get_i16(&bytes[node_offset..node_offset + 2]),
-                    partition_y: WadFile::get_i16(&bytes[node_offset + 2..node_offset + 4]),
-                    delta_x: WadFile::get_i16(&bytes[node_offset + 4..node_offset + 6]),
-                    delta_y: WadFile::get_i16(&bytes[node_offset + 6..node_offset + 8]),
-                    right_bbox: WadFile::get_bbox(&bytes[node_offset + 8..node_offset + 16]),
-                    left_bbox: WadFile::get_bbox(&bytes[node_offset + 16..node_offset + 24]),
-                    right_child: WadFile::get_child(&bytes[node_offset + 24..node_offset + 26]),
-                    left_child: WadFile::get_child(&bytes[node_offset + 26..node_offset + 28]),
-                })
+    // Builds a wall texture by drawing its patches onto a blank canvas. Missing patches
+    // just leave holes.
+    pub fn texture_picture(&self, texture_index: usize) -> Option<Picture> {
+        let def = self.texture_defs.get(texture_index)?;
+        let mut picture = Picture::new(def.width as usize, def.height as usize);
+        for texture_patch in def.patches.iter() {
+            let patch = self
+                .patch_names
+                .get(texture_patch.patch)
+                .and_then(|name| self.lump_bytes(&name.to_ascii_uppercase()))
+                .and_then(picture::decode_patch);
+            if let Some(patch) = patch {
+                picture.blit(
+                    &patch,
+                    i32::from(texture_patch.origin_x),
+                    i32::from(texture_patch.origin_y),
+                );
             }
+        }
+        Some(picture)
+    }
+
+    // Frees the file contents. Every level is parsed first so level() keeps working,
+    // but raw lump access is gone for good: lump_data, lump_bytes and what's read through
+    // them (palette, colormap, dehacked) give None from then on, and colormaps nothing.
+    pub fn drop_raw_bytes(&mut self) {
+        for index in 0..self.level_count() {
+            self.level(index);
+        }
+        self.bytes = Vec::new();
+    }
+
+    // A lump's bytes by its directory index
+    pub fn lump_data(&self, index: usize) -> Option<&[u8]> {
+        let lump = self.directory.get(index)?;
+        self.bytes.get(lump.file_pos..lump.file_pos + lump.size)
+    }
 
-            lump_idx += 1;
-            let sector_lump = &directory[lump_idx];
-            debug_assert!(sector_lump.name == "SECTORS");
-            let mut sectors: Vec<Sector> = Vec::with_capacity(sector_lump.size / 26); // 26 bytes/each
-            for sector_idx in 0..sectors.capacity() {
-                let sector_offset = sector_lump.file_pos + sector_idx * 26;
-                sectors.push(Sector {
-                    floor_height: WadFile::get_i16(&bytes[sector_offset..sector_offset + 2]),
-                    ceiling_height: WadFile::get_i16(&bytes[sector_offset + 2..sector_offset + 4]),
-                    floor_tex: WadFile::get_8char_string(
-                        &bytes[sector_offset + 4..sector_offset + 12],
-                    ),
-                    ceiling_tex: WadFile::get_8char_string(
-                        &bytes[sector_offset + 12..sector_offset + 20],
-                    ),
-                    light_level: WadFile::get_i16(&bytes[sector_offset + 20..sector_offset + 22]),
-                    special_type: WadFile::get_i16(&bytes[sector_offset + 22..sector_offset + 24]),
-                    tag: WadFile::get_i16(&bytes[sector_offset + 24..sector_offset + 26]) as usize,
-                })
+    pub fn lump_bytes(&self, name: &str) -> Option<&[u8]> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        // Later lumps override earlier ones of the same name
+        let lump = self.directory.iter().rev().find(|lump| lump.name == name)?;
+        Some(&self.bytes[lump.file_pos..lump.file_pos + lump.size])
+    }
+
+    // Digitised sound effects, DSPISTOL and so on
+    pub fn sound_lumps(&self) -> Vec<&str> {
+        self.lumps_with_prefix("DS")
+    }
+
+    // MUS format music, D_E1M1 and so on
+    pub fn music_lumps(&self) -> Vec<&str> {
+        self.lumps_with_prefix("D_")
+    }
+
+    fn lumps_with_prefix(&self, prefix: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for lump in self
+            .directory
+            .iter()
+            .filter(|lump| lump.name.starts_with(prefix))
+        {
+            if !names.contains(&lump.name.as_str()) {
+                names.push(&lump.name);
             }
+        }
+        names
+    }
 
-            lump_idx += 1;
-            let reject_lump = &directory[lump_idx];
-            debug_assert!(reject_lump.name == "REJECT");
-            // Ignored like a lord
+    pub fn dehacked(&self) -> Option<DehackedPatch> {
+        let bytes = self.lump_bytes("DEHACKED")?;
+        Some(DehackedPatch::parse(&String::from_utf8_lossy(bytes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Level;
+    use crate::test_support::{build_wad, level_data, level_lumps, MiniLevel};
+
+    const DOOM1: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/doom1.wad");
 
-            lump_idx += 1;
-            let blockmap_lump = &directory[lump_idx];
-            debug_assert!(blockmap_lump.name == "BLOCKMAP");
-            // Ignored like a lord - but we'll need it at SOME point.
+    fn doom1() -> WadFile {
+        WadFile::load_from(DOOM1)
+    }
 
-            levels.push(LevelData {
-                things,
-                linedefs,
-                sidedefs,
-                vertexes,
-                segs,
-                subsectors,
-                nodes: map_nodes,
-                sectors,
-            });
+    fn facing(degrees: f32) -> Thing {
+        Thing {
+            x: 0,
+            y: 0,
+            angle_facing: degrees.to_radians(),
+            thing_type: 1,
+            flags: 7,
         }
+    }
 
-        WadFile {
-            bytes,
-            header,
-            directory,
-            levels,
+    #[test]
+    fn facing_octant_boundaries() {
+        for octant in 0..8u8 {
+            let boundary = f32::from(octant) * 45. + 22.5;
+            assert_eq!(facing(boundary - 0.1).facing_octant(), octant);
+            assert_eq!(facing(boundary + 0.1).facing_octant(), (octant + 1) % 8);
+            assert_eq!(facing(f32::from(octant) * 45.).facing_octant(), octant);
         }
+        assert_eq!(facing(-45.).facing_octant(), 7);
+        assert_eq!(facing(360.).facing_octant(), 0);
+    }
+
+    #[test]
+    fn level_with_unknown_texture_gives_one_error() {
+        let mut data = level_data(&MiniLevel::one_room("MAP01", 128));
+        data.sidedefs[2].middle_tex = "NOSUCHTX".to_string();
+        let mut wad = doom1();
+        wad.levels.push(Some(data));
+        assert_eq!(
+            wad.validate_level_textures(wad.levels.len() - 1),
+            [TextureError::MissingTexture {
+                name: "NOSUCHTX".to_string(),
+                linedef: 2,
+            }]
+        );
+        // Checking a level that isn't loaded yet doesn't keep the copy it parsed
+        assert!(wad.validate_level_textures(0).is_empty());
+        assert!(wad.levels[0].is_none());
+    }
+
+    #[test]
+    fn levels_are_parsed_on_first_access() {
+        let mut wad = doom1();
+        assert_eq!(wad.parse_count(), 0);
+        assert_eq!(wad.level(3).name, "E1M4");
+        assert_eq!(wad.parse_count(), 1);
+        wad.level(3);
+        assert_eq!(wad.parse_count(), 1);
+
+        let eager = WadFile::load(DOOM1, true);
+        assert_eq!(eager.parse_count(), eager.level_count());
+    }
+
+    #[test]
+    fn dehacked_lump_is_parsed_from_a_pwad() {
+        let patch = b"Thing 1 (Player)\nInitial health = 200\n".to_vec();
+        let wad =
+            WadFile::from_bytes(build_wad("PWAD", &[("DEHACKED".to_string(), patch)])).unwrap();
+        let patch = wad.dehacked().unwrap();
+        assert_eq!(patch.things[0].field("Initial health"), Some("200"));
+        assert!(doom1().dehacked().is_none());
+    }
+
+    #[test]
+    fn game_variant_from_header_and_maps() {
+        let variant = doom1().game_variant();
+        assert_eq!(variant, GameVariant::Shareware);
+        assert_eq!(variant.episodes(), 1);
+
+        let wad_with = |id: &str, map: &str| {
+            let lumps = level_lumps(&level_data(&MiniLevel::one_room(map, 128)));
+            WadFile::from_bytes(build_wad(id, &lumps)).unwrap()
+        };
+        assert_eq!(
+            wad_with("IWAD", "E3M1").game_variant(),
+            GameVariant::Registered
+        );
+        assert_eq!(
+            wad_with("IWAD", "E4M1").game_variant(),
+            GameVariant::Ultimate
+        );
+        assert_eq!(wad_with("IWAD", "MAP01").game_variant(), GameVariant::Doom2);
+        assert_eq!(wad_with("PWAD", "E1M1").game_variant(), GameVariant::Pwad);
+    }
+
+    #[test]
+    fn memory_report_matches_manual_sum() {
+        let bytes = build_wad(
+            "PWAD",
+            &level_lumps(&level_data(&MiniLevel::one_room("MAP01", 128))),
+        );
+        let mut wad = WadFile::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(wad.memory_report().levels, 0);
+        wad.level(0);
+        let report = wad.memory_report();
+
+        // Four walls, each with a sidedef of "-", "-" and STARTAN3, one sector of FLOOR4_8
+        // and CEIL3_5, and a 3x3 blockmap listing all four walls in every cell
+        let names = [
+            "MAP01", "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES",
+            "SECTORS", "REJECT", "BLOCKMAP",
+        ];
+        let directory: usize = names
+            .iter()
+            .map(|name| size_of::<FileLump>() + name.len())
+            .sum();
+        let level = size_of::<LevelData>()
+            + size_of::<Thing>()
+            + 4 * (size_of::<Linedef>() + size_of::<Sidedef>() + 10)
+            + 4 * (size_of::<Vertex>() + size_of::<Segment>())
+            + size_of::<Box<SubSector>>()
+            + size_of::<SubSector>()
+            + size_of::<MapNode>()
+            + size_of::<Sector>()
+            + 15
+            + 9 * 4 * size_of::<usize>();
+        let manual = bytes.len() + directory + level;
+        let total = report.total();
+        assert!(
+            total.abs_diff(manual) * 20 < manual,
+            "{} vs {}",
+            total,
+            manual
+        );
+        assert_eq!(report.raw_bytes, bytes.len());
+        assert_eq!((report.textures, report.flats), (0, 0));
+    }
+
+    #[test]
+    fn levels_outlive_the_raw_bytes() {
+        let mut wad = doom1();
+        let e1m1 = Level::new(wad.level(0));
+        let sector_count = e1m1.sectors.len();
+        let playpal = wad.directory.iter().position(|lump| lump.name == "PLAYPAL");
+        assert!(wad.lump_data(playpal.unwrap()).is_some());
+        assert!(wad.palette().is_some());
+
+        wad.drop_raw_bytes();
+        assert_eq!(wad.parse_count(), wad.level_count());
+        assert_eq!(Level::new(wad.level(0)).sectors.len(), sector_count);
+        assert_eq!(wad.level(8).name, "E1M9");
+        assert_eq!(wad.parse_count(), wad.level_count());
+        assert!(wad.lump_data(playpal.unwrap()).is_none());
+        assert!(wad.lump_bytes("PLAYPAL").is_none());
+        assert!(wad.palette().is_none());
+        assert!(wad.colormap().is_none());
+    }
+
+    #[test]
+    fn colormaps_are_gone_after_dropping_bytes() {
+        let lumps = [
+            ("C_START".to_string(), Vec::new()),
+            ("WATERMAP".to_string(), vec![0; 34 * 256]),
+            ("C_END".to_string(), Vec::new()),
+        ];
+        let mut wad = WadFile::from_bytes(build_wad("PWAD", &lumps)).unwrap();
+        assert_eq!(wad.colormaps().len(), 1);
+        wad.drop_raw_bytes();
+        assert!(wad.colormaps().is_empty());
+        assert!(wad.lump_data(1).is_none());
+    }
+
+    #[test]
+    fn progress_is_reported_once_per_level() {
+        let mut calls = Vec::new();
+        let mut wad = doom1();
+        wad.parse_levels(|done, total| calls.push((done, total)));
+        let expected: Vec<(usize, usize)> = (1..=9).map(|done| (done, 9)).collect();
+        assert_eq!(calls, expected);
+        assert_eq!(wad.parse_count(), 9);
+    }
+
+    #[test]
+    fn one_timing_per_parsed_level() {
+        let mut wad = doom1();
+        wad.level(0);
+        assert!(wad.parse_timings().is_empty());
+
+        wad.enable_parse_timings();
+        for idx in [0, 2, 5, 2] {
+            wad.level(idx);
+        }
+        let names: Vec<&str> = wad
+            .parse_timings()
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["E1M3", "E1M6"]);
+    }
+
+    #[test]
+    fn verify_counts_checks_sizes_and_lump_order() {
+        let lumps = level_lumps(&level_data(&MiniLevel::row_of_rooms("MAP01", 2, 128)));
+        let mut wad = WadFile::from_bytes(build_wad("PWAD", &lumps)).unwrap();
+        assert_eq!(wad.verify_counts(0), Ok(()));
+        assert!(doom1().verify_counts(0).is_ok());
+
+        // A parsed level that has drifted from its lumps
+        wad.level(0);
+        wad.levels[0].as_mut().unwrap().vertexes.pop();
+        assert_eq!(
+            wad.verify_counts(0),
+            Err(vec!["VERTEXES: parsed 5 records, lump holds 6".to_string()])
+        );
+
+        let mut swapped = lumps.clone();
+        swapped.swap(5, 6);
+        let wad = WadFile::from_bytes(build_wad("PWAD", &swapped)).unwrap();
+        assert_eq!(
+            wad.verify_counts(0),
+            Err(vec![
+                "SEGS: found SSECTORS in its place".to_string(),
+                "SSECTORS: found SEGS in its place".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn partial_trailing_records_are_not_parsed() {
+        let mut lumps = level_lumps(&level_data(&MiniLevel::row_of_rooms("MAP01", 2, 128)));
+        // Three stray bytes after the six vertexes and eleven after the two sectors
+        lumps[4].1.extend([1, 2, 3]);
+        lumps[8].1.extend([0xff; 11]);
+        let mut wad = WadFile::from_bytes(build_wad("PWAD", &lumps)).unwrap();
+        let level = wad.level(0);
+        assert_eq!(level.vertexes.len(), 6);
+        assert_eq!(level.sectors.len(), 2);
+        assert_eq!(wad.verify_counts(0), Ok(()));
+    }
+
+    #[test]
+    fn doom1_sound_and_music_lumps() {
+        let wad = doom1();
+        let sounds = wad.sound_lumps();
+        let music = wad.music_lumps();
+        assert!(sounds.contains(&"DSPISTOL") && sounds.contains(&"DSPOSIT1"));
+        assert!(music.contains(&"D_E1M1") && music.contains(&"D_INTER"));
+        assert!(sounds.iter().all(|name| name.starts_with("DS")));
+        // PC speaker versions start DP and aren't digitised sounds
+        assert!(!sounds.iter().any(|name| name.starts_with("DP")));
+    }
+
+    #[test]
+    fn later_pwads_in_a_stack_win() {
+        let dir = std::env::temp_dir().join(format!("rune-stack-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |file: &str, id: &str, rooms: usize| {
+            let data = level_data(&MiniLevel::row_of_rooms("MAP01", rooms, 128));
+            let path = dir.join(file);
+            std::fs::write(&path, build_wad(id, &level_lumps(&data))).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let paths = [
+            write("base.wad", "IWAD", 1),
+            write("first.wad", "PWAD", 2),
+            write("second.wad", "PWAD", 3),
+        ];
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        let mut wad = WadFile::load_stack(&paths).unwrap();
+        let map01 = wad.level_index("MAP01").unwrap();
+        assert_eq!(wad.level(map01).sectors.len(), 3);
+        assert_eq!(wad.header.id, "IWAD");
+
+        let mut reversed = WadFile::load_stack(&[paths[0], paths[2], paths[1]]).unwrap();
+        let map01 = reversed.level_index("MAP01").unwrap();
+        assert_eq!(reversed.level(map01).sectors.len(), 2);
+
+        let missing = dir.join("missing.wad");
+        assert!(matches!(
+            WadFile::load_stack(&[paths[0], missing.to_str().unwrap()]),
+            Err(WadError::Io { .. })
+        ));
+        assert!(matches!(WadFile::load_stack(&[]), Err(WadError::NoFiles)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn negative_directory_values_are_truncation() {
+        let lumps = level_lumps(&level_data(&MiniLevel::one_room("MAP01", 128)));
+        let good = build_wad("PWAD", &lumps);
+        let dir_offset = i32::from_le_bytes(good[8..12].try_into().unwrap()) as usize;
+        for at in [4, 8, dir_offset, dir_offset + 4] {
+            let mut bad = good.clone();
+            bad[at..at + 4].copy_from_slice(&(-16_i32).to_le_bytes());
+            assert!(matches!(WadFile::from_bytes(bad), Err(WadError::Truncated)));
+        }
+        // Big enough to overflow when multiplied out
+        let mut bad = good.clone();
+        bad[4..8].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(matches!(WadFile::from_bytes(bad), Err(WadError::Truncated)));
+    }
+
+    #[test]
+    fn colormap_lumps_decode_to_34_maps() {
+        let colormap = doom1().colormap().unwrap();
+        assert_eq!(colormap.maps.len(), 34);
+        // Row 0 is full brightness, which leaves a colour like this green as it is
+        assert_eq!(colormap.maps[0][112], 112);
+
+        let fog: Vec<u8> = (0..34 * 256).map(|idx| (idx / 256) as u8).collect();
+        let lumps = [
+            ("C_START".to_string(), Vec::new()),
+            ("FOGMAP".to_string(), fog),
+            ("C_END".to_string(), Vec::new()),
+            ("NOTAMAP".to_string(), vec![0; 256]),
+        ];
+        let colormaps = WadFile::from_bytes(build_wad("PWAD", &lumps))
+            .unwrap()
+            .colormaps();
+        assert_eq!(colormaps.len(), 1);
+        assert_eq!(colormaps[0].name, "FOGMAP");
+        assert_eq!(colormaps[0].maps.len(), 34);
+        assert!(colormaps[0].maps[33].iter().all(|&entry| entry == 33));
+    }
+
+    #[test]
+    fn episode_list_comes_from_the_level_markers() {
+        assert_eq!(doom1().episode_list(), [1]);
+        // A PWAD isn't limited by the game, and lists each episode once
+        let lumps: Vec<(String, Vec<u8>)> = ["E3M1", "E1M2", "E3M4"]
+            .iter()
+            .flat_map(|name| level_lumps(&level_data(&MiniLevel::one_room(name, 128))))
+            .collect();
+        let pwad = WadFile::from_bytes(build_wad("PWAD", &lumps)).unwrap();
+        assert_eq!(pwad.episode_list(), [1, 3]);
     }
 }